@@ -3,10 +3,11 @@ use client::{
         codec::opus::OpusAudioCodec, cpal::CpalAudioHandler, cpal_device::CpalDeviceHandler,
         DeviceHandler, DeviceType,
     },
-    client::{tokio::TokioClient, Client},
+    client::{tokio::TokioClient, Client, ConnectOptions},
 };
-use tauri::{Manager, Runtime, State, Window};
-use tokio::sync::Mutex;
+use common::packet::Callout;
+use tauri::{AppHandle, Emitter, Manager, Runtime, State, Window};
+use tokio::sync::{broadcast::error::RecvError, Mutex};
 
 #[derive(Debug, serde::Deserialize)]
 enum WindowState {
@@ -74,6 +75,58 @@ async fn get_devices(state: State<'_, Mutex<AppState>>) -> Result<String, String
     Ok(devices)
 }
 
+#[tauri::command]
+async fn set_muted(muted: bool, state: State<'_, Mutex<AppState>>) -> Result<(), String> {
+    let state = state.inner().lock().await;
+    state.client.set_muted(muted).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_deafened(deafened: bool, state: State<'_, Mutex<AppState>>) -> Result<(), String> {
+    let state = state.inner().lock().await;
+    state.client.set_deafened(deafened).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_roster(state: State<'_, Mutex<AppState>>) -> Result<String, String> {
+    let state = state.inner().lock().await;
+
+    let roster = state.client.roster().await;
+    let roster = serde_json::to_string(&roster).unwrap();
+
+    Ok(roster)
+}
+
+#[tauri::command]
+async fn send_chat(text: String, state: State<'_, Mutex<AppState>>) -> Result<(), String> {
+    let state = state.inner().lock().await;
+    state.client.send_chat(text).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_chat_history(state: State<'_, Mutex<AppState>>) -> Result<String, String> {
+    let state = state.inner().lock().await;
+
+    let history = state.client.chat_history().await;
+    let history = serde_json::to_string(&history).unwrap();
+
+    Ok(history)
+}
+
+#[tauri::command]
+async fn send_callout(
+    callout: Callout,
+    target: Option<String>,
+    state: State<'_, Mutex<AppState>>,
+) -> Result<(), String> {
+    let state = state.inner().lock().await;
+    state
+        .client
+        .send_callout(callout, target)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn set_device(
     device_type: DeviceType,
@@ -120,6 +173,9 @@ pub fn run() {
                 }
             };
 
+            forward_speaking_events(&client, app.handle().clone());
+            forward_chat_messages(&client, app.handle().clone());
+            forward_callout_events(&client, app.handle().clone());
             app.manage(Mutex::new(AppState { client }));
 
             Ok(())
@@ -127,7 +183,13 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             get_devices,
+            get_roster,
+            send_chat,
+            get_chat_history,
+            send_callout,
             set_device,
+            set_muted,
+            set_deafened,
             is_running,
             start,
             stop,
@@ -137,10 +199,71 @@ pub fn run() {
         .expect("error while running tauri application");
 }
 
+/// Emits a `speaking` event to the frontend whenever someone starts or stops speaking.
+fn forward_speaking_events(
+    client: &TokioClient<CpalAudioHandler<OpusAudioCodec>, CpalDeviceHandler>,
+    app: AppHandle,
+) {
+    let mut events = client.speaking_events();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let _ = app.emit("speaking", event);
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Emits a `chat` event to the frontend for every message relayed to the room.
+fn forward_chat_messages(
+    client: &TokioClient<CpalAudioHandler<OpusAudioCodec>, CpalDeviceHandler>,
+    app: AppHandle,
+) {
+    let mut messages = client.chat_messages();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match messages.recv().await {
+                Ok(message) => {
+                    let _ = app.emit("chat", message);
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Emits a `callout` event to the frontend for every callout relayed to the room.
+fn forward_callout_events(
+    client: &TokioClient<CpalAudioHandler<OpusAudioCodec>, CpalDeviceHandler>,
+    app: AppHandle,
+) {
+    let mut events = client.callout_events();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let _ = app.emit("callout", event);
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
 async fn setup() -> Result<TokioClient<CpalAudioHandler<OpusAudioCodec>, CpalDeviceHandler>, String>
 {
     let addr = std::borrow::Cow::Borrowed("127.0.0.1:8080");
-    let client = match TokioClient::connect(addr).await {
+    let options = ConnectOptions {
+        udp: true,
+        ..Default::default()
+    };
+    let client = match TokioClient::connect_with(addr, options).await {
         Ok(client) => client,
         Err(e) => {
             eprintln!("Failed to connect to server: {}", e);
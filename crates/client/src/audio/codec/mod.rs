@@ -10,4 +10,8 @@ pub trait AudioCodec: Send + Sync {
     fn update(&mut self, sample_rate: u32, channels: usize) -> Result<(), ClientError>;
     fn encode(&mut self, data: Vec<f32>) -> Result<Vec<u8>, ClientError>;
     fn decode(&mut self, data: Vec<u8>) -> Result<Vec<f32>, ClientError>;
+
+    /// Number of samples per channel, at the codec clock rate, covered by
+    /// `samples` interleaved input samples.
+    fn clock_samples(&self, samples: usize) -> u32;
 }
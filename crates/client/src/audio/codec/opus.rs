@@ -100,6 +100,11 @@ impl AudioCodec for OpusAudioCodec {
         decoded.truncate(len);
         Ok(decoded)
     }
+
+    fn clock_samples(&self, samples: usize) -> u32 {
+        let frames = (samples / self.channels) as u64;
+        (frames * SAMPLE_RATE as u64 / self.sample_rate as u64) as u32
+    }
 }
 
 #[cfg(test)]
@@ -145,6 +150,16 @@ mod tests {
         assert!(codec.encode(data).is_ok());
     }
 
+    #[test]
+    fn should_count_clock_samples_at_codec_rate() {
+        let mut codec = OpusAudioCodec::new().unwrap();
+        codec.update(44100, 2).unwrap();
+        assert_eq!(codec.clock_samples(441 * 2), 480);
+
+        codec.update(48000, 1).unwrap();
+        assert_eq!(codec.clock_samples(480), 480);
+    }
+
     #[test]
     fn should_fail_to_decode_audio_data() {
         let mut codec = OpusAudioCodec::new().unwrap();
@@ -1,8 +1,10 @@
-use super::{codec::AudioCodec, AudioHandler};
+use super::{codec::AudioCodec, vad::VoiceActivityDetector, AudioHandler};
 use crate::error::ClientError;
-use common::packet::{AudioPacket, Packet};
-use std::sync::Arc;
-use std::u32;
+use common::packet::{format::WireFormat, AudioPacket, Packet, SpeakingPacket};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use tokio::{
     select,
     sync::{
@@ -13,6 +15,7 @@ use tokio::{
 
 pub struct CpalAudioHandler<Codec: AudioCodec> {
     codec: Arc<Mutex<Codec>>,
+    muted: Arc<AtomicBool>,
 
     stop_tx: mpsc::Sender<()>,
     stop_rx: Arc<Mutex<mpsc::Receiver<()>>>,
@@ -29,6 +32,7 @@ impl<Codec: AudioCodec + 'static> AudioHandler for CpalAudioHandler<Codec> {
 
         Ok(CpalAudioHandler {
             codec: Arc::new(Mutex::new(Codec::new()?)),
+            muted: Arc::new(AtomicBool::new(false)),
             stop_tx,
             stop_rx: Arc::new(Mutex::new(stop_rx)),
         })
@@ -38,25 +42,55 @@ impl<Codec: AudioCodec + 'static> AudioHandler for CpalAudioHandler<Codec> {
         &self,
         mut mic_rx: mpsc::Receiver<Vec<f32>>,
         packet_sender: mpsc::Sender<Packet>,
+        format: WireFormat,
     ) -> Result<(), ClientError> {
-        let (audio_tx, mut audio_rx) = mpsc::channel::<Vec<u8>>(20);
+        let (audio_tx, mut audio_rx) = mpsc::channel::<AudioPacket>(20);
 
         let codec = self.codec.clone();
+        let speaking_sender = packet_sender.clone();
+        let muted = self.muted.clone();
         let microphone_handle = tokio::spawn(async move {
             println!("Microphone handle started");
+            let mut sequence: u16 = 0;
+            let mut timestamp: u32 = 0;
+            let mut voice = VoiceActivityDetector::default();
             while let Some(audio_samples) = mic_rx.recv().await {
                 let mut codec = codec.lock().await;
-                if let Ok(encoded_data) = codec.encode(audio_samples) {
-                    let _ = audio_tx.send(encoded_data).await;
+                let captured = codec.clock_samples(audio_samples.len());
+                if muted.load(Ordering::Relaxed) {
+                    if let Some(speaking) = voice.reset() {
+                        send_speaking(&speaking_sender, speaking, format).await;
+                    }
+                    // Time goes on while muted, receivers see a gap rather than a jump.
+                    timestamp = timestamp.wrapping_add(captured);
+                    continue;
+                }
+                if let Some(speaking) = voice.update(&audio_samples, captured) {
+                    send_speaking(&speaking_sender, speaking, format).await;
+                }
+                if let Ok(track) = codec.encode(audio_samples) {
+                    let packet = AudioPacket {
+                        sequence,
+                        timestamp,
+                        track,
+                    };
+                    let _ = audio_tx.send(packet).await;
+                    sequence = sequence.wrapping_add(1);
                 }
+                // Keep counting dropped frames so receivers see the gap in time.
+                timestamp = timestamp.wrapping_add(captured);
+            }
+
+            if voice.is_speaking() {
+                send_speaking(&speaking_sender, false, format).await;
             }
             Ok(())
         });
 
         let audio_packets_handle = tokio::spawn(async move {
             println!("Audio packets handle started");
-            while let Some(track) = audio_rx.recv().await {
-                if let Ok(packet) = Packet::new(AudioPacket { track }) {
+            while let Some(audio_packet) = audio_rx.recv().await {
+                if let Ok(packet) = Packet::new_as(audio_packet, format) {
                     let _ = packet_sender.send(packet).await;
                 }
             }
@@ -91,15 +125,26 @@ impl<Codec: AudioCodec + 'static> AudioHandler for CpalAudioHandler<Codec> {
         Ok(())
     }
 
+    fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
     fn get_codec(&self) -> Arc<Mutex<Codec>> {
         self.codec.clone()
     }
 }
 
+async fn send_speaking(packet_sender: &mpsc::Sender<Packet>, speaking: bool, format: WireFormat) {
+    if let Ok(packet) = Packet::new_as(SpeakingPacket { speaking }, format) {
+        let _ = packet_sender.send(packet).await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::audio::codec::opus::OpusAudioCodec;
+    use common::packet::{ids::PacketId, packet_type::PacketType};
     use std::time::Duration;
     use tokio::time::sleep;
 
@@ -113,11 +158,14 @@ mod tests {
         let (_mic_tx, mic_rx) = mpsc::channel(1000);
 
         let audio_handler_handle =
-            tokio::spawn(async move { audio_handler.start(mic_rx, tx).await });
+            tokio::spawn(
+                async move { audio_handler.start(mic_rx, tx, WireFormat::HANDSHAKE).await },
+            );
 
         let sender_handle = tokio::spawn(async move {
             let packet = Packet::new(AudioPacket {
                 track: vec![0; 960],
+                ..Default::default()
             })
             .unwrap();
             let encoded_packet = packet.encode();
@@ -132,13 +180,13 @@ mod tests {
 
         let receiver_handle = tokio::spawn(async move {
             let mut count = 0;
-            while let Some(_) = rx_2.recv().await {
+            while rx_2.recv().await.is_some() {
                 count += 1;
                 if count == 100 {
                     break;
                 }
             }
-            return Ok::<i32, ()>(count);
+            Ok::<i32, ()>(count)
         });
 
         select! {
@@ -153,4 +201,127 @@ mod tests {
             }
         };
     }
+
+    #[tokio::test]
+    async fn should_stamp_sequence_and_timestamp() {
+        let audio_handler = CpalAudioHandler::<OpusAudioCodec>::new().unwrap();
+        audio_handler
+            .get_codec()
+            .lock()
+            .await
+            .update(48000, 1)
+            .unwrap();
+
+        let (packet_tx, mut packet_rx) = mpsc::channel(10);
+        let (mic_tx, mic_rx) = mpsc::channel(10);
+
+        tokio::spawn(async move {
+            audio_handler
+                .start(mic_rx, packet_tx, WireFormat::MessagePack)
+                .await
+        });
+
+        for _ in 0..3 {
+            mic_tx.send(vec![0.0; 480]).await.unwrap();
+        }
+
+        for expected in 0..3u16 {
+            let packet = packet_rx.recv().await.unwrap();
+            let audio_packet =
+                AudioPacket::decode_as(&packet.data, WireFormat::MessagePack).unwrap();
+            assert_eq!(audio_packet.sequence, expected);
+            assert_eq!(audio_packet.timestamp, expected as u32 * 480);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_announce_voice_activity() {
+        let audio_handler = CpalAudioHandler::<OpusAudioCodec>::new().unwrap();
+        audio_handler
+            .get_codec()
+            .lock()
+            .await
+            .update(48000, 1)
+            .unwrap();
+
+        let (packet_tx, mut packet_rx) = mpsc::channel(10);
+        let (mic_tx, mic_rx) = mpsc::channel(10);
+
+        tokio::spawn(async move {
+            audio_handler
+                .start(mic_rx, packet_tx, WireFormat::MessagePack)
+                .await
+        });
+
+        mic_tx.send(vec![0.5; 480]).await.unwrap();
+        drop(mic_tx);
+
+        let mut speaking = Vec::new();
+        while let Some(packet) = packet_rx.recv().await {
+            if packet.packet_id == PacketId::SpeakingPacket.to_u8() {
+                let packet =
+                    SpeakingPacket::decode_as(&packet.data, WireFormat::MessagePack).unwrap();
+                speaking.push(packet.speaking);
+            }
+        }
+        assert_eq!(
+            speaking,
+            vec![true, false],
+            "expected speaking to stop along with the microphone"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_drop_microphone_input_while_muted() {
+        let audio_handler = Arc::new(CpalAudioHandler::<OpusAudioCodec>::new().unwrap());
+        audio_handler
+            .get_codec()
+            .lock()
+            .await
+            .update(48000, 1)
+            .unwrap();
+
+        let (packet_tx, mut packet_rx) = mpsc::channel(10);
+        let (mic_tx, mic_rx) = mpsc::channel(10);
+
+        let handler = audio_handler.clone();
+        tokio::spawn(async move {
+            handler
+                .start(mic_rx, packet_tx, WireFormat::MessagePack)
+                .await
+        });
+
+        let decode = |packet: Packet| match PacketId::from_u8(packet.packet_id) {
+            Some(PacketId::SpeakingPacket) => {
+                let packet = SpeakingPacket::decode_as(&packet.data, WireFormat::MessagePack);
+                Err(packet.unwrap().speaking)
+            }
+            _ => Ok(AudioPacket::decode_as(&packet.data, WireFormat::MessagePack).unwrap()),
+        };
+
+        mic_tx.send(vec![0.5; 480]).await.unwrap();
+        let mut unmuted: Vec<_> = vec![
+            decode(packet_rx.recv().await.unwrap()),
+            decode(packet_rx.recv().await.unwrap()),
+        ];
+        unmuted.sort_by_key(Result::is_ok);
+        assert_eq!(unmuted[0], Err(true));
+
+        audio_handler.set_muted(true);
+        mic_tx.send(vec![0.5; 480]).await.unwrap();
+        assert_eq!(
+            decode(packet_rx.recv().await.unwrap()),
+            Err(false),
+            "expected muting to stop speaking"
+        );
+
+        audio_handler.set_muted(false);
+        mic_tx.send(vec![0.0; 480]).await.unwrap();
+        drop(mic_tx);
+
+        let audio = decode(packet_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(audio.sequence, 1, "expected the muted frame to be dropped");
+        assert_eq!(audio.timestamp, 960, "expected time to go on while muted");
+        assert!(packet_rx.recv().await.is_none());
+    }
 }
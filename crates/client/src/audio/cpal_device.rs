@@ -47,7 +47,7 @@ impl DeviceHandler for CpalDeviceHandler {
             .input_devices
             .clone()
             .into_iter()
-            .chain(self.output_devices.clone().into_iter())
+            .chain(self.output_devices.clone())
             .collect();
 
         devices
@@ -97,12 +97,12 @@ impl DeviceHandler for CpalDeviceHandler {
         let output_device = output_config.device.as_ref().unwrap();
 
         self.input_stream = SendStream(Some(setup_input_stream(
-            &input_device,
+            input_device,
             &input_config,
             mic_tx,
         )?));
         self.output_stream = SendStream(Some(setup_output_stream(
-            &output_device,
+            output_device,
             &output_config,
             output_rx,
         )?));
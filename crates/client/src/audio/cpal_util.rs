@@ -62,7 +62,7 @@ pub fn get_host_devices(
 
 pub fn get_device_config(
     device_name: &str,
-    device_infos: &Vec<DeviceInfo>,
+    device_infos: &[DeviceInfo],
 ) -> Result<DeviceInfo, ClientError> {
     match device_infos
         .iter()
@@ -135,7 +135,7 @@ pub fn init_device_type(
     device_type: DeviceType,
     host: &cpal::Host,
 ) -> Result<Vec<DeviceInfo>, ClientError> {
-    let mut devices_info = get_host_devices(&device_type, &host)?;
+    let mut devices_info = get_host_devices(&device_type, host)?;
     let default_device = devices_info.iter().find(|device| device.default).unwrap();
 
     let device_info = get_device_config(&default_device.name, &devices_info)?;
@@ -0,0 +1,62 @@
+use common::packet::Callout;
+use std::f32::consts::TAU;
+
+/// Rate cues are rendered at, the same as decoded audio.
+const SAMPLE_RATE: u32 = 48000;
+/// Samples per output frame, 20ms like decoded audio.
+pub const FRAME_SIZE: usize = 960;
+const VOLUME: f32 = 0.25;
+/// Samples every tone fades in and out over, so that it does not click.
+const FADE: usize = 240;
+
+/// Notes of a cue as frequency in Hz and length in milliseconds, a frequency
+/// of zero being a rest. Each callout gets its own so they can be told apart
+/// without looking.
+fn notes(callout: Callout) -> &'static [(f32, u32)] {
+    match callout {
+        Callout::OnMyWay => &[(660.0, 80), (880.0, 120)],
+        Callout::Help => &[(880.0, 70), (0.0, 40), (880.0, 70), (0.0, 40), (880.0, 70)],
+        Callout::BackOff => &[(660.0, 120), (440.0, 180)],
+        Callout::Missing => &[(988.0, 90), (0.0, 40), (740.0, 160)],
+        Callout::DragonSoon => &[(392.0, 110), (523.0, 110), (659.0, 160)],
+        Callout::BaronSoon => &[(294.0, 140), (392.0, 140), (294.0, 180)],
+        Callout::HeraldSoon => &[(523.0, 100), (659.0, 100), (523.0, 140)],
+    }
+}
+
+/// Synthesizes the cue of `callout`, mono, padded with silence to whole frames.
+pub fn render(callout: Callout) -> Vec<f32> {
+    let mut samples = Vec::new();
+    for &(frequency, millis) in notes(callout) {
+        let length = (SAMPLE_RATE * millis / 1000) as usize;
+        samples.extend((0..length).map(|i| {
+            let fade = i.min(length - i).min(FADE) as f32 / FADE as f32;
+            let phase = TAU * frequency * i as f32 / SAMPLE_RATE as f32;
+            phase.sin() * fade * VOLUME
+        }));
+    }
+
+    samples.resize(samples.len().div_ceil(FRAME_SIZE) * FRAME_SIZE, 0.0);
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_render_distinct_short_cues() {
+        let cues: Vec<Vec<f32>> = Callout::ALL.into_iter().map(render).collect();
+
+        for (index, cue) in cues.iter().enumerate() {
+            assert_eq!(cue.len() % FRAME_SIZE, 0);
+            assert!(
+                cue.len() <= SAMPLE_RATE as usize / 2,
+                "expected cues under 500ms"
+            );
+            assert!(cue.iter().all(|sample| sample.abs() <= VOLUME));
+            assert!(cue.iter().any(|sample| *sample != 0.0));
+            assert!(!cues[..index].contains(cue), "expected every cue to differ");
+        }
+    }
+}
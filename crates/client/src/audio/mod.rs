@@ -3,7 +3,7 @@ use std::{fmt::Display, sync::Arc};
 use crate::error::ClientError;
 use ::cpal::{Device, SupportedStreamConfig};
 use codec::AudioCodec;
-use common::packet::Packet;
+use common::packet::{format::WireFormat, Packet};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{
     mpsc::{Receiver, Sender},
@@ -14,6 +14,8 @@ pub mod codec;
 pub mod cpal;
 pub mod cpal_device;
 pub mod cpal_util;
+pub mod cue;
+pub mod vad;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum DeviceType {
@@ -62,8 +64,11 @@ pub trait AudioHandler: Send + Sync + Sized {
         &self,
         mut mic_rx: Receiver<Vec<f32>>,
         packet_sender: Sender<Packet>,
+        format: WireFormat,
     ) -> Result<(), ClientError>;
     async fn stop(&self) -> Result<(), ClientError>;
+    /// Drops microphone input while muted, without stopping the handler.
+    fn set_muted(&self, muted: bool);
     fn get_codec(&self) -> Arc<Mutex<Self::Codec>>;
 }
 
@@ -0,0 +1,115 @@
+/// Clock rate `hangover` is counted at, the same as audio packet timestamps.
+const CLOCK_RATE: u32 = 48000;
+
+/// Decides from their loudness whether microphone frames carry speech.
+///
+/// Speaking starts on the first frame louder than `threshold` and only stops
+/// once it has been quiet for `hangover`, so the pauses between words do not
+/// make the indicator flicker.
+pub struct VoiceActivityDetector {
+    /// Root mean square level, full scale being 1.0.
+    threshold: f32,
+    /// Samples at the codec clock rate.
+    hangover: u32,
+    quiet: u32,
+    speaking: bool,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(threshold: f32, hangover: u32) -> Self {
+        Self {
+            threshold,
+            hangover,
+            quiet: 0,
+            speaking: false,
+        }
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    /// Forgets about any ongoing speech, for when the microphone goes away.
+    /// Returns `Some(false)` if it was speaking.
+    pub fn reset(&mut self) -> Option<bool> {
+        self.quiet = 0;
+        std::mem::take(&mut self.speaking).then_some(false)
+    }
+
+    /// Feeds one frame spanning `clock_samples` at the codec clock rate and
+    /// returns the new state if it changed.
+    pub fn update(&mut self, samples: &[f32], clock_samples: u32) -> Option<bool> {
+        if rms(samples) >= self.threshold {
+            self.quiet = 0;
+            if !self.speaking {
+                self.speaking = true;
+                return Some(true);
+            }
+            return None;
+        }
+
+        if self.speaking {
+            self.quiet = self.quiet.saturating_add(clock_samples);
+            if self.quiet >= self.hangover {
+                self.speaking = false;
+                return Some(false);
+            }
+        }
+        None
+    }
+}
+
+impl Default for VoiceActivityDetector {
+    /// About -34 dBFS, held for 300 ms.
+    fn default() -> Self {
+        Self::new(0.02, CLOCK_RATE / 1000 * 300)
+    }
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples.iter().map(|sample| sample * sample).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: u32 = 480;
+
+    #[test]
+    fn should_start_speaking_on_loud_frame() {
+        let mut voice = VoiceActivityDetector::default();
+        assert_eq!(voice.update(&[0.0; 480], FRAME), None);
+        assert_eq!(voice.update(&[0.5; 480], FRAME), Some(true));
+        assert_eq!(voice.update(&[0.5; 480], FRAME), None);
+        assert!(voice.is_speaking());
+    }
+
+    #[test]
+    fn should_stop_speaking_after_hangover() {
+        let mut voice = VoiceActivityDetector::new(0.1, FRAME * 3);
+        voice.update(&[0.5; 480], FRAME);
+
+        assert_eq!(voice.update(&[0.0; 480], FRAME), None);
+        assert_eq!(voice.update(&[0.0; 480], FRAME), None);
+        // Speech before the hangover ran out starts it over.
+        assert_eq!(voice.update(&[0.5; 480], FRAME), None);
+        assert_eq!(voice.update(&[0.0; 480], FRAME), None);
+        assert_eq!(voice.update(&[0.0; 480], FRAME), None);
+        assert_eq!(voice.update(&[0.0; 480], FRAME), Some(false));
+        assert!(!voice.is_speaking());
+    }
+
+    #[test]
+    fn should_stop_speaking_on_reset() {
+        let mut voice = VoiceActivityDetector::default();
+        assert_eq!(voice.reset(), None);
+        voice.update(&[0.5; 480], FRAME);
+        assert_eq!(voice.reset(), Some(false));
+        assert!(!voice.is_speaking());
+    }
+}
@@ -0,0 +1,874 @@
+//! Teammates talking to each other directly, without a server in between.
+//!
+//! Every member accepts connections on a listener of its own and is
+//! connected to every other member, sending its microphone audio to each.
+//! An [`Invite`] lists where members are reached; joining only takes
+//! connecting to one of them, which tells about the rest with a
+//! [`MeshMembersPacket`].
+//!
+//! Audio is played back per member, as it is per speaker of a server's room:
+//! each member gets a [`SpeakerId`] of its own, local to this client.
+
+use crate::{
+    audio::{codec::AudioCodec, AudioHandler, DeviceHandler},
+    client::{start_audio, Client},
+    error::ClientError,
+    handlers::{
+        audio::{AudioFrame, AudioPacketHandler},
+        speaker::Speakers,
+        speaking::SpeakingEvent,
+    },
+};
+use common::{
+    packet::{
+        error::DecodeError, format::WireFormat, fragment::FragmentCodec, ids::PacketId,
+        packet_type::PacketType, AudioPacket, ConnectStatus, MeshHelloPacket, MeshMember,
+        MeshMembersPacket, Packet, RelayedAudioPacket, Speaker, SpeakerId, SpeakingPacket,
+        MESH_PROTOCOL_VERSION,
+    },
+    transport::{
+        tcp::{TcpConnector, TcpListener},
+        Connection, Connector, Listener, PacketStream,
+    },
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{ReadHalf, WriteHalf},
+    select,
+    sync::{broadcast, mpsc, oneshot},
+    task::AbortHandle,
+    time::timeout,
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
+use uuid::Uuid;
+
+/// Members have nothing to negotiate, so everything goes in the format of
+/// the server's connect exchange.
+const FORMAT: WireFormat = WireFormat::HANDSHAKE;
+
+/// How long a member has to say hello once connected.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+type MemberReader = FramedRead<ReadHalf<Box<dyn PacketStream>>, FragmentCodec>;
+type MemberWriter = FramedWrite<WriteHalf<Box<dyn PacketStream>>, FragmentCodec>;
+
+/// Where members of a mesh are reached, shared with teammates to let them
+/// join. Written as `mesh:` followed by comma separated `host:port`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub addrs: Vec<String>,
+}
+
+impl Display for Invite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "mesh:{}", self.addrs.join(","))
+    }
+}
+
+impl FromStr for Invite {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ClientError::InvalidInvite(s.to_string());
+        let addrs = s.trim().strip_prefix("mesh:").ok_or_else(invalid)?;
+        let addrs: Vec<String> = addrs
+            .split(',')
+            .map(|addr| addr.trim().to_string())
+            .collect();
+        let valid = |addr: &String| {
+            addr.rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+        };
+        if !addrs.iter().all(valid) {
+            return Err(invalid());
+        }
+        Ok(Self { addrs })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MeshOptions {
+    pub display_name: String,
+    /// Address to accept other members on.
+    pub listen: String,
+    /// Address teammates reach the listener at, as put into invites, when it
+    /// is not the one bound to, such as behind a port forward.
+    pub advertise: Option<SocketAddr>,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            display_name: String::new(),
+            listen: "0.0.0.0:0".to_string(),
+            advertise: None,
+        }
+    }
+}
+
+/// Another member, as connected to.
+struct Peer {
+    /// Where it accepts connections, unless the transport has no addresses.
+    addr: Option<SocketAddr>,
+    speaker: SpeakerId,
+    sender: mpsc::Sender<Packet>,
+    /// Ends the connection once dropped.
+    _close: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct Members {
+    peers: HashMap<Uuid, Peer>,
+    /// Members being connected to, so none is connected to twice.
+    dialing: HashSet<Uuid>,
+}
+
+impl Members {
+    /// Returns the lowest speaker id no member holds, so ids are reused as
+    /// members leave, as a server does for its rooms.
+    fn allocate_speaker(&self) -> Option<SpeakerId> {
+        let mut taken: Vec<SpeakerId> = self.peers.values().map(|peer| peer.speaker).collect();
+        taken.sort_unstable();
+
+        let mut id: SpeakerId = 0;
+        for taken in taken {
+            if taken != id {
+                break;
+            }
+            id = id.checked_add(1)?;
+        }
+
+        Some(id)
+    }
+}
+
+/// State shared by the connections to every other member.
+struct Mesh<C: AudioCodec> {
+    member: Uuid,
+    display_name: String,
+    addr: SocketAddr,
+    members: Mutex<Members>,
+    speakers: Arc<Speakers>,
+    audio: AudioPacketHandler<C>,
+    speaking: broadcast::Sender<SpeakingEvent>,
+}
+
+impl<C: AudioCodec + 'static> Mesh<C> {
+    fn members(&self) -> std::sync::MutexGuard<'_, Members> {
+        self.members.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sends `packet` to every member. One that cannot keep up misses it
+    /// rather than holding it back for the others.
+    fn broadcast(&self, packet: &Packet) {
+        for peer in self.members().peers.values() {
+            let _ = peer.sender.try_send(packet.clone());
+        }
+    }
+
+    /// Connects to the member at `addr`.
+    async fn dial(self: Arc<Self>, addr: String) -> Result<(), ClientError> {
+        let connection = TcpConnector::new(addr).connect().await?;
+        self.hello(connection).await
+    }
+
+    /// Connects to `member` if it is up to this one, see [`MeshMembersPacket`],
+    /// and its address is one a member may be at, see [`dialable`].
+    fn introduce(self: &Arc<Self>, member: MeshMember) {
+        let addr = {
+            let mut members = self.members();
+            if member.member <= self.member || members.peers.contains_key(&member.member) {
+                return;
+            }
+            let observed = members.peers.values().filter_map(|peer| peer.addr);
+            let Some(addr) = dialable(&member.addr, observed.map(|addr| addr.ip())) else {
+                println!("Not connecting to mesh member at {}", member.addr);
+                return;
+            };
+            if !members.dialing.insert(member.member) {
+                return;
+            }
+            addr
+        };
+
+        let mesh = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mesh.clone().dial(addr.to_string()).await {
+                println!("Failed to connect to mesh member at {}: {}", addr, e);
+            }
+            mesh.members().dialing.remove(&member.member);
+        });
+    }
+
+    /// Exchanges hellos over a new connection, which is then served unless
+    /// it leads to a member already connected to.
+    async fn hello(self: Arc<Self>, connection: Connection) -> Result<(), ClientError> {
+        let (read, write) = tokio::io::split(connection.stream);
+        let mut reader = FramedRead::new(read, FragmentCodec::default());
+        let mut writer = FramedWrite::new(write, FragmentCodec::default());
+
+        let hello = MeshHelloPacket {
+            protocol_version: MESH_PROTOCOL_VERSION,
+            member: self.member,
+            display_name: self.display_name.clone(),
+            port: self.addr.port(),
+        };
+        writer.send(Packet::new(hello)?).await?;
+        let packet = match timeout(HELLO_TIMEOUT, reader.next()).await {
+            Ok(Some(packet)) => packet?,
+            Ok(None) => return Err(ClientError::ConnectionClosedByPeer),
+            Err(_) => return Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        };
+        if packet.packet_id != PacketId::MeshHelloPacket.to_u8() {
+            return Err(ClientError::UnexpectedHandshakePacket(packet.packet_id));
+        }
+        let hello = MeshHelloPacket::decode(&packet.data)?;
+        if hello.protocol_version != MESH_PROTOCOL_VERSION {
+            return Err(ClientError::ConnectionRejected(
+                ConnectStatus::UnsupportedVersion,
+            ));
+        }
+
+        let addr = connection
+            .peer
+            .map(|peer| SocketAddr::new(peer.ip(), hello.port));
+        let (sender, receiver) = mpsc::channel(32);
+        let (close_tx, close_rx) = oneshot::channel();
+        let speaker = {
+            let mut members = self.members();
+            // Reached through an invite listing this member, or twice.
+            if hello.member == self.member || members.peers.contains_key(&hello.member) {
+                return Ok(());
+            }
+            let Some(speaker) = members.allocate_speaker() else {
+                return Err(ClientError::ConnectionRejected(ConnectStatus::RoomFull));
+            };
+
+            let known = members
+                .peers
+                .iter()
+                .filter_map(|(&member, peer)| {
+                    let addr = peer.addr?.to_string();
+                    Some(MeshMember { member, addr })
+                })
+                .collect();
+            // The channel is new, so there is room.
+            let _ = sender.try_send(Packet::new(MeshMembersPacket { members: known })?);
+            if let Some(addr) = addr {
+                let joined = Packet::new(MeshMembersPacket {
+                    members: vec![MeshMember {
+                        member: hello.member,
+                        addr: addr.to_string(),
+                    }],
+                })?;
+                for peer in members.peers.values() {
+                    let _ = peer.sender.try_send(joined.clone());
+                }
+            }
+
+            members.peers.insert(
+                hello.member,
+                Peer {
+                    addr,
+                    speaker,
+                    sender,
+                    _close: close_tx,
+                },
+            );
+            speaker
+        };
+
+        println!("{} joined the mesh", hello.display_name);
+        self.speakers.write().await.insert(
+            speaker,
+            Speaker {
+                id: speaker,
+                client_id: hello.member,
+                display_name: hello.display_name,
+            },
+        );
+        tokio::spawn(send_packets(writer, receiver));
+        tokio::spawn(self.serve(hello.member, speaker, reader, close_rx));
+        Ok(())
+    }
+
+    /// Handles what `member` sends until either side ends the connection.
+    async fn serve(
+        self: Arc<Self>,
+        member: Uuid,
+        speaker: SpeakerId,
+        mut reader: MemberReader,
+        mut close_rx: oneshot::Receiver<()>,
+    ) {
+        loop {
+            let packet = select! {
+                packet = reader.next() => packet,
+                _ = &mut close_rx => break,
+            };
+            let result = match packet {
+                Some(Ok(packet)) => self.handle(member, speaker, packet).await,
+                Some(Err(e)) => Err(e.into()),
+                None => break,
+            };
+            if let Err(e) = result {
+                println!("Dropping mesh member {}: {}", member, e);
+                break;
+            }
+        }
+
+        // The speaker id stays taken until the member is removed, so nobody
+        // joining meanwhile gets it and is then forgotten here.
+        self.speakers.write().await.remove(&speaker);
+        self.audio.remove_speaker(&speaker).await;
+        let mut members = self.members();
+        if members
+            .peers
+            .get(&member)
+            .is_some_and(|peer| peer.speaker == speaker)
+        {
+            members.peers.remove(&member);
+        }
+    }
+
+    async fn handle(
+        self: &Arc<Self>,
+        member: Uuid,
+        speaker: SpeakerId,
+        packet: Packet,
+    ) -> Result<(), ClientError> {
+        let packet_type = match PacketId::from_u8(packet.packet_id) {
+            Some(packet_type) => packet_type,
+            None => return Err(DecodeError::UnknownPacketId(packet.packet_id).into()),
+        };
+
+        match packet_type {
+            PacketId::AudioPacket => {
+                let audio =
+                    AudioPacket::decode_as(&packet.data, FORMAT).map_err(DecodeError::from)?;
+                self.audio.play(RelayedAudioPacket { speaker, audio }).await
+            }
+            PacketId::SpeakingPacket => {
+                let packet =
+                    SpeakingPacket::decode_as(&packet.data, FORMAT).map_err(DecodeError::from)?;
+                // Nobody listening is fine.
+                let _ = self.speaking.send(SpeakingEvent {
+                    speaker,
+                    client_id: member,
+                    speaking: packet.speaking,
+                });
+                Ok(())
+            }
+            PacketId::MeshMembersPacket => {
+                let update = MeshMembersPacket::decode(&packet.data)?;
+                for member in update.members {
+                    self.introduce(member);
+                }
+                Ok(())
+            }
+            packet_type => {
+                println!("Unhandled packet type: {:?}", packet_type);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Where a member another one told about may be connected to.
+///
+/// Members pass on the addresses their connections came from, so those are
+/// always IPs, never host names to look up. Anything not reachable over the
+/// internet is only taken at the IP of a member already connected to, so
+/// members cannot point others at services of their own network.
+fn dialable(addr: &str, observed: impl IntoIterator<Item = IpAddr>) -> Option<SocketAddr> {
+    let addr: SocketAddr = addr.parse().ok()?;
+    if addr.port() == 0 {
+        return None;
+    }
+    let ip = addr.ip();
+    (is_public(ip) || observed.into_iter().any(|observed| observed == ip)).then_some(addr)
+}
+
+/// Whether `ip` is a unicast address reachable over the internet.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            // Shared address space of carrier-grade NATs.
+            let shared = first == 100 && (64..128).contains(&second);
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                // Unique local and link-local unicast.
+                let local = first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || local)
+            }
+        },
+    }
+}
+
+async fn send_packets(
+    mut writer: MemberWriter,
+    mut receiver: mpsc::Receiver<Packet>,
+) -> Result<(), ClientError> {
+    while let Some(packet) = receiver.recv().await {
+        writer.send(packet).await?;
+    }
+    Ok(())
+}
+
+/// A member of a mesh, see the [module docs](self).
+pub struct MeshClient<A: AudioHandler, D: DeviceHandler> {
+    audio_handler: Arc<A>,
+    device_handler: D,
+
+    stop_tx: Option<oneshot::Sender<()>>,
+
+    mesh: Arc<Mesh<A::Codec>>,
+    packet_sender: mpsc::Sender<Packet>,
+    chan_output_rx: Arc<broadcast::Receiver<AudioFrame>>,
+    deafened: Arc<AtomicBool>,
+    /// Accepting members and sending them audio, until dropped.
+    tasks: [AbortHandle; 2],
+}
+
+impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> MeshClient<A, D> {
+    /// Starts a mesh of one, for teammates to join through [`Self::invite`].
+    pub async fn start(options: MeshOptions) -> Result<Self, ClientError> {
+        let mut listener = TcpListener::bind(&options.listen).await?;
+        let addr = options.advertise.unwrap_or(listener.local_addr()?);
+        println!("Accepting mesh members on: {}", addr);
+
+        let (chan_output_tx, chan_output_rx) = broadcast::channel::<AudioFrame>(32);
+        let mesh = Arc::new(Mesh {
+            member: Uuid::new_v4(),
+            display_name: options.display_name,
+            addr,
+            members: Mutex::new(Members::default()),
+            speakers: Arc::new(Speakers::default()),
+            audio: AudioPacketHandler::new(chan_output_tx, FORMAT),
+            speaking: broadcast::channel(32).0,
+        });
+
+        let accept_mesh = mesh.clone();
+        let accept_handle = tokio::spawn(async move {
+            loop {
+                let incoming = match listener.accept().await {
+                    Ok(incoming) => incoming,
+                    Err(e) => {
+                        println!("Stopped accepting mesh members: {}", e);
+                        return;
+                    }
+                };
+                let mesh = accept_mesh.clone();
+                tokio::spawn(async move {
+                    let result = match incoming.await {
+                        Ok(connection) => mesh.hello(connection).await,
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Err(e) = result {
+                        println!("Mesh member failed to connect: {}", e);
+                    }
+                });
+            }
+        });
+
+        let (packet_sender, mut message_receiver) = mpsc::channel::<Packet>(32);
+        let send_mesh = mesh.clone();
+        let send_handle = tokio::spawn(async move {
+            while let Some(packet) = message_receiver.recv().await {
+                send_mesh.broadcast(&packet);
+            }
+        });
+
+        Ok(Self {
+            audio_handler: Arc::new(A::new()?),
+            device_handler: D::new()?,
+            stop_tx: None,
+            mesh,
+            packet_sender,
+            chan_output_rx: Arc::new(chan_output_rx),
+            deafened: Arc::new(AtomicBool::new(false)),
+            tasks: [accept_handle.abort_handle(), send_handle.abort_handle()],
+        })
+    }
+
+    /// Joins the mesh of `invite`, through the first of its members that
+    /// answers.
+    pub async fn join(invite: &Invite, options: MeshOptions) -> Result<Self, ClientError> {
+        let client = Self::start(options).await?;
+        let mut result = Err(ClientError::InvalidInvite(invite.to_string()));
+        for addr in &invite.addrs {
+            result = client.mesh.clone().dial(addr.clone()).await;
+            if result.is_ok() {
+                break;
+            }
+            println!("Failed to join the mesh at {}", addr);
+        }
+        result.map(|_| client)
+    }
+
+    /// Invite to the mesh, listing this member and every other one.
+    pub fn invite(&self) -> Invite {
+        let mut addrs = vec![self.mesh.addr.to_string()];
+        let members = self.mesh.members();
+        addrs.extend(
+            members
+                .peers
+                .values()
+                .filter_map(|peer| Some(peer.addr?.to_string())),
+        );
+        Invite { addrs }
+    }
+
+    /// Id this client goes by in the mesh.
+    pub fn member(&self) -> Uuid {
+        self.mesh.member
+    }
+
+    /// Looks up which member is behind a speaker id.
+    pub async fn speaker(&self, id: SpeakerId) -> Option<Speaker> {
+        self.mesh.speakers.read().await.get(&id).cloned()
+    }
+
+    /// Other members connected to, ordered by speaker id.
+    pub async fn members(&self) -> Vec<Speaker> {
+        let mut members: Vec<Speaker> = self.mesh.speakers.read().await.values().cloned().collect();
+        members.sort_unstable_by_key(|member| member.id);
+        members
+    }
+
+    /// Subscribes to other members starting and stopping to speak, as they
+    /// announce it themselves.
+    pub fn speaking_events(&self) -> broadcast::Receiver<SpeakingEvent> {
+        self.mesh.speaking.subscribe()
+    }
+
+    /// Stops sending microphone audio without leaving the mesh.
+    pub fn set_muted(&self, muted: bool) {
+        self.audio_handler.set_muted(muted);
+    }
+
+    /// Stops playing back the other members without leaving the mesh. Their
+    /// audio still arrives, as nobody is there to stop sending it.
+    pub fn set_deafened(&self, deafened: bool) {
+        self.deafened.store(deafened, Ordering::Relaxed);
+    }
+}
+
+impl<A: AudioHandler, D: DeviceHandler> Drop for MeshClient<A, D> {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        // Dropping the peers is what closes their connections.
+        self.mesh
+            .members
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .peers
+            .clear();
+    }
+}
+
+#[async_trait::async_trait]
+impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> Client<A, D> for MeshClient<A, D> {
+    /// Joins the mesh of the invite `addr`, see [`Invite`].
+    async fn connect(addr: Cow<'_, str>) -> Result<Self, ClientError> {
+        Self::join(&addr.parse()?, MeshOptions::default()).await
+    }
+
+    async fn run(&mut self) -> Result<(), ClientError> {
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        self.stop_tx = Some(stop_tx);
+
+        let (microphone_handle, output_handle) = start_audio(
+            &self.audio_handler,
+            &mut self.device_handler,
+            self.packet_sender.clone(),
+            &self.chan_output_rx,
+            self.deafened.clone(),
+            FORMAT,
+        )
+        .await?;
+
+        select! {
+            Ok(microphone_result) = microphone_handle => {
+                println!("Microphone result: {:?}", microphone_result);
+                Ok(())
+            }
+            Ok(stop_result) = stop_rx => {
+                println!("Stop result: {:?}", stop_result);
+                Ok(())
+            }
+            Ok(output_result) = output_handle => {
+                println!("Output result: {:?}", output_result);
+                Ok(())
+            }
+        }
+    }
+
+    fn device_handler(&self) -> &D {
+        &self.device_handler
+    }
+
+    fn device_handler_mut(&mut self) -> &mut D {
+        &mut self.device_handler
+    }
+
+    async fn stop(&mut self) -> Result<(), ClientError> {
+        let Some(stop_tx) = self.stop_tx.take() else {
+            return Ok(());
+        };
+
+        // The run loop may have ended on its own already.
+        let _ = stop_tx.send(());
+        self.audio_handler.stop().await?;
+        self.device_handler.stop().await?;
+
+        Ok(())
+    }
+
+    async fn is_running(&self) -> bool {
+        self.stop_tx.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::{codec::opus::OpusAudioCodec, cpal::CpalAudioHandler},
+        client::tokio::tests::NoDevices,
+    };
+
+    type HeadlessMember = MeshClient<CpalAudioHandler<OpusAudioCodec>, NoDevices>;
+
+    fn options(display_name: &str) -> MeshOptions {
+        MeshOptions {
+            display_name: display_name.to_string(),
+            listen: "127.0.0.1:0".to_string(),
+            advertise: None,
+        }
+    }
+
+    async fn wait_for_members(member: &HeadlessMember, count: usize) {
+        timeout(Duration::from_secs(5), async {
+            while member.members().await.len() != count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("mesh did not settle");
+    }
+
+    #[test]
+    fn should_read_back_invites() {
+        let invite: Invite = "mesh:127.0.0.1:4000, example.com:4001".parse().unwrap();
+        assert_eq!(invite.addrs, vec!["127.0.0.1:4000", "example.com:4001"]);
+        assert_eq!(invite.to_string().parse::<Invite>().unwrap(), invite);
+
+        for invalid in ["127.0.0.1:4000", "mesh:", "mesh:localhost", "mesh::4000"] {
+            assert!(matches!(
+                invalid.parse::<Invite>(),
+                Err(ClientError::InvalidInvite(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn should_only_dial_members_at_public_or_observed_ips() {
+        let observed = ["192.168.1.20".parse().unwrap()];
+        for addr in ["8.8.8.8:4000", "[2001:4860::1]:4000", "192.168.1.20:4000"] {
+            assert_eq!(dialable(addr, observed), addr.parse().ok(), "{}", addr);
+        }
+
+        for addr in [
+            "example.com:4000",
+            "8.8.8.8:0",
+            "192.168.1.21:4000",
+            "203.0.113.1:4000",
+            "127.0.0.1:22",
+            "10.0.0.1:4000",
+            "169.254.169.254:80",
+            "100.64.0.1:4000",
+            "[::1]:4000",
+            "[fd00::1]:4000",
+            "[::ffff:10.0.0.1]:4000",
+        ] {
+            assert_eq!(dialable(addr, observed), None, "{}", addr);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_connect_every_member_and_play_each_one_apart() {
+        let first = HeadlessMember::start(options("first")).await.unwrap();
+        let second = HeadlessMember::join(&first.invite(), options("second"))
+            .await
+            .unwrap();
+        // Only told about the second member, which knows the first one.
+        let invite = Invite {
+            addrs: vec![second.invite().addrs[0].clone()],
+        };
+        let third = HeadlessMember::join(&invite, options("third"))
+            .await
+            .unwrap();
+
+        for member in [&first, &second, &third] {
+            wait_for_members(member, 2).await;
+        }
+        assert_eq!(third.invite().addrs.len(), 3);
+
+        let mut second_output = second.chan_output_rx.resubscribe();
+        let mut third_output = third.chan_output_rx.resubscribe();
+        let mut codec = OpusAudioCodec::new().unwrap();
+        codec.update(48000, 1).unwrap();
+        for (member, sequence) in [(&first, 1), (&third, 2)] {
+            let audio = AudioPacket {
+                sequence,
+                timestamp: 0,
+                track: codec.encode(vec![0.0; 480]).unwrap(),
+            };
+            member
+                .packet_sender
+                .send(Packet::new(audio).unwrap())
+                .await
+                .unwrap();
+        }
+
+        let mut heard = HashMap::new();
+        for _ in 0..2 {
+            let frame = timeout(Duration::from_secs(5), second_output.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let speaker = second.speaker(frame.speaker).await.unwrap();
+            heard.insert(speaker.client_id, frame.sequence);
+        }
+        assert_eq!(
+            heard,
+            HashMap::from([(first.member(), 1), (third.member(), 2)])
+        );
+
+        let frame = timeout(Duration::from_secs(5), third_output.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let speaker = third.speaker(frame.speaker).await.unwrap();
+        assert_eq!(speaker.client_id, first.member());
+        assert_eq!(speaker.display_name, "first");
+    }
+
+    #[test]
+    fn should_reuse_the_lowest_free_speaker_id() {
+        let peer = |speaker| {
+            let (sender, _) = mpsc::channel(1);
+            let (close, _) = oneshot::channel();
+            Peer {
+                addr: None,
+                speaker,
+                sender,
+                _close: close,
+            }
+        };
+
+        let mut members = Members::default();
+        assert_eq!(members.allocate_speaker(), Some(0));
+        for speaker in [0, 1, 2] {
+            members.peers.insert(Uuid::new_v4(), peer(speaker));
+        }
+        assert_eq!(members.allocate_speaker(), Some(3));
+
+        members.peers.retain(|_, peer| peer.speaker != 1);
+        assert_eq!(members.allocate_speaker(), Some(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_turn_speaking_packets_into_events() {
+        let first = HeadlessMember::start(options("first")).await.unwrap();
+        let second = HeadlessMember::join(&first.invite(), options("second"))
+            .await
+            .unwrap();
+        for member in [&first, &second] {
+            wait_for_members(member, 1).await;
+        }
+
+        let mut events = second.speaking_events();
+        first
+            .packet_sender
+            .send(Packet::new(SpeakingPacket { speaking: true }).unwrap())
+            .await
+            .unwrap();
+        let event = timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("expected a speaking event")
+            .unwrap();
+        assert_eq!(event.client_id, first.member());
+        assert_eq!(
+            second.speaker(event.speaker).await.unwrap().client_id,
+            first.member()
+        );
+        assert!(event.speaking);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_forget_members_that_leave() {
+        let first = HeadlessMember::start(options("first")).await.unwrap();
+        let second = HeadlessMember::join(&first.invite(), options("second"))
+            .await
+            .unwrap();
+        let third = HeadlessMember::join(&second.invite(), options("third"))
+            .await
+            .unwrap();
+        for member in [&first, &second, &third] {
+            wait_for_members(member, 2).await;
+        }
+
+        let left = third.member();
+        drop(third);
+
+        for member in [&first, &second] {
+            wait_for_members(member, 1).await;
+            assert!(member
+                .members()
+                .await
+                .iter()
+                .all(|speaker| speaker.client_id != left));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_fail_to_join_without_a_member_answering() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let invite = Invite {
+            addrs: vec![listener.local_addr().unwrap().to_string()],
+        };
+        drop(listener);
+
+        assert!(HeadlessMember::join(&invite, options("alone"))
+            .await
+            .is_err());
+    }
+}
@@ -1,10 +1,78 @@
 use crate::{
-    audio::{AudioHandler, DeviceHandler},
+    audio::{codec::AudioCodec, AudioHandler, DeviceHandler, DeviceType},
     error::ClientError,
+    handlers::audio::AudioFrame,
+};
+use ::tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use common::{
+    heartbeat::HeartbeatConfig,
+    packet::{format::WireFormat, Packet, Summoner},
+    quic::CertificateDer,
+    secure::PublicKey,
+    tls::Trust,
+};
+use std::{
+    borrow::Cow,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
-use std::borrow::Cow;
 
+pub mod mesh;
+mod quic;
 pub mod tokio;
+pub mod udp;
+
+/// Settings sent to, or negotiated with, the server when connecting.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Room to join; clients in the same room hear each other.
+    pub room: String,
+    pub display_name: String,
+    /// Shown in the roster until changed with `TokioClient::update_user`.
+    pub summoner: Option<Summoner>,
+    pub heartbeat: HeartbeatConfig,
+    /// Records the session to this file, see [`common::capture`].
+    pub capture: Option<PathBuf>,
+    pub encryption: Encryption,
+    /// Offers the server to carry audio over UDP, which keeps a lost packet
+    /// from holding back the frames after it. Audio stays on TCP wherever
+    /// datagrams do not get through.
+    pub udp: bool,
+    /// Certificates that `quic://` servers are trusted by, see
+    /// [`common::quic`]. Usually the server's own, self-signed one.
+    pub quic_roots: Vec<CertificateDer<'static>>,
+    /// How `tls://` servers are trusted, see [`common::tls`]: by roots, or
+    /// for a self-signed certificate, by its pinned fingerprint.
+    pub tls: Trust,
+}
+
+/// Whether the connection is encrypted, see [`common::secure`]. It has to
+/// match the server, which either encrypts every connection or none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encryption {
+    #[default]
+    None,
+    /// Trusts whichever key the server authenticates with.
+    Unpinned,
+    /// Refuses servers authenticating with any other key.
+    Pinned(PublicKey),
+}
+
+impl Encryption {
+    /// Key the server has to authenticate with, if pinned.
+    pub fn pinned_key(&self) -> Option<&PublicKey> {
+        match self {
+            Encryption::Pinned(key) => Some(key),
+            Encryption::None | Encryption::Unpinned => None,
+        }
+    }
+}
 
 #[async_trait::async_trait]
 pub trait Client<A: AudioHandler, D: DeviceHandler>: Send + Sync + Sized {
@@ -18,3 +86,59 @@ pub trait Client<A: AudioHandler, D: DeviceHandler>: Send + Sync + Sized {
 
     async fn is_running(&self) -> bool;
 }
+
+/// Starts the active devices, sending microphone audio to `packet_sender` in
+/// `format` and playing back the frames of `output` unless `deafened`.
+///
+/// Returns the microphone and playback tasks, either of which ending means
+/// audio stopped.
+pub(crate) async fn start_audio<A: AudioHandler + 'static, D: DeviceHandler>(
+    audio_handler: &Arc<A>,
+    device_handler: &mut D,
+    packet_sender: mpsc::Sender<Packet>,
+    output: &broadcast::Receiver<AudioFrame>,
+    deafened: Arc<AtomicBool>,
+    format: WireFormat,
+) -> Result<
+    (
+        JoinHandle<Result<(), ClientError>>,
+        JoinHandle<Result<(), ClientError>>,
+    ),
+    ClientError,
+> {
+    let (mic_tx, mic_rx) = mpsc::channel::<Vec<f32>>(20);
+    let (output_tx, output_rx) = std::sync::mpsc::channel::<Vec<f32>>();
+
+    device_handler.start_actives(mic_tx, output_rx).await?;
+
+    {
+        let input_device = match device_handler.get_active_device(DeviceType::Input) {
+            Some(device) => device,
+            None => return Err(ClientError::NoDevice),
+        };
+
+        audio_handler.get_codec().lock().await.update(
+            input_device.config().sample_rate().0,
+            input_device.config().channels() as usize,
+        )?;
+    }
+
+    let audio_handler = audio_handler.clone();
+    let microphone_handle =
+        ::tokio::spawn(async move { audio_handler.start(mic_rx, packet_sender, format).await });
+
+    let mut output_rx = output.resubscribe();
+    let output_handle = ::tokio::spawn(async move {
+        while let Ok(frame) = output_rx.recv().await {
+            if deafened.load(Ordering::Relaxed) {
+                continue;
+            }
+            if output_tx.send(frame.samples).is_err() {
+                break;
+            }
+        }
+        Ok::<(), ClientError>(())
+    });
+
+    Ok((microphone_handle, output_handle))
+}
@@ -0,0 +1,18 @@
+use crate::{client::udp, error::ClientError, handlers::PacketHandlers};
+use common::{packet::codec::CodecError, quic::QuicDatagrams};
+use std::sync::Arc;
+
+/// Passes the audio among the datagrams of a QUIC connection on to
+/// `handlers`, see [`common::quic`].
+pub(crate) async fn run(
+    datagrams: Arc<QuicDatagrams>,
+    handlers: Arc<PacketHandlers>,
+) -> Result<(), ClientError> {
+    loop {
+        match datagrams.receive().await {
+            Ok(packet) => udp::handle_audio(&handlers, packet).await,
+            Err(CodecError::Io(e)) => return Err(e.into()),
+            Err(e) => println!("Dropping datagram from server: {}", e),
+        }
+    }
+}
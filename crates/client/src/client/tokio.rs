@@ -1,17 +1,62 @@
 use crate::{
-    audio::{codec::AudioCodec, AudioHandler, DeviceHandler, DeviceType},
-    client::Client,
+    audio::{AudioHandler, DeviceHandler},
+    client::{
+        quic, start_audio,
+        udp::{self, UdpContext, UdpPath},
+        Client, ConnectOptions, Encryption,
+    },
     error::ClientError,
-    handlers::audio::AudioPacketHandler,
+    handlers::{
+        audio::{AudioFrame, AudioPacketHandler},
+        callout::{CalloutEvent, CalloutPacketHandler},
+        chat::{ChatLog, ChatPacketHandler},
+        close::ClosePacketHandler,
+        heartbeat::HeartbeatPacketHandler,
+        rejected::RejectedPacketHandler,
+        roster::{Roster, RosterPacketHandler},
+        speaker::{SpeakerPacketHandler, Speakers},
+        speaking::{SpeakingEvent, SpeakingPacketHandler},
+        udp::UdpOfferPacketHandler,
+        PacketHandlers,
+    },
+};
+use common::{
+    address::Scheme,
+    capture::{Capture, Direction, Side},
+    datagram::DatagramPath,
+    heartbeat::Heartbeat,
+    packet::{
+        error::DecodeError, format::WireFormat, fragment::FragmentCodec, ids::PacketId,
+        packet_type::PacketType, Callout, CalloutPacket, Capabilities, ChatHistoryPacket,
+        ChatPacket, ClosePacket, ConnectPacket, ConnectResponsePacket, ConnectStatus, Packet,
+        PingPacket, PongPacket, RejectedPacket, RelayedAudioPacket, RelayedCalloutPacket,
+        RelayedChatPacket, RelayedSpeakingPacket, RosterPacket, RosterUser, Speaker, SpeakerId,
+        SpeakerMapPacket, Summoner, UdpOfferPacket, UserUpdatePacket,
+    },
+    quic::{QuicConnector, QuicDatagrams},
+    secure::{self, SecureCodec},
+    tls::TlsConnector,
+    transport::{tcp::TcpConnector, Connection, Connector, PacketStream},
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
-use common::packet::{ids::PacketId, Packet, MAX_PACKET_SIZE};
-use std::{borrow::Cow, sync::Arc};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::ReadHalf,
     select,
-    sync::{broadcast, mpsc, oneshot},
+    sync::{broadcast, mpsc, oneshot, watch, Mutex},
+    time::{interval_at, Instant},
 };
+use tokio_util::codec::{FramedRead, FramedWrite};
+use uuid::Uuid;
+
+type PacketReader = FramedRead<ReadHalf<Box<dyn PacketStream>>, SecureCodec<FragmentCodec>>;
 
 pub struct TokioClient<A: AudioHandler, D: DeviceHandler> {
     audio_handler: Arc<A>,
@@ -20,80 +65,431 @@ pub struct TokioClient<A: AudioHandler, D: DeviceHandler> {
     stop_tx: Option<oneshot::Sender<()>>,
 
     packet_sender: mpsc::Sender<Packet>,
-    chan_output_rx: Arc<broadcast::Receiver<Vec<f32>>>,
+    chan_output_rx: Arc<broadcast::Receiver<AudioFrame>>,
+
+    capabilities: Capabilities,
+    format: WireFormat,
+    speakers: Arc<Speakers>,
+    roster: Arc<Roster>,
+    speaking_tx: broadcast::Sender<SpeakingEvent>,
+    chat_log: Arc<ChatLog>,
+    chat_tx: broadcast::Sender<RelayedChatPacket>,
+    callout_tx: broadcast::Sender<CalloutEvent>,
+    /// The local user as last announced to the room.
+    user: Mutex<UserUpdatePacket>,
+    deafened: Arc<AtomicBool>,
+    heartbeat: Arc<Mutex<Heartbeat>>,
+    close_rx: watch::Receiver<Option<ClosePacket>>,
+    udp: Arc<UdpPath>,
 }
 
-#[async_trait::async_trait]
-impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> Client<A, D> for TokioClient<A, D> {
-    async fn connect(addr: Cow<'_, str>) -> Result<Self, ClientError> {
-        let stream = TcpStream::connect(Cow::into_owned(addr.clone())).await?;
+impl<A: AudioHandler, D: DeviceHandler> TokioClient<A, D> {
+    /// Capabilities negotiated with the server during the connect exchange.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Payload format negotiated with the server.
+    pub fn format(&self) -> WireFormat {
+        self.format
+    }
+
+    /// Looks up who is behind a speaker id of the current room.
+    pub async fn speaker(&self, id: SpeakerId) -> Option<Speaker> {
+        self.speakers.read().await.get(&id).cloned()
+    }
+
+    /// Users of the current room, the local one included, ordered by speaker id.
+    pub async fn roster(&self) -> Vec<RosterUser> {
+        let mut roster: Vec<RosterUser> = self.roster.read().await.values().cloned().collect();
+        roster.sort_unstable_by_key(|user| user.speaker);
+        roster
+    }
+
+    pub async fn user(&self, client_id: Uuid) -> Option<RosterUser> {
+        self.roster.read().await.get(&client_id).cloned()
+    }
+
+    /// Changes the summoner shown for the local user in the roster.
+    pub async fn set_summoner(&self, summoner: Option<Summoner>) -> Result<(), ClientError> {
+        self.update_user(|user| user.summoner = summoner).await
+    }
+
+    /// Stops sending microphone audio without leaving the room. The server
+    /// drops any that is still on its way.
+    pub async fn set_muted(&self, muted: bool) -> Result<(), ClientError> {
+        self.audio_handler.set_muted(muted);
+        self.update_user(|user| set_flag(&mut user.flags, RosterUser::MUTED, muted))
+            .await
+    }
+
+    /// Stops playing back the room without leaving it. The server stops
+    /// relaying audio to the client as well.
+    pub async fn set_deafened(&self, deafened: bool) -> Result<(), ClientError> {
+        self.deafened.store(deafened, Ordering::Relaxed);
+        self.update_user(|user| set_flag(&mut user.flags, RosterUser::DEAFENED, deafened))
+            .await
+    }
+
+    pub async fn is_muted(&self) -> bool {
+        self.user.lock().await.flags & RosterUser::MUTED != 0
+    }
+
+    pub async fn is_deafened(&self) -> bool {
+        self.user.lock().await.flags & RosterUser::DEAFENED != 0
+    }
+
+    /// Applies `change` to the local user and announces the result. The roster
+    /// itself is updated once the server has relayed it back.
+    async fn update_user(
+        &self,
+        change: impl FnOnce(&mut UserUpdatePacket),
+    ) -> Result<(), ClientError> {
+        let mut user = self.user.lock().await;
+        change(&mut user);
+        self.packet_sender
+            .send(Packet::new_as(user.clone(), self.format)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribes to other users of the room starting and stopping to speak.
+    pub fn speaking_events(&self) -> broadcast::Receiver<SpeakingEvent> {
+        self.speaking_tx.subscribe()
+    }
+
+    /// Sends a chat message to the room. It shows up in [`Self::chat_messages`]
+    /// once the server has relayed it back.
+    pub async fn send_chat(&self, text: impl Into<String>) -> Result<(), ClientError> {
+        let chat = ChatPacket { text: text.into() };
+        if chat.text.trim().chars().count() > ChatPacket::MAX_LENGTH {
+            return Err(ClientError::ChatMessageTooLong);
+        }
+        self.packet_sender
+            .send(Packet::new_as(chat, self.format)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribes to chat messages of the room, the local user's included.
+    pub fn chat_messages(&self) -> broadcast::Receiver<RelayedChatPacket> {
+        self.chat_tx.subscribe()
+    }
+
+    /// Latest chat messages of the room, oldest first, starting with those
+    /// sent before joining.
+    pub async fn chat_history(&self) -> Vec<RelayedChatPacket> {
+        self.chat_log.read().await.iter().cloned().collect()
+    }
+
+    /// Sends a callout to the room, its cue plays for everyone once the
+    /// server has relayed it.
+    pub async fn send_callout(
+        &self,
+        callout: Callout,
+        target: Option<String>,
+    ) -> Result<(), ClientError> {
+        if target
+            .as_ref()
+            .is_some_and(|target| target.chars().count() > CalloutPacket::MAX_TARGET_LENGTH)
+        {
+            return Err(ClientError::CalloutTargetTooLong);
+        }
+        let callout = CalloutPacket { callout, target };
+        self.packet_sender
+            .send(Packet::new_as(callout, self.format)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribes to callouts of the room, the local user's included.
+    pub fn callout_events(&self) -> broadcast::Receiver<CalloutEvent> {
+        self.callout_tx.subscribe()
+    }
+
+    /// Round-trip time to the server, measured by the latest answered ping.
+    pub async fn rtt(&self) -> Option<Duration> {
+        self.heartbeat.lock().await.rtt()
+    }
+
+    /// Whether audio goes as datagrams rather than over the connection's
+    /// stream, as it does over QUIC or once UDP worked out, see
+    /// [`ConnectOptions::udp`].
+    pub fn is_audio_over_udp(&self) -> bool {
+        self.udp.get().is_some()
+    }
+
+    /// Why the server closed the connection, once it has.
+    pub fn close_reason(&self) -> Option<ClosePacket> {
+        self.close_rx.borrow().clone()
+    }
+
+    async fn handshake(
+        reader: &mut PacketReader,
+        capture: &Capture,
+    ) -> Result<ConnectResponsePacket, ClientError> {
+        let packet = match reader.next().await {
+            Some(packet) => packet?,
+            None => return Err(ClientError::ConnectionClosedByPeer),
+        };
+        capture.record(Direction::Received, &packet);
+
+        if packet.packet_id == PacketId::ClosePacket.to_u8() {
+            return Err(ClosePacket::decode(&packet.data)?.into());
+        }
+        if packet.packet_id != PacketId::ConnectResponsePacket.to_u8() {
+            return Err(ClientError::UnexpectedHandshakePacket(packet.packet_id));
+        }
+
+        let response = ConnectResponsePacket::decode(&packet.data)?;
+        if !response.is_accepted() {
+            return Err(ClientError::ConnectionRejected(response.status));
+        }
+        // A newer server answers with this build's version, an older one
+        // with its own, which may be too old.
+        if !response.is_supported_version() {
+            return Err(ClientError::ConnectionRejected(
+                ConnectStatus::UnsupportedVersion,
+            ));
+        }
+
+        Ok(response)
+    }
+}
+
+impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> TokioClient<A, D> {
+    /// Connects to the server and joins the room named in `options`.
+    ///
+    /// `addr` may start with `quic://` to connect over QUIC rather than TCP,
+    /// see [`common::quic`], or with `tls://` to run TLS over TCP, see
+    /// [`common::tls`].
+    pub async fn connect_with(
+        addr: Cow<'_, str>,
+        options: ConnectOptions,
+    ) -> Result<Self, ClientError> {
+        let connection = match Scheme::split(&addr) {
+            Some((Scheme::Tcp, host)) => TcpConnector::new(host).connect().await?,
+            Some((Scheme::Quic, host)) => {
+                let roots = options.quic_roots.clone();
+                QuicConnector::new(host, roots).connect().await?
+            }
+            Some((Scheme::Tls, host)) => {
+                let name = common::quic::host(host);
+                TlsConnector::new(TcpConnector::new(host), name, &options.tls)?
+                    .connect()
+                    .await?
+            }
+            None => return Err(ClientError::UnsupportedScheme(addr.into_owned())),
+        };
         println!("Connected to server: {}", addr);
 
+        Self::connect_over(connection, options).await
+    }
+
+    /// Joins the room named in `options` over `connection`, of any transport,
+    /// see [`common::transport`].
+    pub async fn connect_over(
+        connection: Connection,
+        options: ConnectOptions,
+    ) -> Result<Self, ClientError> {
+        let Connection {
+            mut stream,
+            peer,
+            quic,
+        } = connection;
+
+        let session = match options.encryption {
+            Encryption::None => None,
+            Encryption::Unpinned | Encryption::Pinned(_) => {
+                let pinned = options.encryption.pinned_key();
+                Some(secure::initiate(&mut stream, pinned).await?)
+            }
+        };
+        let codec = || match &session {
+            Some(session) => session.codec(FragmentCodec::default()),
+            None => SecureCodec::plaintext(FragmentCodec::default()),
+        };
+        let (read_codec, write_codec) = (codec(), codec());
+
+        let capture = match &options.capture {
+            Some(path) => Capture::create(path, Side::Client)?,
+            None => Capture::default(),
+        };
         let (packet_sender, mut message_receiver) = mpsc::channel::<Packet>(32);
-        let (chan_output_tx, chan_output_rx) = broadcast::channel::<Vec<f32>>(32);
+        let (chan_output_tx, chan_output_rx) = broadcast::channel::<AudioFrame>(32);
+
+        let (read, write) = tokio::io::split(stream);
+        let mut reader = FramedRead::new(read, read_codec);
+        let mut writer = FramedWrite::new(write, write_codec);
+
+        let mut capabilities = Capabilities::default();
+        // QUIC connections come with datagrams of their own.
+        if options.udp && quic.is_none() && peer.is_some() {
+            capabilities.transports |= Capabilities::TRANSPORT_UDP;
+        }
+        let connect = ConnectPacket {
+            capabilities,
+            room: options.room,
+            display_name: options.display_name,
+            summoner: options.summoner.clone(),
+            ..Default::default()
+        };
+        let connect = Packet::new(connect)?;
+        capture.record(Direction::Sent, &connect);
+        writer.send(connect).await?;
+        let response = Self::handshake(&mut reader, &capture).await?;
+        println!("Negotiated capabilities: {:?}", response.capabilities);
+        let format = response
+            .capabilities
+            .format()
+            .unwrap_or(WireFormat::HANDSHAKE);
 
         let audio_handler: Arc<A> = Arc::new(A::new()?);
-        let audio_handler_clone = audio_handler.clone();
-        let (mut read, mut write) = stream.into_split();
+        let speakers = Arc::new(Speakers::default());
+        let roster = Arc::new(Roster::default());
+        let (speaking_tx, _) = broadcast::channel(32);
+        let chat_log = Arc::new(ChatLog::default());
+        let (chat_tx, _) = broadcast::channel(32);
+        let (callout_tx, _) = broadcast::channel(32);
+        let heartbeat = Arc::new(Mutex::new(Heartbeat::new(options.heartbeat)));
+        let (close_tx, close_rx) = watch::channel(None);
+        let (udp_offer_tx, udp_offer_rx) = mpsc::channel(1);
+        let udp = Arc::new(UdpPath::default());
+
+        let audio_packet_handler = Arc::new(AudioPacketHandler::<A::Codec>::new(
+            chan_output_tx.clone(),
+            format,
+        ));
+        let heartbeat_packet_handler = Arc::new(HeartbeatPacketHandler::new(
+            heartbeat.clone(),
+            packet_sender.clone(),
+            format,
+        ));
+        let mut handlers = PacketHandlers::new();
+        handlers.register::<RelayedAudioPacket>(Box::new(audio_packet_handler.clone()));
+        handlers.register::<SpeakerMapPacket>(Box::new(SpeakerPacketHandler::new(
+            speakers.clone(),
+            audio_packet_handler,
+            format,
+        )));
+        handlers
+            .register::<RosterPacket>(Box::new(RosterPacketHandler::new(roster.clone(), format)));
+        handlers.register::<RelayedSpeakingPacket>(Box::new(SpeakingPacketHandler::new(
+            speakers.clone(),
+            speaking_tx.clone(),
+            format,
+        )));
+        handlers.register::<RelayedCalloutPacket>(Box::new(CalloutPacketHandler::new(
+            speakers.clone(),
+            chan_output_tx,
+            callout_tx.clone(),
+            format,
+        )));
+        let chat_packet_handler = Arc::new(ChatPacketHandler::new(
+            chat_log.clone(),
+            chat_tx.clone(),
+            format,
+        ));
+        handlers.register::<RelayedChatPacket>(Box::new(chat_packet_handler.clone()));
+        handlers.register::<ChatHistoryPacket>(Box::new(chat_packet_handler));
+        handlers.register::<PingPacket>(Box::new(heartbeat_packet_handler.clone()));
+        handlers.register::<PongPacket>(Box::new(heartbeat_packet_handler));
+        handlers.register::<ClosePacket>(Box::new(ClosePacketHandler::new(close_tx, format)));
+        handlers
+            .register::<UdpOfferPacket>(Box::new(UdpOfferPacketHandler::new(udp_offer_tx, format)));
+        handlers.register::<RejectedPacket>(Box::new(RejectedPacketHandler::new(format)));
+        let handlers = Arc::new(handlers);
+
+        let datagram_handle = match (quic, peer) {
+            (Some(connection), _) => {
+                let datagrams = Arc::new(QuicDatagrams::new(connection, capture.clone()));
+                udp.set(Some(DatagramPath::Quic(datagrams.clone())));
+                Some(tokio::spawn(quic::run(datagrams, handlers.clone())))
+            }
+            (None, Some(server)) => Some(tokio::spawn(udp::run(
+                udp_offer_rx,
+                UdpContext {
+                    server,
+                    session,
+                    capture: capture.clone(),
+                    format,
+                    heartbeat: options.heartbeat,
+                    stream: packet_sender.clone(),
+                },
+                udp.clone(),
+                handlers.clone(),
+            ))),
+            // Nowhere to send datagrams to, as over an in-memory transport.
+            (None, None) => None,
+        };
 
+        let read_capture = capture.clone();
         let read_handle = tokio::spawn(async move {
             println!("Started reading from server");
-            let audio_handler = audio_handler_clone.clone();
-            let mut buffer = Vec::with_capacity(MAX_PACKET_SIZE * 2);
-            loop {
-                let mut temp_buffer = [0; MAX_PACKET_SIZE];
-                let bytes_read = read.read(&mut temp_buffer).await?;
-                if bytes_read == 0 {
-                    return Err(ClientError::ConnectionClosedByPeer);
-                }
-
-                buffer.extend_from_slice(&temp_buffer[..bytes_read]);
-                loop {
-                    let packet = match Packet::decode(&mut buffer) {
-                        Ok(packet) => packet,
-                        Err(err) => {
-                            println!("Failed to decode packet {:?}", err);
-                            break;
-                        }
-                    };
-                    let packet_type = match PacketId::from_u8(packet.packet_id) {
-                        Some(packet_type) => packet_type,
-                        None => return Err(ClientError::InvalidPacket),
-                    };
-
-                    match packet_type {
-                        PacketId::AudioPacket => {
-                            AudioPacketHandler::handle_packet(
-                                packet,
-                                audio_handler.get_codec(),
-                                chan_output_tx.clone(),
-                            )
-                            .await?;
-                        }
-                        _ => {
-                            println!("Unknown packet type: {:?}", packet_type);
-                        }
+            while let Some(packet) = reader.next().await {
+                let packet = match packet {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        println!("Closing connection to server: {}", e);
+                        return Err(e.into());
                     }
-                }
+                };
+                read_capture.record(Direction::Received, &packet);
+                let packet_type = match PacketId::from_u8(packet.packet_id) {
+                    Some(packet_type) => packet_type,
+                    None => return Err(DecodeError::UnknownPacketId(packet.packet_id).into()),
+                };
 
-                if buffer.len() > MAX_PACKET_SIZE * 2 {
-                    println!("Buffer length: {}", buffer.len());
-                    return Err(ClientError::BufferOverflow);
+                match handlers.handler(packet_type) {
+                    Some(handler) => handler.handle_packet(packet).await?,
+                    None => println!("Unhandled packet type: {:?}", packet_type),
                 }
             }
+
+            Err(ClientError::ConnectionClosedByPeer)
         });
 
+        let write_udp = udp.clone();
         let write_handle = tokio::spawn(async move {
             println!("Started writing to server");
             while let Some(packet) = message_receiver.recv().await {
-                write.write_all(&packet.encode()).await?;
-                write.flush().await?;
+                if packet.packet_id == PacketId::AudioPacket.to_u8() {
+                    if let Some(path) = write_udp.get() {
+                        match path.send(packet.clone()).await {
+                            Ok(true) => continue,
+                            Ok(false) => {}
+                            Err(e) => println!("Sending audio datagram failed: {}", e),
+                        }
+                    }
+                }
+                capture.record(Direction::Sent, &packet);
+                writer.send(packet).await?;
             }
             Ok(())
         });
 
+        let ping_heartbeat = heartbeat.clone();
+        let ping_sender = packet_sender.clone();
+        let heartbeat_handle = tokio::spawn(async move {
+            let interval = options.heartbeat.interval;
+            let mut interval = interval_at(Instant::now() + interval, interval);
+            loop {
+                interval.tick().await;
+
+                let ping = match ping_heartbeat.lock().await.ping() {
+                    Some(ping) => ping,
+                    None => return Err(ClientError::HeartbeatTimeout),
+                };
+                ping_sender.send(Packet::new_as(ping, format)?).await?;
+            }
+        });
+
+        let read_abort = read_handle.abort_handle();
+        let write_abort = write_handle.abort_handle();
+        let heartbeat_abort = heartbeat_handle.abort_handle();
+        let datagram_abort = datagram_handle.map(|handle| handle.abort_handle());
         tokio::spawn(async move {
-            select! {
+            let result = select! {
                 Ok(read_result) = read_handle => {
                     println!("Read result: {:?}", read_result);
                     read_result
@@ -101,8 +497,21 @@ impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> Client<A, D> for Tok
                 Ok(write_result) = write_handle => {
                     println!("Write result: {:?}", write_result);
                     write_result
+                },
+                Ok(heartbeat_result) = heartbeat_handle => {
+                    println!("Heartbeat result: {:?}", heartbeat_result);
+                    heartbeat_result
                 }
+            };
+
+            // Dropping both halves of the stream is what closes the connection.
+            read_abort.abort();
+            write_abort.abort();
+            heartbeat_abort.abort();
+            if let Some(abort) = datagram_abort {
+                abort.abort();
             }
+            result
         });
 
         Ok(Self {
@@ -111,47 +520,51 @@ impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> Client<A, D> for Tok
             stop_tx: None,
             packet_sender,
             chan_output_rx: Arc::new(chan_output_rx),
+            capabilities: response.capabilities,
+            format,
+            speakers,
+            roster,
+            speaking_tx,
+            chat_log,
+            chat_tx,
+            callout_tx,
+            user: Mutex::new(UserUpdatePacket {
+                summoner: options.summoner,
+                flags: 0,
+            }),
+            deafened: Arc::new(AtomicBool::new(false)),
+            heartbeat,
+            close_rx,
+            udp,
         })
     }
+}
+
+#[async_trait::async_trait]
+impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> Client<A, D> for TokioClient<A, D> {
+    async fn connect(addr: Cow<'_, str>) -> Result<Self, ClientError> {
+        Self::connect_with(addr, ConnectOptions::default()).await
+    }
 
     async fn run(&mut self) -> Result<(), ClientError> {
         let (stop_tx, stop_rx) = oneshot::channel::<()>();
         self.stop_tx = Some(stop_tx);
 
-        let (mic_tx, mic_rx) = mpsc::channel::<Vec<f32>>(20);
-        let (output_tx, output_rx) = std::sync::mpsc::channel::<Vec<f32>>();
-
-        self.device_handler.start_actives(mic_tx, output_rx).await?;
-
-        {
-            let input_device = match self.device_handler.get_active_device(DeviceType::Input) {
-                Some(device) => device,
-                None => return Err(ClientError::NoDevice),
-            };
-
-            self.audio_handler.get_codec().lock().await.update(
-                input_device.config().sample_rate().0,
-                input_device.config().channels() as usize,
-            )?;
-        }
-
-        let audio_handler = self.audio_handler.clone();
-        let packet_sender = self.packet_sender.clone();
-        let microphone_handle =
-            tokio::spawn(async move { audio_handler.start(mic_rx, packet_sender).await });
-
-        let chan_output_rx = self.chan_output_rx.clone();
-        let output_handle = tokio::spawn(async move {
-            let mut output_rx = chan_output_rx.resubscribe();
-            while let Ok(track) = output_rx.recv().await {
-                if let Err(_) = output_tx.send(track) {
-                    break;
-                }
-            }
-            Ok::<(), ClientError>(())
-        });
+        let (microphone_handle, output_handle) = start_audio(
+            &self.audio_handler,
+            &mut self.device_handler,
+            self.packet_sender.clone(),
+            &self.chan_output_rx,
+            self.deafened.clone(),
+            self.format,
+        )
+        .await?;
 
+        let mut close_rx = self.close_rx.clone();
         select! {
+            Ok(close) = close_rx.wait_for(Option::is_some) => {
+                Err(close.clone().map_or(ClientError::ConnectionClosedByPeer, ClientError::from))
+            }
             Ok(microphone_result) = microphone_handle => {
                 println!("Microphone result: {:?}", microphone_result);
                 Ok(())
@@ -200,34 +613,197 @@ impl<A: AudioHandler + 'static, D: DeviceHandler + 'static> Client<A, D> for Tok
     }
 }
 
+fn set_flag(flags: &mut u32, flag: u32, set: bool) {
+    if set {
+        *flags |= flag;
+    } else {
+        *flags &= !flag;
+    }
+}
+
 #[cfg(test)]
-mod tests {
-    use common::packet::{AudioPacket, Packet};
-    use tokio::{io::AsyncWriteExt, select};
+pub(crate) mod tests {
+    use common::{
+        capture::{CaptureReader, Direction, Side},
+        datagram::{DatagramCodec, MAX_DATAGRAM_SIZE},
+        heartbeat::HeartbeatConfig,
+        packet::{
+            codec::PacketCodec, format::WireFormat, fragment::FragmentCodec, ids::PacketId,
+            packet_type::PacketType, AudioPacket, Capabilities, ChatHistoryPacket, ChatPacket,
+            ClosePacket, CloseReason, ConnectPacket, ConnectResponsePacket, ConnectStatus, Packet,
+            PingPacket, PongPacket, RelayedAudioPacket, RelayedChatPacket, RelayedSpeakingPacket,
+            RosterPacket, RosterUser, Speaker, SpeakerMapPacket, Summoner, UdpOfferPacket,
+            UdpReadyPacket, UserUpdatePacket, MAX_PACKET_SIZE, PROTOCOL_VERSION,
+        },
+        quic::{PrivateKeyDer, QuicListener},
+        secure::{self, SecureCodec, SecureError, StaticKey},
+        tls::{Fingerprint, TlsListener, Trust},
+        transport::{
+            memory::{self, MemoryListener},
+            tcp::TcpListener,
+            Connector, Listener, PacketStream,
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        net::UdpSocket,
+        select,
+        sync::mpsc,
+    };
+    use tokio_util::codec::{FramedRead, FramedWrite};
+    use uuid::Uuid;
 
     use crate::{
         audio::{
             codec::opus::OpusAudioCodec, cpal::CpalAudioHandler, cpal_device::CpalDeviceHandler,
+            DeviceHandler, DeviceInfo, DeviceType,
+        },
+        client::{
+            udp::{PROBE_ATTEMPTS, PROBE_TIMEOUT},
+            Client, ConnectOptions, Encryption,
         },
-        client::Client,
         error::ClientError,
     };
 
     use super::TokioClient;
+    use bytes::BytesMut;
 
     pub type TokoClient = TokioClient<CpalAudioHandler<OpusAudioCodec>, CpalDeviceHandler>;
+    type HeadlessClient = TokioClient<CpalAudioHandler<OpusAudioCodec>, NoDevices>;
+
+    /// Device handler for tests that only exercise the connection.
+    pub(crate) struct NoDevices;
+
+    #[async_trait::async_trait]
+    impl DeviceHandler for NoDevices {
+        fn new() -> Result<Self, ClientError> {
+            Ok(Self)
+        }
+
+        fn get_devices(&self, _device_type: DeviceType) -> Vec<DeviceInfo> {
+            Vec::new()
+        }
+
+        fn get_active_device(&self, _device_type: DeviceType) -> Option<DeviceInfo> {
+            None
+        }
+
+        async fn start_actives(
+            &mut self,
+            _mic_tx: mpsc::Sender<Vec<f32>>,
+            _output_rx: std::sync::mpsc::Receiver<Vec<f32>>,
+        ) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        async fn set_active_device(
+            &mut self,
+            _device_type: &DeviceType,
+            _device_name: String,
+        ) -> Result<(), ClientError> {
+            Err(ClientError::NoDevice)
+        }
+
+        async fn stop(&mut self) -> Result<(), ClientError> {
+            Ok(())
+        }
+    }
+
+    async fn accept(listener: &mut MemoryListener) -> Box<dyn PacketStream> {
+        listener.accept().await.unwrap().await.unwrap().stream
+    }
+
+    async fn accept_handshake(
+        socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        supported: Capabilities,
+    ) {
+        let mut buffer = BytesMut::with_capacity(MAX_PACKET_SIZE);
+        socket.read_buf(&mut buffer).await.unwrap();
+
+        let packet = Packet::decode(&mut buffer).unwrap();
+        let request = ConnectPacket::decode(&packet.data).unwrap();
+        let response = ConnectResponsePacket::negotiate(&request, &supported);
+
+        socket
+            .write_all(&Packet::new(response).unwrap().encode())
+            .await
+            .unwrap();
+        socket.flush().await.unwrap();
+    }
+
+    fn udp_capabilities() -> Capabilities {
+        Capabilities {
+            transports: Capabilities::TRANSPORT_TCP | Capabilities::TRANSPORT_UDP,
+            ..Capabilities::default()
+        }
+    }
+
+    /// Offers the client a datagram path to a fresh socket.
+    async fn offer_udp(socket: &mut (impl AsyncWrite + Unpin)) -> (UdpSocket, DatagramCodec) {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let offer = UdpOfferPacket {
+            port: udp.local_addr().unwrap().port(),
+            token: Uuid::from_u128(7),
+        };
+        socket
+            .write_all(
+                &Packet::new_as(offer, WireFormat::MessagePack)
+                    .unwrap()
+                    .encode(),
+            )
+            .await
+            .unwrap();
+
+        let codec = DatagramCodec::new(offer.token, SecureCodec::plaintext(PacketCodec));
+        (udp, codec)
+    }
+
+    async fn read_packet(
+        socket: &mut (impl AsyncRead + Unpin),
+        buffer: &mut BytesMut,
+    ) -> Option<Packet> {
+        loop {
+            if let Ok(length) = Packet::frame_length(buffer) {
+                if buffer.len() >= length {
+                    return Packet::decode(buffer).ok();
+                }
+            }
+            if socket.read_buf(buffer).await.ok()? == 0 {
+                return None;
+            }
+        }
+    }
+
+    fn heartbeat_options(max_missed: u32) -> ConnectOptions {
+        ConnectOptions {
+            heartbeat: HeartbeatConfig {
+                interval: Duration::from_millis(10),
+                max_missed,
+            },
+            ..Default::default()
+        }
+    }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
     async fn test_tokio_client_connect() -> Result<(), ClientError> {
-        let addr = "127.0.0.1:8111";
+        let (mut listener, connector) = memory::channel();
 
         let server = tokio::spawn(async move {
-            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
 
-            let packet = Packet::new(AudioPacket {
-                track: vec![0; 960],
-            })
+            let packet = Packet::new_as(
+                RelayedAudioPacket {
+                    audio: AudioPacket {
+                        track: vec![0; 960],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                WireFormat::MessagePack,
+            )
             .unwrap()
             .encode();
 
@@ -239,7 +815,12 @@ mod tests {
             Ok::<(), ClientError>(())
         });
         let client = tokio::spawn(async move {
-            let mut client = TokoClient::connect(addr.into()).await.unwrap();
+            let mut client = TokoClient::connect_over(
+                connector.connect().await.unwrap(),
+                ConnectOptions::default(),
+            )
+            .await
+            .unwrap();
             client.run().await
         });
         select! {
@@ -256,11 +837,11 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
     async fn test_tokio_client_connect_fail_buffer_overflow() -> Result<(), ClientError> {
-        let addr = "127.0.0.1:8112";
+        let (mut listener, connector) = memory::channel();
 
         let server = tokio::spawn(async move {
-            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
 
             let packet = [1; 4 * 1024];
 
@@ -271,7 +852,12 @@ mod tests {
             Ok::<(), ClientError>(())
         });
         let client = tokio::spawn(async move {
-            let mut client = match TokoClient::connect(addr.into()).await {
+            let mut client = match TokoClient::connect_over(
+                connector.connect().await.unwrap(),
+                ConnectOptions::default(),
+            )
+            .await
+            {
                 Ok(client) => client,
                 Err(e) => {
                     return Err(e);
@@ -291,4 +877,874 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_tokio_client_connect_rejected() {
+        let (mut listener, connector) = memory::channel();
+
+        tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            let mut buffer = vec![0; MAX_PACKET_SIZE];
+            let _ = socket.read(&mut buffer).await.unwrap();
+
+            let response = ConnectResponsePacket::negotiate(
+                &ConnectPacket {
+                    protocol_version: PROTOCOL_VERSION - 1,
+                    ..Default::default()
+                },
+                &Capabilities::default(),
+            );
+            socket
+                .write_all(&Packet::new(response).unwrap().encode())
+                .await
+                .unwrap();
+        });
+
+        let result = TokoClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await;
+        assert!(
+            matches!(
+                result,
+                Err(ClientError::ConnectionRejected(
+                    ConnectStatus::UnsupportedVersion
+                ))
+            ),
+            "expected client to refuse a rejected handshake"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_connect_too_old_server() {
+        let (mut listener, connector) = memory::channel();
+
+        tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            let mut buffer = vec![0; MAX_PACKET_SIZE];
+            let _ = socket.read(&mut buffer).await.unwrap();
+
+            let response = ConnectResponsePacket {
+                status: ConnectStatus::Accepted,
+                protocol_version: PROTOCOL_VERSION - 1,
+                capabilities: Capabilities::default(),
+            };
+            socket
+                .write_all(&Packet::new(response).unwrap().encode())
+                .await
+                .unwrap();
+        });
+
+        let result = TokoClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await;
+        assert!(
+            matches!(
+                result,
+                Err(ClientError::ConnectionRejected(
+                    ConnectStatus::UnsupportedVersion
+                ))
+            ),
+            "expected client to refuse a server speaking an older version"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_connect_unexpected_handshake_packet() {
+        let (mut listener, connector) = memory::channel();
+
+        tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            let packet = Packet::new(AudioPacket {
+                track: vec![1],
+                ..Default::default()
+            })
+            .unwrap()
+            .encode();
+            socket.write_all(&packet).await.unwrap();
+        });
+
+        let result = TokoClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await;
+        assert!(
+            matches!(result, Err(ClientError::UnexpectedHandshakePacket(2))),
+            "expected client to refuse a non-handshake reply"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_heartbeat() {
+        let (mut listener, connector) = memory::channel();
+
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+            socket
+                .write_all(
+                    &Packet::new_as(PingPacket { timestamp: 9 }, WireFormat::MessagePack)
+                        .unwrap()
+                        .encode(),
+                )
+                .await
+                .unwrap();
+
+            let mut buffer = BytesMut::new();
+            let mut pong = None;
+            let mut answered = false;
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                match PacketId::from_u8(packet.packet_id) {
+                    Some(PacketId::PingPacket) => {
+                        answered = true;
+                        let ping =
+                            PingPacket::decode_as(&packet.data, WireFormat::MessagePack).unwrap();
+                        let pong = Packet::new_as(PongPacket::from(ping), WireFormat::MessagePack);
+                        socket.write_all(&pong.unwrap().encode()).await.unwrap();
+                    }
+                    Some(PacketId::PongPacket) => {
+                        pong = Some(
+                            PongPacket::decode_as(&packet.data, WireFormat::MessagePack).unwrap(),
+                        );
+                    }
+                    _ => {}
+                }
+                if pong.is_some() && answered {
+                    break;
+                }
+            }
+            (socket, pong)
+        });
+
+        let client =
+            HeadlessClient::connect_over(connector.connect().await.unwrap(), heartbeat_options(10))
+                .await
+                .unwrap();
+        let (_socket, pong) = server.await.unwrap();
+        assert_eq!(pong, Some(PongPacket { timestamp: 9 }));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(
+            client.rtt().await.is_some(),
+            "expected client to measure round-trip time"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_heartbeat_timeout() {
+        let (mut listener, connector) = memory::channel();
+
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+
+            let mut buffer = BytesMut::new();
+            let mut pings = 0;
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                pings += (packet.packet_id == PacketId::PingPacket.to_u8()) as usize;
+            }
+            pings
+        });
+
+        let _client =
+            HeadlessClient::connect_over(connector.connect().await.unwrap(), heartbeat_options(2))
+                .await
+                .unwrap();
+        assert_eq!(
+            server.await.unwrap(),
+            2,
+            "expected client to hang up after two unanswered pings"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_close_reason() {
+        let (mut listener, connector) = memory::channel();
+
+        let (kick_tx, kick_rx) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+            kick_rx.await.unwrap();
+
+            let close = ClosePacket::new(CloseReason::Kicked, "behave");
+            socket
+                .write_all(
+                    &Packet::new_as(close, WireFormat::MessagePack)
+                        .unwrap()
+                        .encode(),
+                )
+                .await
+                .unwrap();
+        });
+
+        let client = HeadlessClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.close_reason(), None);
+
+        kick_tx.send(()).unwrap();
+        let mut close_rx = client.close_rx.clone();
+        close_rx.wait_for(Option::is_some).await.unwrap();
+        assert_eq!(
+            client.close_reason(),
+            Some(ClosePacket::new(CloseReason::Kicked, "behave"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_closed_during_handshake() {
+        let (mut listener, connector) = memory::channel();
+
+        tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            let close = ClosePacket::new(CloseReason::ServerShutdown, "maintenance");
+            socket
+                .write_all(&Packet::new(close).unwrap().encode())
+                .await
+                .unwrap();
+        });
+
+        let result = HeadlessClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await;
+        assert!(
+            matches!(
+                result,
+                Err(ClientError::ConnectionClosed(CloseReason::ServerShutdown, ref message))
+                    if message == "maintenance"
+            ),
+            "expected client to surface the close reason"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_negotiates_format() {
+        let (mut listener, connector) = memory::channel();
+
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            let supported = Capabilities {
+                formats: WireFormat::Bincode.bit(),
+                ..Default::default()
+            };
+            accept_handshake(&mut socket, supported).await;
+            socket
+                .write_all(&Packet::new(PingPacket { timestamp: 3 }).unwrap().encode())
+                .await
+                .unwrap();
+
+            let mut buffer = BytesMut::new();
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                if packet.packet_id == PacketId::PongPacket.to_u8() {
+                    return PongPacket::decode(&packet.data).ok();
+                }
+            }
+            None
+        });
+
+        let client = HeadlessClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.format(), WireFormat::Bincode);
+        assert_eq!(
+            server.await.unwrap(),
+            Some(PongPacket { timestamp: 3 }),
+            "expected client to answer in the negotiated format"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_capture() {
+        let (mut listener, connector) = memory::channel();
+        let path = std::env::temp_dir().join(format!("client-capture-{}.lvcp", std::process::id()));
+
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+            socket
+        });
+
+        let options = ConnectOptions {
+            capture: Some(path.clone()),
+            ..Default::default()
+        };
+        let _client = HeadlessClient::connect_over(connector.connect().await.unwrap(), options)
+            .await
+            .unwrap();
+        let _socket = server.await.unwrap();
+
+        let expected = vec![
+            (Direction::Sent, PacketId::ConnectPacket.to_u8()),
+            (Direction::Received, PacketId::ConnectResponsePacket.to_u8()),
+        ];
+        // The connection is still open, so wait for the periodic flush.
+        let records = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let reader = CaptureReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+                assert_eq!(reader.side(), Side::Client);
+                let records: Vec<(Direction, u8)> = reader
+                    .map_while(Result::ok)
+                    .map(|record| (record.direction, record.packet.packet_id))
+                    .collect();
+                if records.len() >= expected.len() {
+                    return records;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(records, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_roster() {
+        let (mut listener, connector) = memory::channel();
+        let format = WireFormat::MessagePack;
+        let local = RosterUser {
+            client_id: Uuid::from_u128(1),
+            display_name: "Teemo".to_string(),
+            ..Default::default()
+        };
+
+        let user = local.clone();
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+            let snapshot = RosterPacket::Snapshot(vec![user.clone()]);
+            socket
+                .write_all(&Packet::new_as(snapshot, format).unwrap().encode())
+                .await
+                .unwrap();
+
+            let mut buffer = BytesMut::new();
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                if packet.packet_id != PacketId::UserUpdatePacket.to_u8() {
+                    continue;
+                }
+                let update = UserUpdatePacket::decode_as(&packet.data, format).unwrap();
+                let updated = RosterPacket::UserUpdated(RosterUser {
+                    summoner: update.summoner,
+                    flags: update.flags,
+                    ..user
+                });
+                socket
+                    .write_all(&Packet::new_as(updated, format).unwrap().encode())
+                    .await
+                    .unwrap();
+                return socket;
+            }
+            panic!("expected the client to send its update");
+        });
+
+        let client = HeadlessClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await
+        .unwrap();
+        let summoner = Summoner {
+            riot_id: "Teemo#EUW".to_string(),
+            champion: Some("Teemo".to_string()),
+        };
+        client.set_summoner(Some(summoner.clone())).await.unwrap();
+        let _socket = server.await.unwrap();
+
+        let expected = RosterUser {
+            summoner: Some(summoner),
+            ..local
+        };
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while client.roster().await != vec![expected.clone()] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("expected the roster to reflect the update");
+        assert_eq!(client.user(expected.client_id).await, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_speaking_events() {
+        let (mut listener, connector) = memory::channel();
+        let format = WireFormat::MessagePack;
+
+        let (ready_tx, ready_rx) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+            ready_rx.await.unwrap();
+
+            let speakers = SpeakerMapPacket {
+                added: vec![Speaker {
+                    id: 3,
+                    client_id: Uuid::from_u128(3),
+                    display_name: "Teemo".to_string(),
+                }],
+                removed: Vec::new(),
+            };
+            let speaking = RelayedSpeakingPacket {
+                speaker: 3,
+                speaking: true,
+            };
+            for packet in [
+                Packet::new_as(speakers, format).unwrap(),
+                Packet::new_as(speaking, format).unwrap(),
+            ] {
+                socket.write_all(&packet.encode()).await.unwrap();
+            }
+            socket
+        });
+
+        let client = HeadlessClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await
+        .unwrap();
+        let mut events = client.speaking_events();
+        ready_tx.send(()).unwrap();
+        let _socket = server.await.unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .expect("expected a speaking event")
+            .unwrap();
+        assert_eq!(event.client_id, Uuid::from_u128(3));
+        assert!(event.speaking);
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_mute_and_deafen() {
+        let (mut listener, connector) = memory::channel();
+        let format = WireFormat::MessagePack;
+
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+
+            let mut updates = Vec::new();
+            let mut buffer = BytesMut::new();
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                if packet.packet_id == PacketId::UserUpdatePacket.to_u8() {
+                    let update = UserUpdatePacket::decode_as(&packet.data, format).unwrap();
+                    updates.push(update.flags);
+                    if updates.len() == 3 {
+                        break;
+                    }
+                }
+            }
+            updates
+        });
+
+        let client = HeadlessClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await
+        .unwrap();
+        client.set_muted(true).await.unwrap();
+        client.set_deafened(true).await.unwrap();
+        client.set_muted(false).await.unwrap();
+
+        assert!(!client.is_muted().await);
+        assert!(client.is_deafened().await);
+        assert_eq!(
+            server.await.unwrap(),
+            vec![
+                RosterUser::MUTED,
+                RosterUser::MUTED | RosterUser::DEAFENED,
+                RosterUser::DEAFENED
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_chat() {
+        let (mut listener, connector) = memory::channel();
+        let format = WireFormat::MessagePack;
+        let earlier = RelayedChatPacket {
+            sender: Uuid::from_u128(3),
+            display_name: "Teemo".to_string(),
+            sent_at: 1,
+            text: "gl hf".to_string(),
+        };
+
+        let history = ChatHistoryPacket {
+            messages: vec![earlier.clone()],
+        };
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&mut listener).await;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+            socket
+                .write_all(&Packet::new_as(history, format).unwrap().encode())
+                .await
+                .unwrap();
+
+            let mut buffer = BytesMut::new();
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                if packet.packet_id != PacketId::ChatPacket.to_u8() {
+                    continue;
+                }
+                let chat = ChatPacket::decode_as(&packet.data, format).unwrap();
+                let relayed = RelayedChatPacket {
+                    sender: Uuid::from_u128(1),
+                    display_name: "Annie".to_string(),
+                    sent_at: 2,
+                    text: chat.text,
+                };
+                socket
+                    .write_all(&Packet::new_as(relayed, format).unwrap().encode())
+                    .await
+                    .unwrap();
+                return socket;
+            }
+            panic!("expected the client to send its message");
+        });
+
+        let client = HeadlessClient::connect_over(
+            connector.connect().await.unwrap(),
+            ConnectOptions::default(),
+        )
+        .await
+        .unwrap();
+        let mut messages = client.chat_messages();
+        assert!(matches!(
+            client
+                .send_chat("a".repeat(ChatPacket::MAX_LENGTH + 1))
+                .await,
+            Err(ClientError::ChatMessageTooLong)
+        ));
+        client.send_chat("gg").await.unwrap();
+        let _socket = server.await.unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(1), messages.recv())
+            .await
+            .expect("expected the relayed message")
+            .unwrap();
+        assert_eq!(message.text, "gg");
+        assert_eq!(client.chat_history().await, vec![earlier, message]);
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_encryption() {
+        let (mut listener, connector) = memory::channel();
+        let key = StaticKey::generate().unwrap();
+        let pinned = *key.public();
+
+        let server = tokio::spawn(async move {
+            // The first client pins another key and gives up after the handshake.
+            for _ in 0..2 {
+                let mut socket = accept(&mut listener).await;
+                let session = secure::respond(&mut socket, &key).await.unwrap();
+                let (read, write) = tokio::io::split(socket);
+                let mut reader = FramedRead::new(read, session.codec(PacketCodec));
+                let mut writer = FramedWrite::new(write, session.codec(PacketCodec));
+
+                let Some(Ok(packet)) = reader.next().await else {
+                    continue;
+                };
+                let request = ConnectPacket::decode(&packet.data).unwrap();
+                let response = ConnectResponsePacket::negotiate(&request, &Capabilities::default());
+                writer.send(Packet::new(response).unwrap()).await.unwrap();
+                return (reader, writer);
+            }
+            panic!("expected the pinned client to connect");
+        });
+
+        let other = *StaticKey::generate().unwrap().public();
+        let options = |key| ConnectOptions {
+            encryption: Encryption::Pinned(key),
+            ..Default::default()
+        };
+        assert!(matches!(
+            HeadlessClient::connect_over(connector.connect().await.unwrap(), options(other)).await,
+            Err(ClientError::SecureHandshake(SecureError::KeyMismatch))
+        ));
+        let _client =
+            HeadlessClient::connect_over(connector.connect().await.unwrap(), options(pinned))
+                .await
+                .unwrap();
+        let _connection = server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_udp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let format = WireFormat::MessagePack;
+        let audio = AudioPacket {
+            sequence: 1,
+            track: vec![1],
+            ..Default::default()
+        };
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut socket, udp_capabilities()).await;
+            let (udp, mut codec) = offer_udp(&mut socket).await;
+
+            let mut buffer = [0; MAX_DATAGRAM_SIZE];
+            let (length, from) = udp.recv_from(&mut buffer).await.unwrap();
+            let probe = codec.decode(&buffer[..length]).unwrap();
+            assert_eq!(probe.packet_id, PacketId::UdpProbePacket.to_u8());
+            udp.send_to(&codec.encode(probe).unwrap(), from)
+                .await
+                .unwrap();
+
+            loop {
+                let (length, _) = udp.recv_from(&mut buffer).await.unwrap();
+                let packet = codec.decode(&buffer[..length]).unwrap();
+                if packet.packet_id == PacketId::AudioPacket.to_u8() {
+                    return (
+                        socket,
+                        AudioPacket::decode_as(&packet.data, format).unwrap(),
+                    );
+                }
+            }
+        });
+
+        let options = ConnectOptions {
+            udp: true,
+            ..Default::default()
+        };
+        let client = HeadlessClient::connect_with(addr.into(), options)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !client.is_audio_over_udp() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("expected audio to move to UDP");
+
+        client
+            .packet_sender
+            .send(Packet::new_as(audio.clone(), format).unwrap())
+            .await
+            .unwrap();
+        let (_socket, received) = server.await.unwrap();
+        assert_eq!(received, audio);
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_udp_fallback_once_datagrams_stop() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let format = WireFormat::MessagePack;
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut socket, udp_capabilities()).await;
+            let (udp, mut codec) = offer_udp(&mut socket).await;
+
+            // Only the first probe is answered, as if the path broke after.
+            let mut buffer = [0; MAX_DATAGRAM_SIZE];
+            let (length, from) = udp.recv_from(&mut buffer).await.unwrap();
+            let probe = codec.decode(&buffer[..length]).unwrap();
+            udp.send_to(&codec.encode(probe).unwrap(), from)
+                .await
+                .unwrap();
+
+            let mut updates = Vec::new();
+            let mut buffer = BytesMut::new();
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                if packet.packet_id == PacketId::PingPacket.to_u8() {
+                    let ping = PingPacket::decode_as(&packet.data, format).unwrap();
+                    let pong = Packet::new_as(PongPacket::from(ping), format).unwrap();
+                    socket.write_all(&pong.encode()).await.unwrap();
+                } else if packet.packet_id == PacketId::UdpReadyPacket.to_u8() {
+                    let update = UdpReadyPacket::decode_as(&packet.data, format).unwrap();
+                    updates.push(update.ready);
+                    if !update.ready {
+                        return (socket, updates);
+                    }
+                }
+            }
+            panic!("expected the client to fall back");
+        });
+
+        let options = ConnectOptions {
+            udp: true,
+            heartbeat: HeartbeatConfig {
+                interval: Duration::from_millis(50),
+                max_missed: 4,
+            },
+            ..Default::default()
+        };
+        let client = HeadlessClient::connect_with(addr.into(), options)
+            .await
+            .unwrap();
+        let (_socket, updates) = server.await.unwrap();
+        assert_eq!(updates, vec![true, false]);
+        assert!(!client.is_audio_over_udp());
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_udp_fallback() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let format = WireFormat::MessagePack;
+
+        let (probed_tx, probed_rx) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut socket, udp_capabilities()).await;
+            // Swallows every probe, as a firewall would.
+            let (udp, _) = offer_udp(&mut socket).await;
+            let mut buffer = [0; MAX_DATAGRAM_SIZE];
+            for _ in 0..PROBE_ATTEMPTS {
+                udp.recv_from(&mut buffer).await.unwrap();
+            }
+            probed_tx.send(()).unwrap();
+
+            let mut buffer = BytesMut::new();
+            while let Some(packet) = read_packet(&mut socket, &mut buffer).await {
+                if packet.packet_id == PacketId::AudioPacket.to_u8() {
+                    return socket;
+                }
+            }
+            panic!("expected audio over TCP");
+        });
+
+        let options = ConnectOptions {
+            udp: true,
+            ..Default::default()
+        };
+        let client = HeadlessClient::connect_with(addr.into(), options)
+            .await
+            .unwrap();
+        probed_rx.await.unwrap();
+        tokio::time::sleep(PROBE_TIMEOUT).await;
+        assert!(!client.is_audio_over_udp());
+
+        let audio = AudioPacket {
+            track: vec![1],
+            ..Default::default()
+        };
+        client
+            .packet_sender
+            .send(Packet::new_as(audio, format).unwrap())
+            .await
+            .unwrap();
+        let _socket = server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_quic() {
+        let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let root = certified.cert.der().clone();
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+        let mut listener = QuicListener::bind("127.0.0.1:0", vec![root.clone()], key)
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let format = WireFormat::MessagePack;
+        let audio = AudioPacket {
+            sequence: 1,
+            track: vec![1],
+            ..Default::default()
+        };
+
+        let server = tokio::spawn(async move {
+            let connection = listener.accept().await.unwrap().await.unwrap();
+            let (recv, send) = tokio::io::split(connection.stream);
+            let connection = connection.quic.unwrap();
+            let mut reader = FramedRead::new(recv, FragmentCodec::default());
+            let mut writer = FramedWrite::new(send, FragmentCodec::default());
+
+            let packet = reader.next().await.unwrap().unwrap();
+            let request = ConnectPacket::decode(&packet.data).unwrap();
+            // Datagrams of the connection make the offer of UDP pointless.
+            assert!(!request
+                .capabilities
+                .has_transport(Capabilities::TRANSPORT_UDP));
+            let response = ConnectResponsePacket::negotiate(&request, &udp_capabilities());
+            writer.send(Packet::new(response).unwrap()).await.unwrap();
+
+            let datagram = connection.read_datagram().await.unwrap();
+            let packet = Packet::decode(&mut BytesMut::from(&datagram[..])).unwrap();
+            assert_eq!(packet.packet_id, PacketId::AudioPacket.to_u8());
+            (
+                connection,
+                AudioPacket::decode_as(&packet.data, format).unwrap(),
+            )
+        });
+
+        let options = ConnectOptions {
+            udp: true,
+            quic_roots: vec![root],
+            ..Default::default()
+        };
+        let client = HeadlessClient::connect_with(format!("quic://{}", addr).into(), options)
+            .await
+            .unwrap();
+        assert!(client.is_audio_over_udp());
+
+        client
+            .packet_sender
+            .send(Packet::new_as(audio.clone(), format).unwrap())
+            .await
+            .unwrap();
+        let (_connection, received) = server.await.unwrap();
+        assert_eq!(received, audio);
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_tls_pinned() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+        let pinned = Trust::Pinned(Fingerprint::of(&cert));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut listener = TlsListener::new(listener, vec![cert], key).unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut socket = listener.accept().await.unwrap().await.unwrap().stream;
+            accept_handshake(&mut socket, Capabilities::default()).await;
+            // The next client refuses the certificate.
+            let refused = listener.accept().await.unwrap().await;
+            (socket, refused.is_err())
+        });
+
+        let options = ConnectOptions {
+            tls: pinned,
+            ..Default::default()
+        };
+        let addr = format!("tls://{}", addr);
+        let client = HeadlessClient::connect_with(addr.clone().into(), options).await;
+        assert!(client.is_ok());
+
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let options = ConnectOptions {
+            tls: Trust::Pinned(Fingerprint::of(other.cert.der())),
+            ..Default::default()
+        };
+        let result = HeadlessClient::connect_with(addr.into(), options).await;
+        assert!(matches!(result, Err(ClientError::IoError(_))));
+        let (_socket, refused) = server.await.unwrap();
+        assert!(refused);
+    }
+
+    #[tokio::test]
+    async fn test_tokio_client_unsupported_scheme() {
+        let result = HeadlessClient::connect("smtp://127.0.0.1:25".into()).await;
+        assert!(matches!(result, Err(ClientError::UnsupportedScheme(_))));
+    }
 }
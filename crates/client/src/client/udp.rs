@@ -0,0 +1,190 @@
+use crate::{error::ClientError, handlers::PacketHandlers};
+use common::{
+    capture::Capture,
+    datagram::{DatagramCodec, DatagramLink, DatagramPath, MAX_DATAGRAM_SIZE},
+    heartbeat::HeartbeatConfig,
+    packet::{
+        codec::PacketCodec, format::WireFormat, ids::PacketId, Packet, UdpOfferPacket,
+        UdpProbePacket, UdpReadyPacket,
+    },
+    secure::{SecureCodec, Session},
+};
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    select,
+    sync::mpsc,
+    time::{interval_at, timeout, Instant},
+};
+
+/// Probes sent before deciding datagrams do not get through.
+pub const PROBE_ATTEMPTS: u8 = 5;
+
+/// How long to wait for each probe to be echoed.
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Datagram path to the server, while it is known to work.
+#[derive(Default)]
+pub struct UdpPath(Mutex<Option<DatagramPath>>);
+
+impl UdpPath {
+    pub fn get(&self) -> Option<DatagramPath> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set(&self, path: Option<DatagramPath>) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = path;
+    }
+}
+
+/// The connection a datagram path is taken up next to.
+pub(crate) struct UdpContext {
+    /// Address the connection goes to, datagrams go to the same host.
+    pub server: SocketAddr,
+    pub session: Option<Session>,
+    pub capture: Capture,
+    pub format: WireFormat,
+    /// Probes go out every interval, and datagrams are given up on once as
+    /// many as the stream's pings go without any arriving.
+    pub heartbeat: HeartbeatConfig,
+    /// Queue of the stream, to tell the server where audio goes.
+    pub stream: mpsc::Sender<Packet>,
+}
+
+/// Takes up the server's offer of a datagram path, see [`common::datagram`].
+///
+/// Audio moves to `path` once a probe made it to the server and back, and
+/// received audio is passed on to `handlers`. Probing goes on from there,
+/// and audio moves back to TCP once nothing arrived for a while. Either way,
+/// the server is told with an [`UdpReadyPacket`]. If no probe makes it, or
+/// datagrams stop arriving, audio stays on TCP for the rest of the connection.
+pub(crate) async fn run(
+    mut offers: mpsc::Receiver<UdpOfferPacket>,
+    context: UdpContext,
+    path: Arc<UdpPath>,
+    handlers: Arc<PacketHandlers>,
+) -> Result<(), ClientError> {
+    let Some(offer) = offers.recv().await else {
+        return Ok(());
+    };
+    let UdpContext {
+        server,
+        session,
+        capture,
+        format,
+        heartbeat,
+        stream,
+    } = context;
+
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = Arc::new(UdpSocket::bind(local).await?);
+    let codec = match &session {
+        Some(session) => session.datagram_codec(PacketCodec),
+        None => SecureCodec::plaintext(PacketCodec),
+    };
+    let link = Arc::new(DatagramLink::new(
+        socket.clone(),
+        DatagramCodec::new(offer.token, codec),
+        Some(SocketAddr::new(server.ip(), offer.port)),
+        capture,
+    ));
+
+    let mut buffer = [0; MAX_DATAGRAM_SIZE];
+    if !probe(&socket, &link, format, &mut buffer).await? {
+        println!("No answer over UDP, audio stays on TCP");
+        return Ok(());
+    }
+    println!("Sending audio over UDP");
+    path.set(Some(DatagramPath::Udp(link.clone())));
+    stream
+        .send(Packet::new_as(UdpReadyPacket { ready: true }, format)?)
+        .await?;
+
+    let silence = heartbeat.interval * heartbeat.max_missed;
+    let mut last_received = Instant::now();
+    let mut keepalive = interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+    let mut attempt = PROBE_ATTEMPTS;
+    loop {
+        select! {
+            packet = receive(&socket, &link, &mut buffer) => {
+                // Echoed probes count as well, so a silent room is no silence.
+                last_received = Instant::now();
+                handle_audio(&handlers, packet?).await;
+            }
+            _ = keepalive.tick() => {
+                if last_received.elapsed() >= silence {
+                    break;
+                }
+                attempt = attempt.wrapping_add(1);
+                link.send(Packet::new_as(UdpProbePacket { attempt }, format)?)
+                    .await?;
+            }
+        }
+    }
+
+    println!(
+        "Nothing arrived over UDP for {:?}, audio goes back to TCP",
+        silence
+    );
+    path.set(None);
+    stream
+        .send(Packet::new_as(UdpReadyPacket { ready: false }, format)?)
+        .await?;
+    Ok(())
+}
+
+/// Passes relayed audio that arrived as a datagram on to its handler.
+pub(crate) async fn handle_audio(handlers: &PacketHandlers, packet: Packet) {
+    // Anything else the server sends over the stream.
+    if packet.packet_id != PacketId::RelayedAudioPacket.to_u8() {
+        return;
+    }
+    if let Some(handler) = handlers.handler(PacketId::RelayedAudioPacket) {
+        if let Err(e) = handler.handle_packet(packet).await {
+            println!("Dropping audio datagram: {}", e);
+        }
+    }
+}
+
+/// Whether the server echoed one of a few probes.
+async fn probe(
+    socket: &UdpSocket,
+    link: &DatagramLink,
+    format: WireFormat,
+    buffer: &mut [u8],
+) -> Result<bool, ClientError> {
+    for attempt in 0..PROBE_ATTEMPTS {
+        link.send(Packet::new_as(UdpProbePacket { attempt }, format)?)
+            .await?;
+        // Echoes of earlier attempts count as well.
+        if let Ok(echo) = timeout(PROBE_TIMEOUT, receive(socket, link, buffer)).await {
+            if echo?.packet_id == PacketId::UdpProbePacket.to_u8() {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Waits for the next datagram from the server that opens.
+async fn receive(socket: &UdpSocket, link: &DatagramLink, buffer: &mut [u8]) -> io::Result<Packet> {
+    loop {
+        let (length, from) = socket.recv_from(buffer).await?;
+        if Some(from) != link.peer() {
+            continue;
+        }
+        match link.receive(&buffer[..length], from) {
+            Ok(packet) => return Ok(packet),
+            Err(e) => println!("Dropping datagram from server: {}", e),
+        }
+    }
+}
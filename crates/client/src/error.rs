@@ -1,4 +1,11 @@
-use common::packet::{error::DecodeError, Packet};
+use common::{
+    packet::{
+        codec::CodecError, error::DecodeError, format::FormatError, CalloutPacket, ChatPacket,
+        ClosePacket, CloseReason, ConnectStatus, Packet,
+    },
+    secure::SecureError,
+    tls::TlsError,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -33,9 +40,6 @@ pub enum ClientError {
     #[error("failed within opus: {0}")]
     OpusError(#[from] opus::Error),
 
-    #[error("buffer overflow: possible protocol error")]
-    BufferOverflow,
-
     #[error("connection closed by peer")]
     ConnectionClosedByPeer,
 
@@ -45,6 +49,9 @@ pub enum ClientError {
     #[error("failed on bincode: {0}")]
     BincodeError(#[from] Box<bincode::ErrorKind>),
 
+    #[error("failed on packet format: {0}")]
+    PacketFormatError(#[from] FormatError),
+
     #[error("failed on std mpsc send f32: {0}")]
     StdSendErrorF32(#[from] std::sync::mpsc::SendError<Vec<f32>>),
 
@@ -75,6 +82,54 @@ pub enum ClientError {
     #[error("poisoned lock")]
     PoisonedLock,
 
-    #[error("failed to decode: {0}")]
+    #[error("{0}")]
     DecodeError(#[from] DecodeError),
+
+    #[error("invalid mesh invite: {0}")]
+    InvalidInvite(String),
+
+    #[error("unsupported address scheme: {0}")]
+    UnsupportedScheme(String),
+
+    #[error("secure handshake failed: {0}")]
+    SecureHandshake(#[from] SecureError),
+
+    #[error("tls error: {0}")]
+    Tls(#[from] TlsError),
+
+    #[error("server rejected connection: {0}")]
+    ConnectionRejected(ConnectStatus),
+
+    #[error("unexpected packet id {0} during handshake")]
+    UnexpectedHandshakePacket(u8),
+
+    #[error("server missed too many heartbeats")]
+    HeartbeatTimeout,
+
+    #[error("server closed the connection ({0}): {1}")]
+    ConnectionClosed(CloseReason, String),
+
+    #[error("chat message is longer than {} characters", ChatPacket::MAX_LENGTH)]
+    ChatMessageTooLong,
+
+    #[error(
+        "callout target is longer than {} characters",
+        CalloutPacket::MAX_TARGET_LENGTH
+    )]
+    CalloutTargetTooLong,
+}
+
+impl From<CodecError> for ClientError {
+    fn from(error: CodecError) -> Self {
+        match error {
+            CodecError::Io(e) => ClientError::IoError(e),
+            CodecError::Decode(e) => ClientError::DecodeError(e),
+        }
+    }
+}
+
+impl From<ClosePacket> for ClientError {
+    fn from(close: ClosePacket) -> Self {
+        ClientError::ConnectionClosed(close.reason, close.message)
+    }
 }
@@ -1,23 +1,61 @@
-use std::sync::Arc;
+use std::collections::{hash_map::Entry, HashMap};
 
-use common::packet::{packet_type::PacketType, AudioPacket, Packet};
-use tokio::sync::Mutex;
+use common::packet::{
+    error::DecodeError, format::WireFormat, packet_type::PacketType, Packet, RelayedAudioPacket,
+    SpeakerId,
+};
+use tokio::sync::{broadcast, Mutex};
 
+use super::PacketHandler;
 use crate::{audio::codec::AudioCodec, error::ClientError};
 
-pub struct AudioPacketHandler {}
+/// Decoded audio along with who said it and the stream position it was captured at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioFrame {
+    pub speaker: SpeakerId,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub samples: Vec<f32>,
+}
+
+/// Decodes relayed audio, keeping one decoder per speaker so that
+/// interleaved streams do not corrupt each other's codec state.
+pub struct AudioPacketHandler<A: AudioCodec> {
+    decoders: Mutex<HashMap<SpeakerId, A>>,
+    audio_output_tx: broadcast::Sender<AudioFrame>,
+    format: WireFormat,
+}
+
+impl<A: AudioCodec> AudioPacketHandler<A> {
+    pub fn new(audio_output_tx: broadcast::Sender<AudioFrame>, format: WireFormat) -> Self {
+        Self {
+            decoders: Mutex::new(HashMap::new()),
+            audio_output_tx,
+            format,
+        }
+    }
 
-impl AudioPacketHandler {
-    pub async fn handle_packet<A: AudioCodec>(
-        packet: Packet,
-        codec: Arc<Mutex<A>>,
-        audio_output_tx: tokio::sync::broadcast::Sender<Vec<f32>>,
-    ) -> Result<(), ClientError> {
-        let audio_packet = AudioPacket::decode(&packet.data)?;
+    /// Drops the decoder state kept for a speaker that left.
+    pub async fn remove_speaker(&self, speaker: &SpeakerId) {
+        self.decoders.lock().await.remove(speaker);
+    }
+
+    /// Decodes audio of a speaker, however it arrived.
+    pub async fn play(&self, relayed: RelayedAudioPacket) -> Result<(), ClientError> {
+        let mut decoders = self.decoders.lock().await;
+        let codec = match decoders.entry(relayed.speaker) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(A::new()?),
+        };
 
-        let mut codec = codec.lock().await;
-        if let Ok(decoded_data) = codec.decode(audio_packet.track) {
-            match audio_output_tx.send(decoded_data) {
+        if let Ok(samples) = codec.decode(relayed.audio.track) {
+            let frame = AudioFrame {
+                speaker: relayed.speaker,
+                sequence: relayed.audio.sequence,
+                timestamp: relayed.audio.timestamp,
+                samples,
+            };
+            match self.audio_output_tx.send(frame) {
                 Ok(_) => {}
                 Err(_) => {
                     return Err(ClientError::InvalidPacket);
@@ -27,3 +65,67 @@ impl AudioPacketHandler {
         Ok(())
     }
 }
+
+#[async_trait::async_trait]
+impl<A: AudioCodec> PacketHandler for AudioPacketHandler<A> {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let relayed =
+            RelayedAudioPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+        self.play(relayed).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::codec::opus::OpusAudioCodec;
+    use common::packet::AudioPacket;
+
+    fn relayed_packet(speaker: SpeakerId, sequence: u16) -> Packet {
+        let mut codec = OpusAudioCodec::new().unwrap();
+        codec.update(48000, 1).unwrap();
+
+        Packet::new(RelayedAudioPacket {
+            speaker,
+            audio: AudioPacket {
+                sequence,
+                timestamp: sequence as u32 * 480,
+                track: codec.encode(vec![0.0; 480]).unwrap(),
+            },
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_expose_speaker_sequence_and_timestamp() {
+        let (tx, mut rx) = broadcast::channel(1);
+        let handler = AudioPacketHandler::<OpusAudioCodec>::new(tx, WireFormat::HANDSHAKE);
+        let speaker = 1;
+
+        handler
+            .handle_packet(relayed_packet(speaker, 7))
+            .await
+            .unwrap();
+
+        let frame = rx.recv().await.unwrap();
+        assert_eq!(frame.speaker, speaker);
+        assert_eq!(frame.sequence, 7);
+        assert_eq!(frame.timestamp, 3360);
+        assert_eq!(frame.samples.len(), 480);
+    }
+
+    #[tokio::test]
+    async fn should_keep_a_decoder_per_speaker() {
+        let (tx, mut rx) = broadcast::channel(2);
+        let handler = AudioPacketHandler::<OpusAudioCodec>::new(tx, WireFormat::HANDSHAKE);
+
+        handler.handle_packet(relayed_packet(1, 0)).await.unwrap();
+        handler.handle_packet(relayed_packet(2, 0)).await.unwrap();
+        assert_eq!(handler.decoders.lock().await.len(), 2);
+        assert_eq!(rx.recv().await.unwrap().speaker, 1);
+        assert_eq!(rx.recv().await.unwrap().speaker, 2);
+
+        handler.remove_speaker(&1).await;
+        assert_eq!(handler.decoders.lock().await.len(), 1);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{sync::Arc, time::Instant};
+
+use common::{
+    cooldown::Cooldowns,
+    packet::{
+        error::DecodeError, format::WireFormat, packet_type::PacketType, Callout, Packet,
+        RelayedCalloutPacket, SpeakerId,
+    },
+};
+use serde::Serialize;
+use tokio::sync::{broadcast, Mutex};
+use uuid::Uuid;
+
+use super::{audio::AudioFrame, speaker::Speakers, PacketHandler};
+use crate::{
+    audio::cue::{self, FRAME_SIZE},
+    error::ClientError,
+};
+
+/// Someone in the room, possibly the local user, sent a callout.
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+pub struct CalloutEvent {
+    pub speaker: SpeakerId,
+    pub client_id: Uuid,
+    pub callout: Callout,
+    pub target: Option<String>,
+}
+
+/// Plays the cue of every relayed callout into the output, and turns them
+/// into [`CalloutEvent`]s for whoever subscribed.
+///
+/// A callout several users send at once is only played once per
+/// [`Callout::COOLDOWN`], though each still makes an event.
+pub struct CalloutPacketHandler {
+    speakers: Arc<Speakers>,
+    cooldowns: Mutex<Cooldowns<Callout>>,
+    audio_output_tx: broadcast::Sender<AudioFrame>,
+    events: broadcast::Sender<CalloutEvent>,
+    format: WireFormat,
+}
+
+impl CalloutPacketHandler {
+    pub fn new(
+        speakers: Arc<Speakers>,
+        audio_output_tx: broadcast::Sender<AudioFrame>,
+        events: broadcast::Sender<CalloutEvent>,
+        format: WireFormat,
+    ) -> Self {
+        Self {
+            speakers,
+            cooldowns: Mutex::new(Cooldowns::new(Callout::COOLDOWN)),
+            audio_output_tx,
+            events,
+            format,
+        }
+    }
+
+    fn play(&self, speaker: SpeakerId, callout: Callout) {
+        for (index, samples) in cue::render(callout).chunks(FRAME_SIZE).enumerate() {
+            let frame = AudioFrame {
+                speaker,
+                sequence: index as u16,
+                timestamp: (index * FRAME_SIZE) as u32,
+                samples: samples.to_vec(),
+            };
+            // Nothing is playing the output until the client runs.
+            if self.audio_output_tx.send(frame).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for CalloutPacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let packet = RelayedCalloutPacket::decode_as(&packet.data, self.format)
+            .map_err(DecodeError::from)?;
+
+        let Some(client_id) = self
+            .speakers
+            .read()
+            .await
+            .get(&packet.speaker)
+            .map(|speaker| speaker.client_id)
+        else {
+            return Ok(());
+        };
+
+        if self
+            .cooldowns
+            .lock()
+            .await
+            .try_start(packet.callout, Instant::now())
+        {
+            self.play(packet.speaker, packet.callout);
+        }
+
+        // Nobody listening is fine.
+        let _ = self.events.send(CalloutEvent {
+            speaker: packet.speaker,
+            client_id,
+            callout: packet.callout,
+            target: packet.target,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::packet::Speaker;
+
+    fn relayed(speaker: SpeakerId, callout: Callout) -> Packet {
+        let packet = RelayedCalloutPacket {
+            speaker,
+            callout,
+            target: None,
+        };
+        Packet::new_as(packet, WireFormat::MessagePack).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_play_each_callout_once_per_cooldown() {
+        let speakers = Arc::new(Speakers::default());
+        for id in [1, 2] {
+            speakers.write().await.insert(
+                id,
+                Speaker {
+                    id,
+                    client_id: Uuid::from_u128(id as u128),
+                    display_name: "Teemo".to_string(),
+                },
+            );
+        }
+        let (output_tx, mut output_rx) = broadcast::channel(64);
+        let (events_tx, mut events_rx) = broadcast::channel(4);
+        let handler =
+            CalloutPacketHandler::new(speakers, output_tx, events_tx, WireFormat::MessagePack);
+
+        handler
+            .handle_packet(relayed(1, Callout::DragonSoon))
+            .await
+            .unwrap();
+        handler
+            .handle_packet(relayed(2, Callout::DragonSoon))
+            .await
+            .unwrap();
+
+        let cue = cue::render(Callout::DragonSoon);
+        let mut played = Vec::new();
+        while let Ok(frame) = output_rx.try_recv() {
+            assert_eq!(frame.speaker, 1);
+            played.extend(frame.samples);
+        }
+        assert_eq!(played, cue, "expected the cue to be played once");
+
+        for id in [1, 2] {
+            let event = events_rx.recv().await.unwrap();
+            assert_eq!(event.client_id, Uuid::from_u128(id));
+            assert_eq!(event.callout, Callout::DragonSoon);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use common::packet::{
+    error::DecodeError, format::WireFormat, ids::PacketId, packet_type::PacketType,
+    ChatHistoryPacket, Packet, RelayedChatPacket,
+};
+use tokio::sync::{broadcast, RwLock};
+
+use super::PacketHandler;
+use crate::error::ClientError;
+
+/// Chat messages of the current room, oldest first.
+pub type ChatLog = RwLock<VecDeque<RelayedChatPacket>>;
+
+/// Keeps the latest chat messages of the room and publishes new ones to
+/// whoever subscribed.
+pub struct ChatPacketHandler {
+    log: Arc<ChatLog>,
+    messages: broadcast::Sender<RelayedChatPacket>,
+    format: WireFormat,
+}
+
+impl ChatPacketHandler {
+    pub fn new(
+        log: Arc<ChatLog>,
+        messages: broadcast::Sender<RelayedChatPacket>,
+        format: WireFormat,
+    ) -> Self {
+        Self {
+            log,
+            messages,
+            format,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for ChatPacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let mut log = self.log.write().await;
+
+        if packet.packet_id == PacketId::ChatHistoryPacket.to_u8() {
+            let history = ChatHistoryPacket::decode_as(&packet.data, self.format)
+                .map_err(DecodeError::from)?;
+            *log = history.messages.into();
+            return Ok(());
+        }
+
+        let message =
+            RelayedChatPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+        if log.len() == ChatHistoryPacket::MAX_MESSAGES {
+            log.pop_front();
+        }
+        log.push_back(message.clone());
+        // Nobody listening is fine.
+        let _ = self.messages.send(message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> RelayedChatPacket {
+        RelayedChatPacket {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_keep_history_and_publish_new_messages() {
+        let format = WireFormat::MessagePack;
+        let log = Arc::new(ChatLog::default());
+        let (tx, mut rx) = broadcast::channel(4);
+        let handler = ChatPacketHandler::new(log.clone(), tx, format);
+
+        let history = ChatHistoryPacket {
+            messages: vec![message("gl hf")],
+        };
+        handler
+            .handle_packet(Packet::new_as(history, format).unwrap())
+            .await
+            .unwrap();
+        handler
+            .handle_packet(Packet::new_as(message("gg"), format).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), message("gg"));
+        assert!(
+            rx.try_recv().is_err(),
+            "expected the history not to be published"
+        );
+        assert_eq!(
+            log.read().await.iter().cloned().collect::<Vec<_>>(),
+            vec![message("gl hf"), message("gg")]
+        );
+    }
+}
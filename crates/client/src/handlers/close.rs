@@ -0,0 +1,53 @@
+use common::packet::{
+    error::DecodeError, format::WireFormat, packet_type::PacketType, ClosePacket, Packet,
+};
+use tokio::sync::watch;
+
+use super::PacketHandler;
+use crate::error::ClientError;
+
+/// Records why the server closed the connection and stops reading from it.
+pub struct ClosePacketHandler {
+    close_tx: watch::Sender<Option<ClosePacket>>,
+    format: WireFormat,
+}
+
+impl ClosePacketHandler {
+    pub fn new(close_tx: watch::Sender<Option<ClosePacket>>, format: WireFormat) -> Self {
+        Self { close_tx, format }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for ClosePacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let close = ClosePacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+        println!("Server closed the connection: {:?}", close);
+
+        self.close_tx.send_replace(Some(close.clone()));
+        Err(close.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::packet::CloseReason;
+
+    #[tokio::test]
+    async fn should_record_close_reason() {
+        let (tx, rx) = watch::channel(None);
+        let handler = ClosePacketHandler::new(tx, WireFormat::MessagePack);
+
+        let close = ClosePacket::new(CloseReason::Kicked, "bye");
+        let result = handler
+            .handle_packet(Packet::new_as(close.clone(), WireFormat::MessagePack).unwrap())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(ClientError::ConnectionClosed(CloseReason::Kicked, _))
+        ));
+        assert_eq!(*rx.borrow(), Some(close));
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use common::{
+    heartbeat::Heartbeat,
+    packet::{
+        error::DecodeError, format::WireFormat, ids::PacketId, packet_type::PacketType, Packet,
+        PingPacket, PongPacket,
+    },
+};
+use tokio::sync::{mpsc, Mutex};
+
+use super::PacketHandler;
+use crate::error::ClientError;
+
+/// Answers the server's pings and records the answers to ours.
+pub struct HeartbeatPacketHandler {
+    heartbeat: Arc<Mutex<Heartbeat>>,
+    packet_sender: mpsc::Sender<Packet>,
+    format: WireFormat,
+}
+
+impl HeartbeatPacketHandler {
+    pub fn new(
+        heartbeat: Arc<Mutex<Heartbeat>>,
+        packet_sender: mpsc::Sender<Packet>,
+        format: WireFormat,
+    ) -> Self {
+        Self {
+            heartbeat,
+            packet_sender,
+            format,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for HeartbeatPacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        match PacketId::from_u8(packet.packet_id) {
+            Some(PacketId::PingPacket) => {
+                let ping =
+                    PingPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+                self.packet_sender
+                    .send(Packet::new_as(PongPacket::from(ping), self.format)?)
+                    .await?;
+            }
+            Some(PacketId::PongPacket) => {
+                let pong =
+                    PongPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+                self.heartbeat.lock().await.pong(&pong);
+            }
+            _ => return Err(ClientError::InvalidPacket),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_answer_ping_with_pong() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let handler = HeartbeatPacketHandler::new(Arc::default(), tx, WireFormat::HANDSHAKE);
+
+        handler
+            .handle_packet(Packet::new(PingPacket { timestamp: 42 }).unwrap())
+            .await
+            .unwrap();
+
+        let pong = rx.recv().await.unwrap();
+        assert_eq!(pong.packet_id, PacketId::PongPacket.to_u8());
+        assert_eq!(PongPacket::decode(&pong.data).unwrap().timestamp, 42);
+    }
+
+    #[tokio::test]
+    async fn should_record_round_trip_time_on_pong() {
+        let (tx, _rx) = mpsc::channel(1);
+        let heartbeat = Arc::new(Mutex::new(Heartbeat::default()));
+        let handler = HeartbeatPacketHandler::new(heartbeat.clone(), tx, WireFormat::HANDSHAKE);
+
+        let ping = heartbeat.lock().await.ping().unwrap();
+        handler
+            .handle_packet(Packet::new(PongPacket::from(ping)).unwrap())
+            .await
+            .unwrap();
+
+        assert!(heartbeat.lock().await.rtt().is_some());
+    }
+}
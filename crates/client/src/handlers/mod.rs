@@ -1,11 +1,32 @@
+use std::sync::Arc;
+
 use crate::error::ClientError;
-use common::packet::Packet;
+use common::packet::{dispatch::Dispatcher, Packet};
 
 pub mod audio;
+pub mod callout;
+pub mod chat;
+pub mod close;
 pub mod connect;
 pub mod disconnect;
+pub mod heartbeat;
+pub mod rejected;
+pub mod roster;
+pub mod speaker;
+pub mod speaking;
+pub mod udp;
+
+/// Packet handlers keyed by the packet type they take care of.
+pub type PacketHandlers = Dispatcher<Box<dyn PacketHandler>>;
 
 #[async_trait::async_trait]
-pub trait PacketHandler {
+pub trait PacketHandler: Send + Sync {
     async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError>;
 }
+
+#[async_trait::async_trait]
+impl<H: PacketHandler + ?Sized> PacketHandler for Arc<H> {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        self.as_ref().handle_packet(packet).await
+    }
+}
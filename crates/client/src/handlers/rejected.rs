@@ -0,0 +1,54 @@
+use common::packet::{
+    error::DecodeError, format::WireFormat, ids::PacketId, packet_type::PacketType, Packet,
+    RejectedPacket,
+};
+
+use super::PacketHandler;
+use crate::error::ClientError;
+
+/// Reports packets the server dropped; the connection itself stays up.
+pub struct RejectedPacketHandler {
+    format: WireFormat,
+}
+
+impl RejectedPacketHandler {
+    pub fn new(format: WireFormat) -> Self {
+        Self { format }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for RejectedPacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let rejected =
+            RejectedPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+        println!(
+            "Server dropped a {:?}: {}",
+            PacketId::from_u8(rejected.packet_id),
+            rejected.message
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::packet::RejectReason;
+
+    #[tokio::test]
+    async fn should_keep_the_connection_up() {
+        let handler = RejectedPacketHandler::new(WireFormat::MessagePack);
+
+        let rejected = RejectedPacket::new(
+            PacketId::ChatPacket,
+            RejectReason::ChatMessageTooLong,
+            "too long",
+        );
+        let result = handler
+            .handle_packet(Packet::new_as(rejected, WireFormat::MessagePack).unwrap())
+            .await;
+
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashMap, sync::Arc};
+
+use common::packet::{
+    error::DecodeError, format::WireFormat, packet_type::PacketType, Packet, RosterPacket,
+    RosterUser,
+};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use super::PacketHandler;
+use crate::error::ClientError;
+
+pub type Roster = RwLock<HashMap<Uuid, RosterUser>>;
+
+/// Keeps the users of the current room in sync with the server.
+pub struct RosterPacketHandler {
+    roster: Arc<Roster>,
+    format: WireFormat,
+}
+
+impl RosterPacketHandler {
+    pub fn new(roster: Arc<Roster>, format: WireFormat) -> Self {
+        Self { roster, format }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for RosterPacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let update =
+            RosterPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+
+        let mut roster = self.roster.write().await;
+        match update {
+            RosterPacket::Snapshot(users) => {
+                *roster = users
+                    .into_iter()
+                    .map(|user| (user.client_id, user))
+                    .collect();
+            }
+            RosterPacket::UserJoined(user) | RosterPacket::UserUpdated(user) => {
+                roster.insert(user.client_id, user);
+            }
+            RosterPacket::UserLeft(client_id) => {
+                roster.remove(&client_id);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u128) -> RosterUser {
+        RosterUser {
+            client_id: Uuid::from_u128(id),
+            speaker: id as u16,
+            display_name: format!("user {}", id),
+            ..Default::default()
+        }
+    }
+
+    async fn apply(handler: &RosterPacketHandler, packet: RosterPacket) {
+        handler
+            .handle_packet(Packet::new_as(packet, WireFormat::MessagePack).unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_apply_roster_updates() {
+        let roster = Arc::new(Roster::default());
+        let handler = RosterPacketHandler::new(roster.clone(), WireFormat::MessagePack);
+
+        apply(&handler, RosterPacket::Snapshot(vec![user(0), user(1)])).await;
+        apply(&handler, RosterPacket::UserJoined(user(2))).await;
+        apply(&handler, RosterPacket::UserLeft(Uuid::from_u128(0))).await;
+        let updated = RosterUser {
+            flags: 1,
+            ..user(1)
+        };
+        apply(&handler, RosterPacket::UserUpdated(updated.clone())).await;
+
+        let roster = roster.read().await;
+        assert_eq!(roster.len(), 2);
+        assert_eq!(roster.get(&Uuid::from_u128(1)), Some(&updated));
+        assert_eq!(roster.get(&Uuid::from_u128(2)), Some(&user(2)));
+    }
+
+    #[tokio::test]
+    async fn should_replace_roster_on_snapshot() {
+        let roster = Arc::new(Roster::default());
+        let handler = RosterPacketHandler::new(roster.clone(), WireFormat::MessagePack);
+
+        apply(&handler, RosterPacket::Snapshot(vec![user(0), user(1)])).await;
+        apply(&handler, RosterPacket::Snapshot(vec![user(2)])).await;
+
+        let roster = roster.read().await;
+        assert_eq!(roster.len(), 1);
+        assert!(roster.contains_key(&Uuid::from_u128(2)));
+    }
+}
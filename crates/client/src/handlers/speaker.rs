@@ -0,0 +1,99 @@
+use std::{collections::HashMap, sync::Arc};
+
+use common::packet::{
+    error::DecodeError, format::WireFormat, packet_type::PacketType, Packet, Speaker, SpeakerId,
+    SpeakerMapPacket,
+};
+use tokio::sync::RwLock;
+
+use super::{audio::AudioPacketHandler, PacketHandler};
+use crate::{audio::codec::AudioCodec, error::ClientError};
+
+pub type Speakers = RwLock<HashMap<SpeakerId, Speaker>>;
+
+/// Keeps the speaker id mapping of the current room in sync with the server.
+///
+/// Decoder state of removed speakers is dropped along with them, before the
+/// server can hand their ids out again.
+pub struct SpeakerPacketHandler<A: AudioCodec> {
+    speakers: Arc<Speakers>,
+    audio: Arc<AudioPacketHandler<A>>,
+    format: WireFormat,
+}
+
+impl<A: AudioCodec> SpeakerPacketHandler<A> {
+    pub fn new(
+        speakers: Arc<Speakers>,
+        audio: Arc<AudioPacketHandler<A>>,
+        format: WireFormat,
+    ) -> Self {
+        Self {
+            speakers,
+            audio,
+            format,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<A: AudioCodec> PacketHandler for SpeakerPacketHandler<A> {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let update =
+            SpeakerMapPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+
+        let mut speakers = self.speakers.write().await;
+        for id in &update.removed {
+            speakers.remove(id);
+            self.audio.remove_speaker(id).await;
+        }
+        for speaker in update.added {
+            speakers.insert(speaker.id, speaker);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::codec::opus::OpusAudioCodec;
+    use tokio::sync::broadcast;
+    use uuid::Uuid;
+
+    fn speaker(id: SpeakerId) -> Speaker {
+        Speaker {
+            id,
+            client_id: Uuid::from_u128(id as u128),
+            display_name: format!("speaker {}", id),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_apply_speaker_map_updates() {
+        let speakers = Arc::new(Speakers::default());
+        let (tx, _rx) = broadcast::channel(1);
+        let audio = Arc::new(AudioPacketHandler::<OpusAudioCodec>::new(
+            tx,
+            WireFormat::HANDSHAKE,
+        ));
+        let handler = SpeakerPacketHandler::new(speakers.clone(), audio, WireFormat::HANDSHAKE);
+
+        let added = Packet::new(SpeakerMapPacket {
+            added: vec![speaker(0), speaker(1)],
+            removed: Vec::new(),
+        })
+        .unwrap();
+        handler.handle_packet(added).await.unwrap();
+        assert_eq!(speakers.read().await.len(), 2);
+
+        let removed = Packet::new(SpeakerMapPacket {
+            added: Vec::new(),
+            removed: vec![1],
+        })
+        .unwrap();
+        handler.handle_packet(removed).await.unwrap();
+        assert_eq!(speakers.read().await.get(&0), Some(&speaker(0)));
+        assert_eq!(speakers.read().await.get(&1), None);
+    }
+}
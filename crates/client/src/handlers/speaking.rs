@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use common::packet::{
+    error::DecodeError, format::WireFormat, packet_type::PacketType, Packet, RelayedSpeakingPacket,
+    SpeakerId,
+};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use super::{speaker::Speakers, PacketHandler};
+use crate::error::ClientError;
+
+/// Someone else in the room started or stopped speaking.
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+pub struct SpeakingEvent {
+    pub speaker: SpeakerId,
+    pub client_id: Uuid,
+    pub speaking: bool,
+}
+
+/// Turns relayed speaking states into [`SpeakingEvent`]s for whoever subscribed.
+pub struct SpeakingPacketHandler {
+    speakers: Arc<Speakers>,
+    events: broadcast::Sender<SpeakingEvent>,
+    format: WireFormat,
+}
+
+impl SpeakingPacketHandler {
+    pub fn new(
+        speakers: Arc<Speakers>,
+        events: broadcast::Sender<SpeakingEvent>,
+        format: WireFormat,
+    ) -> Self {
+        Self {
+            speakers,
+            events,
+            format,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for SpeakingPacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let packet = RelayedSpeakingPacket::decode_as(&packet.data, self.format)
+            .map_err(DecodeError::from)?;
+
+        // The speaker map is sent before anything relayed, an unknown id is a
+        // speaker that has already left.
+        let Some(client_id) = self
+            .speakers
+            .read()
+            .await
+            .get(&packet.speaker)
+            .map(|speaker| speaker.client_id)
+        else {
+            return Ok(());
+        };
+
+        // Nobody listening is fine.
+        let _ = self.events.send(SpeakingEvent {
+            speaker: packet.speaker,
+            client_id,
+            speaking: packet.speaking,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::packet::Speaker;
+
+    fn relayed(speaker: SpeakerId, speaking: bool) -> Packet {
+        Packet::new_as(
+            RelayedSpeakingPacket { speaker, speaking },
+            WireFormat::MessagePack,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_publish_speaking_events() {
+        let speakers = Arc::new(Speakers::default());
+        speakers.write().await.insert(
+            3,
+            Speaker {
+                id: 3,
+                client_id: Uuid::from_u128(3),
+                display_name: "Teemo".to_string(),
+            },
+        );
+        let (tx, mut rx) = broadcast::channel(4);
+        let handler = SpeakingPacketHandler::new(speakers, tx, WireFormat::MessagePack);
+
+        handler.handle_packet(relayed(3, true)).await.unwrap();
+        handler.handle_packet(relayed(4, true)).await.unwrap();
+        handler.handle_packet(relayed(3, false)).await.unwrap();
+
+        for speaking in [true, false] {
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                SpeakingEvent {
+                    speaker: 3,
+                    client_id: Uuid::from_u128(3),
+                    speaking,
+                }
+            );
+        }
+        assert!(
+            rx.try_recv().is_err(),
+            "expected unknown speakers to be skipped"
+        );
+    }
+}
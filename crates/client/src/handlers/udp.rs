@@ -0,0 +1,60 @@
+use common::packet::{
+    error::DecodeError, format::WireFormat, packet_type::PacketType, Packet, UdpOfferPacket,
+};
+use tokio::sync::mpsc;
+
+use super::PacketHandler;
+use crate::error::ClientError;
+
+/// Hands the server's offer of a datagram path over to whoever sets it up,
+/// so probing it does not hold up reading the connection.
+pub struct UdpOfferPacketHandler {
+    offers: mpsc::Sender<UdpOfferPacket>,
+    format: WireFormat,
+}
+
+impl UdpOfferPacketHandler {
+    pub fn new(offers: mpsc::Sender<UdpOfferPacket>, format: WireFormat) -> Self {
+        Self { offers, format }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketHandler for UdpOfferPacketHandler {
+    async fn handle_packet(&self, packet: Packet) -> Result<(), ClientError> {
+        let offer =
+            UdpOfferPacket::decode_as(&packet.data, self.format).map_err(DecodeError::from)?;
+
+        // Only the first offer is taken up, audio keeps to TCP if it fails.
+        if self.offers.try_send(offer).is_err() {
+            println!("Ignoring another UDP offer: {:?}", offer);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn should_only_pass_on_the_first_offer() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let handler = UdpOfferPacketHandler::new(tx, WireFormat::MessagePack);
+
+        for port in [1, 2] {
+            let offer = UdpOfferPacket {
+                port,
+                token: Uuid::from_u128(1),
+            };
+            handler
+                .handle_packet(Packet::new_as(offer, WireFormat::MessagePack).unwrap())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(rx.recv().await.unwrap().port, 1);
+        assert!(rx.try_recv().is_err());
+    }
+}
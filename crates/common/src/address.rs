@@ -0,0 +1,54 @@
+//! Addresses pick the transport through their scheme, such as
+//! `quic://127.0.0.1:8080`. Without one, they are TCP addresses.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Tcp,
+    /// See [`crate::quic`].
+    Quic,
+    /// TCP under TLS, see [`crate::tls`].
+    Tls,
+}
+
+impl Scheme {
+    /// Splits `addr` into its scheme and the `host:port` that follows, or
+    /// returns `None` for a scheme this build does not know.
+    pub fn split(addr: &str) -> Option<(Scheme, &str)> {
+        let Some((scheme, rest)) = addr.split_once("://") else {
+            return Some((Scheme::Tcp, addr));
+        };
+
+        match scheme {
+            "tcp" => Some((Scheme::Tcp, rest)),
+            "quic" => Some((Scheme::Quic, rest)),
+            "tls" => Some((Scheme::Tls, rest)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_split_scheme_off_addresses() {
+        assert_eq!(
+            Scheme::split("127.0.0.1:8080"),
+            Some((Scheme::Tcp, "127.0.0.1:8080"))
+        );
+        assert_eq!(
+            Scheme::split("tcp://localhost:8080"),
+            Some((Scheme::Tcp, "localhost:8080"))
+        );
+        assert_eq!(
+            Scheme::split("quic://[::1]:8080"),
+            Some((Scheme::Quic, "[::1]:8080"))
+        );
+        assert_eq!(
+            Scheme::split("tls://voice.example.com:8080"),
+            Some((Scheme::Tls, "voice.example.com:8080"))
+        );
+        assert_eq!(Scheme::split("smtp://localhost:25"), None);
+    }
+}
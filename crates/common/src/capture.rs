@@ -0,0 +1,469 @@
+//! Recording of the packets exchanged on a connection, for reproducing issues
+//! after the fact.
+//!
+//! A capture file starts with an 8 byte header:
+//!
+//! | bytes | content                                    |
+//! |-------|--------------------------------------------|
+//! | 0..4  | magic, `LVCP`                              |
+//! | 4     | format version, currently `1`              |
+//! | 5     | side that recorded it, `0` client, `1` server |
+//! | 6..8  | reserved, zero                             |
+//!
+//! followed by one record per packet until the end of the file:
+//!
+//! | bytes | content                                          |
+//! |-------|--------------------------------------------------|
+//! | 0..8  | microseconds since the capture started, big endian |
+//! | 8     | direction, `0` sent, `1` received                |
+//! | 9..   | the packet framed as on the wire: `u32` big endian payload length, packet id, payload |
+//!
+//! Packets are recorded whole, after reassembly and before fragmentation, so a
+//! record may be larger than [`MAX_PACKET_SIZE`](crate::packet::MAX_PACKET_SIZE).
+//! Payloads are kept as they were encoded, so reading them back takes the
+//! format negotiated by the `ConnectResponsePacket` at the start of the capture.
+
+use crate::packet::{Packet, HEADER_SIZE};
+use bytes::Bytes;
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const MAGIC: [u8; 4] = *b"LVCP";
+pub const VERSION: u8 = 1;
+
+/// Larger payloads are taken as a corrupt length rather than allocated.
+const MAX_RECORD_PAYLOAD: usize = 16 * 1024 * 1024;
+
+/// Which end of the connection recorded a capture.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaptureRecord {
+    /// Time since the capture started.
+    pub elapsed: Duration,
+    pub direction: Direction,
+    pub packet: Packet,
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    /// The file does not start with [`MAGIC`].
+    NotACapture,
+    UnsupportedVersion(u8),
+    /// The file ends in the middle of a record.
+    Truncated,
+    BadRecord(String),
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CaptureError::Io(e) => write!(f, "Failed to read capture: {}", e),
+            CaptureError::NotACapture => write!(f, "Failed to read capture: not a capture file"),
+            CaptureError::UnsupportedVersion(version) => {
+                write!(f, "Failed to read capture: unsupported version {}", version)
+            }
+            CaptureError::Truncated => write!(f, "Failed to read capture: truncated record"),
+            CaptureError::BadRecord(reason) => {
+                write!(f, "Failed to read capture: bad record: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<io::Error> for CaptureError {
+    fn from(error: io::Error) -> Self {
+        CaptureError::Io(error)
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    started: Instant,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the header; record timestamps count from here.
+    pub fn new(mut writer: W, side: Side) -> io::Result<Self> {
+        let side = match side {
+            Side::Client => 0,
+            Side::Server => 1,
+        };
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, side, 0, 0])?;
+
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, direction: Direction, packet: &Packet) -> io::Result<()> {
+        self.record_at(self.started.elapsed(), direction, packet)
+    }
+
+    fn record_at(
+        &mut self,
+        elapsed: Duration,
+        direction: Direction,
+        packet: &Packet,
+    ) -> io::Result<()> {
+        let direction = match direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        };
+
+        let mut record = Vec::with_capacity(9 + HEADER_SIZE + packet.data.len());
+        record.extend_from_slice(&(elapsed.as_micros() as u64).to_be_bytes());
+        record.push(direction);
+        record.extend_from_slice(&(packet.data.len() as u32).to_be_bytes());
+        record.push(packet.packet_id);
+        record.extend_from_slice(&packet.data);
+        self.writer.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    side: Side,
+    done: bool,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads and validates the header.
+    pub fn new(mut reader: R) -> Result<Self, CaptureError> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => CaptureError::NotACapture,
+            _ => CaptureError::Io(e),
+        })?;
+
+        if header[0..4] != MAGIC {
+            return Err(CaptureError::NotACapture);
+        }
+        if header[4] != VERSION {
+            return Err(CaptureError::UnsupportedVersion(header[4]));
+        }
+        let side = match header[5] {
+            0 => Side::Client,
+            1 => Side::Server,
+            side => return Err(CaptureError::BadRecord(format!("unknown side {}", side))),
+        };
+
+        Ok(Self {
+            reader,
+            side,
+            done: false,
+        })
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    fn read_record(&mut self) -> Result<Option<CaptureRecord>, CaptureError> {
+        let mut header = [0; 9 + HEADER_SIZE];
+        let read = read_full(&mut self.reader, &mut header)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < header.len() {
+            return Err(CaptureError::Truncated);
+        }
+
+        let elapsed = u64::from_be_bytes(header[0..8].try_into().unwrap());
+        let direction = match header[8] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            direction => {
+                return Err(CaptureError::BadRecord(format!(
+                    "unknown direction {}",
+                    direction
+                )))
+            }
+        };
+        let length = u32::from_be_bytes(header[9..13].try_into().unwrap());
+        let packet_id = header[13];
+        if length as usize > MAX_RECORD_PAYLOAD {
+            return Err(CaptureError::BadRecord(format!(
+                "{} byte payload is too large",
+                length
+            )));
+        }
+
+        let mut data = vec![0; length as usize];
+        if read_full(&mut self.reader, &mut data)? < data.len() {
+            return Err(CaptureError::Truncated);
+        }
+
+        Ok(Some(CaptureRecord {
+            elapsed: Duration::from_micros(elapsed),
+            direction,
+            packet: Packet {
+                length,
+                packet_id,
+                data: Bytes::from(data),
+            },
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let record = self.read_record().transpose();
+        // Nothing after a bad record can be trusted.
+        self.done = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
+
+/// Reads until `buffer` is full or the reader is exhausted, returning how much was read.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// How often recorded packets are flushed to the capture file, so that little
+/// is lost when the process gets killed, which is how most sessions worth
+/// capturing end.
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Records waiting for the capture thread beyond this are dropped, so a slow
+/// disk costs packets in the capture rather than memory.
+pub const MAX_PENDING_RECORDS: usize = 1024;
+
+/// Shared handle recording a connection to a capture file, or doing nothing
+/// when capturing is off.
+///
+/// Records are written out by a thread of the capture's own, so recording
+/// never blocks the connection. It is best effort: records are dropped while
+/// the thread is [`MAX_PENDING_RECORDS`] behind, and the first write error is
+/// reported and stops the capture, the connection itself carries on. Once the
+/// last handle is dropped the thread writes out what is left and exits on its
+/// own.
+#[derive(Clone, Default)]
+pub struct Capture {
+    writer: Option<Arc<CaptureThread>>,
+}
+
+struct CaptureThread {
+    started: Instant,
+    records: mpsc::SyncSender<CaptureRecord>,
+}
+
+impl Capture {
+    pub fn create(path: impl AsRef<Path>, side: Side) -> io::Result<Self> {
+        let mut writer = CaptureWriter::new(BufWriter::new(File::create(path)?), side)?;
+        // A valid, if empty, capture from the start.
+        writer.flush()?;
+        let started = writer.started;
+        let (records, receiver) = mpsc::sync_channel(MAX_PENDING_RECORDS);
+        thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || write_records(writer, receiver))?;
+
+        Ok(Self {
+            writer: Some(Arc::new(CaptureThread { started, records })),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn record(&self, direction: Direction, packet: &Packet) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+
+        // Fails once the thread stopped, which it already reported, or while
+        // it is too far behind.
+        let _ = writer.records.try_send(CaptureRecord {
+            elapsed: writer.started.elapsed(),
+            direction,
+            packet: packet.clone(),
+        });
+    }
+}
+
+fn write_records(
+    mut writer: CaptureWriter<BufWriter<File>>,
+    records: mpsc::Receiver<CaptureRecord>,
+) {
+    let mut flushed = Instant::now();
+    loop {
+        let result = match records.recv_timeout(FLUSH_INTERVAL) {
+            Ok(record) => writer.record_at(record.elapsed, record.direction, &record.packet),
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Err(e) = writer.flush() {
+                    println!("Stopping capture: {}", e);
+                }
+                return;
+            }
+        };
+        let result = result.and_then(|_| {
+            if flushed.elapsed() < FLUSH_INTERVAL {
+                return Ok(());
+            }
+            flushed = Instant::now();
+            writer.flush()
+        });
+
+        if let Err(e) = result {
+            println!("Stopping capture: {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{AudioPacket, ConnectPacket};
+
+    fn capture_of(records: &[(u64, Direction, Packet)]) -> Vec<u8> {
+        let mut writer = CaptureWriter::new(Vec::new(), Side::Client).unwrap();
+        for (elapsed, direction, packet) in records {
+            writer
+                .record_at(Duration::from_micros(*elapsed), *direction, packet)
+                .unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn should_read_back_recorded_packets() {
+        let connect = Packet::new(ConnectPacket::default()).unwrap();
+        let audio = Packet::new(AudioPacket {
+            track: vec![7; 2048],
+            ..Default::default()
+        })
+        .unwrap();
+        let bytes = capture_of(&[
+            (0, Direction::Sent, connect.clone()),
+            (20_000, Direction::Received, audio.clone()),
+        ]);
+
+        let mut reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.side(), Side::Client);
+        assert_eq!(
+            reader.next().unwrap().unwrap(),
+            CaptureRecord {
+                elapsed: Duration::ZERO,
+                direction: Direction::Sent,
+                packet: connect,
+            }
+        );
+        assert_eq!(
+            reader.next().unwrap().unwrap(),
+            CaptureRecord {
+                elapsed: Duration::from_millis(20),
+                direction: Direction::Received,
+                packet: audio,
+            },
+            "expected packets larger than a frame to be kept whole"
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn should_reject_files_that_are_not_captures() {
+        assert!(matches!(
+            CaptureReader::new(&b"RIFF\x01\x00\x00\x00"[..]),
+            Err(CaptureError::NotACapture)
+        ));
+        assert!(matches!(
+            CaptureReader::new(&b"LVCP\x09\x00\x00\x00"[..]),
+            Err(CaptureError::UnsupportedVersion(9))
+        ));
+        assert!(matches!(
+            CaptureReader::new(&b"LV"[..]),
+            Err(CaptureError::NotACapture)
+        ));
+    }
+
+    #[test]
+    fn should_stop_at_truncated_record() {
+        let packet = Packet::new(ConnectPacket::default()).unwrap();
+        let mut bytes = capture_of(&[
+            (0, Direction::Sent, packet.clone()),
+            (1, Direction::Sent, packet),
+        ]);
+        bytes.truncate(bytes.len() - 3);
+
+        let mut reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(CaptureError::Truncated))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn should_write_capture_file() {
+        let path = std::env::temp_dir().join(format!("capture-{}.lvcp", std::process::id()));
+        let capture = Capture::create(&path, Side::Server).unwrap();
+        assert!(capture.is_enabled());
+        capture.record(
+            Direction::Received,
+            &Packet::new(ConnectPacket::default()).unwrap(),
+        );
+        drop(capture);
+
+        // The thread flushes once it sees the capture is gone.
+        let read = || CaptureReader::new(File::open(&path).unwrap()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while read().count() == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(read().side(), Side::Server);
+        assert_eq!(read().count(), 1);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(!Capture::default().is_enabled());
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Lets each key through at most once per period.
+#[derive(Debug)]
+pub struct Cooldowns<K> {
+    period: Duration,
+    last: HashMap<K, Instant>,
+}
+
+impl<K: Hash + Eq> Cooldowns<K> {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Whether `key` may go through at `now`, starting its cooldown if so.
+    pub fn try_start(&mut self, key: K, now: Instant) -> bool {
+        if let Some(last) = self.last.get(&key) {
+            if now.saturating_duration_since(*last) < self.period {
+                return false;
+            }
+        }
+
+        // Keys whose cooldown is over are as good as never seen.
+        self.last
+            .retain(|_, last| now.saturating_duration_since(*last) < self.period);
+        self.last.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_hold_back_keys_until_their_cooldown_is_over() {
+        let mut cooldowns = Cooldowns::new(Duration::from_secs(3));
+        let start = Instant::now();
+
+        assert!(cooldowns.try_start("dragon", start));
+        assert!(cooldowns.try_start("baron", start));
+        assert!(!cooldowns.try_start("dragon", start + Duration::from_secs(2)));
+        assert!(cooldowns.try_start("dragon", start + Duration::from_secs(3)));
+        assert!(!cooldowns.try_start("dragon", start + Duration::from_secs(4)));
+    }
+}
@@ -0,0 +1,263 @@
+//! Packets sent as UDP datagrams next to a connection's stream.
+//!
+//! Over a single stream, one lost segment holds back every audio frame behind
+//! it. Once the server offered it with an
+//! [`UdpOfferPacket`](crate::packet::UdpOfferPacket), audio may travel as
+//! datagrams instead, where a lost frame is only a lost frame:
+//!
+//! | bytes | content                                                      |
+//! |-------|--------------------------------------------------------------|
+//! | 0..16 | token from the offer, binding the datagram to its connection |
+//! | 16..  | a single packet, framed as on the stream                     |
+//!
+//! On encrypted connections, the packet is sealed into a record of its own
+//! with [`Session::datagram_codec`](crate::secure::Session::datagram_codec).
+//!
+//! Probes tell whether datagrams get through, and the client tells the server
+//! over the stream whenever audio moves, with an
+//! [`UdpReadyPacket`](crate::packet::UdpReadyPacket).
+
+use crate::{
+    capture::{Capture, Direction},
+    packet::{
+        codec::{CodecError, PacketCodec},
+        error::DecodeError,
+        Packet,
+    },
+    quic::QuicDatagrams,
+    secure::SecureCodec,
+};
+use bytes::BytesMut;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::UdpSocket;
+use tokio_util::codec::{Decoder, Encoder};
+use uuid::Uuid;
+
+pub const TOKEN_SIZE: usize = 16;
+
+/// Large enough for any packet, framed and sealed, behind its token.
+pub const MAX_DATAGRAM_SIZE: usize = 2048;
+
+/// Frames packets into datagrams of one connection, see the [module docs](self).
+#[derive(Debug)]
+pub struct DatagramCodec {
+    token: Uuid,
+    codec: SecureCodec<PacketCodec>,
+}
+
+impl DatagramCodec {
+    pub fn new(token: Uuid, codec: SecureCodec<PacketCodec>) -> Self {
+        Self { token, codec }
+    }
+
+    /// Reads the token off a datagram, to find the connection it belongs to.
+    pub fn token(datagram: &[u8]) -> Option<Uuid> {
+        datagram
+            .get(..TOKEN_SIZE)
+            .and_then(|token| Uuid::from_slice(token).ok())
+    }
+
+    pub fn encode(&mut self, packet: Packet) -> Result<BytesMut, CodecError> {
+        let mut datagram = BytesMut::from(&self.token.as_bytes()[..]);
+        self.codec.encode(packet, &mut datagram)?;
+        Ok(datagram)
+    }
+
+    pub fn decode(&mut self, datagram: &[u8]) -> Result<Packet, CodecError> {
+        if Self::token(datagram) != Some(self.token) {
+            return Err(DecodeError::BadDatagram(datagram.len()).into());
+        }
+
+        let mut src = BytesMut::from(&datagram[TOKEN_SIZE..]);
+        match self.codec.decode(&mut src)? {
+            Some(packet) if src.is_empty() => Ok(packet),
+            _ => Err(DecodeError::BadDatagram(datagram.len()).into()),
+        }
+    }
+}
+
+/// One end of a connection's datagram path, shared by the tasks sending and
+/// receiving on it.
+///
+/// The peer is whoever last sent a datagram that opened, so a client whose
+/// NAT mapping changes keeps being reached.
+pub struct DatagramLink {
+    socket: Arc<UdpSocket>,
+    token: Uuid,
+    codec: Mutex<DatagramCodec>,
+    peer: Mutex<Option<SocketAddr>>,
+    capture: Capture,
+}
+
+impl DatagramLink {
+    pub fn new(
+        socket: Arc<UdpSocket>,
+        codec: DatagramCodec,
+        peer: Option<SocketAddr>,
+        capture: Capture,
+    ) -> Self {
+        Self {
+            socket,
+            token: codec.token,
+            codec: Mutex::new(codec),
+            peer: Mutex::new(peer),
+            capture,
+        }
+    }
+
+    pub fn token(&self) -> Uuid {
+        self.token
+    }
+
+    pub fn peer(&self) -> Option<SocketAddr> {
+        *self.peer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Opens a datagram `from` the peer, which is then sent to there.
+    pub fn receive(&self, datagram: &[u8], from: SocketAddr) -> Result<Packet, CodecError> {
+        let packet = self
+            .codec
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .decode(datagram)?;
+        *self.peer.lock().unwrap_or_else(|e| e.into_inner()) = Some(from);
+        self.capture.record(Direction::Received, &packet);
+        Ok(packet)
+    }
+
+    /// Sends `packet` to the peer. Returns whether it was sent, which it is
+    /// not while the peer is unknown.
+    pub async fn send(&self, packet: Packet) -> Result<bool, CodecError> {
+        let Some(peer) = self.peer() else {
+            return Ok(false);
+        };
+
+        self.capture.record(Direction::Sent, &packet);
+        let datagram = self
+            .codec
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .encode(packet)?;
+        self.socket.send_to(&datagram, peer).await?;
+        Ok(true)
+    }
+}
+
+/// Where a connection's audio goes when not over its stream.
+#[derive(Clone)]
+pub enum DatagramPath {
+    /// Datagrams next to a TCP connection, see the [module docs](self).
+    Udp(Arc<DatagramLink>),
+    /// Datagrams of the QUIC connection itself.
+    Quic(Arc<QuicDatagrams>),
+}
+
+impl DatagramPath {
+    /// Sends `packet` as a datagram. Returns whether it was sent, otherwise
+    /// it is left to the stream.
+    pub async fn send(&self, packet: Packet) -> Result<bool, CodecError> {
+        match self {
+            DatagramPath::Udp(link) => link.send(packet).await,
+            DatagramPath::Quic(datagrams) => datagrams.send(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packet::{PingPacket, UdpProbePacket},
+        secure::{initiate, respond, StaticKey, DATAGRAM_NONCES},
+    };
+
+    fn packet() -> Packet {
+        Packet::new(PingPacket { timestamp: 7 }).unwrap()
+    }
+
+    #[test]
+    fn should_refuse_datagrams_of_other_connections() {
+        let mut sender =
+            DatagramCodec::new(Uuid::from_u128(1), SecureCodec::plaintext(PacketCodec));
+        let mut receiver =
+            DatagramCodec::new(Uuid::from_u128(1), SecureCodec::plaintext(PacketCodec));
+        let mut other = DatagramCodec::new(Uuid::from_u128(2), SecureCodec::plaintext(PacketCodec));
+
+        let datagram = sender.encode(packet()).unwrap();
+        assert_eq!(DatagramCodec::token(&datagram), Some(Uuid::from_u128(1)));
+        assert_eq!(receiver.decode(&datagram).unwrap(), packet());
+        assert!(matches!(
+            other.decode(&datagram),
+            Err(CodecError::Decode(DecodeError::BadDatagram(_)))
+        ));
+        assert!(matches!(
+            receiver.decode(&datagram[..datagram.len() - 1]),
+            Err(CodecError::Decode(DecodeError::BadDatagram(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_seal_datagrams_apart_from_the_stream() {
+        let key = StaticKey::generate().unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move { respond(&mut server, &key).await });
+        let client = initiate(&mut client, None).await.unwrap();
+        let server = server.await.unwrap().unwrap();
+
+        let token = Uuid::from_u128(1);
+        let mut sender = DatagramCodec::new(token, client.datagram_codec(PacketCodec));
+        let mut receiver = DatagramCodec::new(token, server.datagram_codec(PacketCodec));
+
+        let probe = Packet::new(UdpProbePacket::default()).unwrap();
+        let first = sender.encode(probe.clone()).unwrap();
+        let second = sender.encode(packet()).unwrap();
+        let plaintext = probe.encode();
+        assert!(
+            !first
+                .windows(plaintext.len())
+                .any(|bytes| bytes == plaintext),
+            "expected the datagram to be encrypted"
+        );
+        // Nonces the stream sealed with the same key are never reused.
+        assert_eq!(
+            first[TOKEN_SIZE + 2..TOKEN_SIZE + 10],
+            DATAGRAM_NONCES.to_be_bytes()
+        );
+
+        // Datagrams may arrive out of order, but only once.
+        assert_eq!(receiver.decode(&second).unwrap(), packet());
+        assert_eq!(receiver.decode(&first).unwrap(), probe);
+        assert!(matches!(
+            receiver.decode(&first),
+            Err(CodecError::Decode(DecodeError::Replayed(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_answer_whoever_sent_the_last_datagram() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let token = Uuid::from_u128(1);
+        let link = DatagramLink::new(
+            socket.clone(),
+            DatagramCodec::new(token, SecureCodec::plaintext(PacketCodec)),
+            None,
+            Capture::default(),
+        );
+        assert!(!link.send(packet()).await.unwrap());
+
+        let mut codec = DatagramCodec::new(token, SecureCodec::plaintext(PacketCodec));
+        let datagram = codec.encode(packet()).unwrap();
+        let from = peer.local_addr().unwrap();
+        assert_eq!(link.receive(&datagram, from).unwrap(), packet());
+        assert_eq!(link.peer(), Some(from));
+
+        assert!(link.send(packet()).await.unwrap());
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        let (length, _) = peer.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(codec.decode(&buffer[..length]).unwrap(), packet());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::packet::{PingPacket, PongPacket};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Time between two pings.
+    pub interval: Duration,
+    /// Number of pings that may go unanswered before the peer is considered dead.
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_missed: 3,
+        }
+    }
+}
+
+/// Tracks pings sent to a peer and the round-trip time of the answers.
+///
+/// Every [`Heartbeat::ping`] counts as missed until any pong arrives, which
+/// resets the count; a peer that leaves `max_missed` pings in a row unanswered
+/// is dead.
+#[derive(Debug)]
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    started: Instant,
+    missed: u32,
+    rtt: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig) -> Self {
+        Self {
+            config,
+            started: Instant::now(),
+            missed: 0,
+            rtt: None,
+        }
+    }
+
+    pub fn config(&self) -> HeartbeatConfig {
+        self.config
+    }
+
+    /// Returns the next ping to send, or `None` once the peer is dead.
+    pub fn ping(&mut self) -> Option<PingPacket> {
+        if self.is_dead() {
+            return None;
+        }
+
+        self.missed += 1;
+        Some(PingPacket {
+            timestamp: self.now(),
+        })
+    }
+
+    /// Records an answer to one of our pings and returns its round-trip time.
+    pub fn pong(&mut self, pong: &PongPacket) -> Duration {
+        let rtt = Duration::from_micros(self.now().saturating_sub(pong.timestamp));
+        self.missed = 0;
+        self.rtt = Some(rtt);
+        rtt
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.missed >= self.config.max_missed
+    }
+
+    /// Round-trip time measured by the latest pong, if any arrived yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new(HeartbeatConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_measure_round_trip_time() {
+        let mut heartbeat = Heartbeat::default();
+        assert_eq!(heartbeat.rtt(), None);
+
+        let ping = heartbeat.ping().unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        let rtt = heartbeat.pong(&PongPacket::from(ping));
+
+        assert!(rtt >= Duration::from_millis(2));
+        assert_eq!(heartbeat.rtt(), Some(rtt));
+    }
+
+    #[test]
+    fn should_declare_peer_dead_after_missed_pings() {
+        let mut heartbeat = Heartbeat::new(HeartbeatConfig {
+            max_missed: 2,
+            ..Default::default()
+        });
+
+        assert!(heartbeat.ping().is_some());
+        assert!(heartbeat.ping().is_some());
+        assert!(heartbeat.is_dead());
+        assert_eq!(heartbeat.ping(), None);
+    }
+
+    #[test]
+    fn should_reset_missed_pings_on_pong() {
+        let mut heartbeat = Heartbeat::new(HeartbeatConfig {
+            max_missed: 2,
+            ..Default::default()
+        });
+
+        let ping = heartbeat.ping().unwrap();
+        heartbeat.ping().unwrap();
+        heartbeat.pong(&ping.into());
+
+        assert!(!heartbeat.is_dead());
+        assert!(heartbeat.ping().is_some());
+    }
+}
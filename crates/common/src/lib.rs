@@ -1 +1,10 @@
+pub mod address;
+pub mod capture;
+pub mod cooldown;
+pub mod datagram;
+pub mod heartbeat;
 pub mod packet;
+pub mod quic;
+pub mod secure;
+pub mod tls;
+pub mod transport;
@@ -0,0 +1,166 @@
+use super::{error::DecodeError, Packet, HEADER_SIZE, MAX_PACKET_SIZE};
+use bytes::{BufMut, BytesMut};
+use std::fmt::Display;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(std::io::Error),
+    Decode(DecodeError),
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "io error: {}", e),
+            CodecError::Decode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<std::io::Error> for CodecError {
+    fn from(error: std::io::Error) -> Self {
+        CodecError::Io(error)
+    }
+}
+
+impl From<DecodeError> for CodecError {
+    fn from(error: DecodeError) -> Self {
+        CodecError::Decode(error)
+    }
+}
+
+/// Length-prefixed framing for [`Packet`]s, usable with `FramedRead`/`FramedWrite`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PacketCodec;
+
+impl Decoder for PacketCodec {
+    type Item = Packet;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, CodecError> {
+        let frame_length = match Packet::frame_length(src) {
+            Ok(frame_length) => frame_length,
+            Err(DecodeError::NeedMoreData) => return Ok(None),
+            Err(e) => return Err(CodecError::Decode(e)),
+        };
+
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
+            return Ok(None);
+        }
+
+        Packet::decode(src).map(Some).map_err(CodecError::Decode)
+    }
+}
+
+impl Encoder<Packet> for PacketCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> Result<(), CodecError> {
+        if packet.data.len() > MAX_PACKET_SIZE {
+            return Err(CodecError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "packet payload exceeds MAX_PACKET_SIZE",
+            )));
+        }
+
+        dst.reserve(HEADER_SIZE + packet.data.len());
+        dst.put_u32(packet.data.len() as u32);
+        dst.put_u8(packet.packet_id);
+        dst.put_slice(&packet.data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{AudioPacket, ConnectPacket};
+
+    #[test]
+    fn should_encode_and_decode_packet() {
+        let packet = Packet::new(ConnectPacket::default()).unwrap();
+
+        let mut buffer = BytesMut::new();
+        PacketCodec.encode(packet.clone(), &mut buffer).unwrap();
+        assert_eq!(&buffer[..], packet.encode().as_slice());
+
+        let decoded = PacketCodec.decode(&mut buffer).unwrap();
+        assert_eq!(decoded, Some(packet));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn should_wait_for_partial_frame() {
+        let encoded = Packet::new(AudioPacket {
+            track: vec![1; 16],
+            ..Default::default()
+        })
+        .unwrap()
+        .encode();
+        let mut codec = PacketCodec;
+
+        let mut buffer = BytesMut::from(&encoded[..3]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+
+        buffer.extend_from_slice(&encoded[3..10]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+
+        buffer.extend_from_slice(&encoded[10..]);
+        assert!(codec.decode(&mut buffer).unwrap().is_some());
+    }
+
+    #[test]
+    fn should_decode_multiple_frames() {
+        let first = Packet::new(AudioPacket {
+            track: vec![1],
+            ..Default::default()
+        })
+        .unwrap();
+        let second = Packet::new(AudioPacket {
+            track: vec![2],
+            ..Default::default()
+        })
+        .unwrap();
+        let mut codec = PacketCodec;
+
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&first.encode());
+        buffer.extend_from_slice(&second.encode());
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(first));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(second));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+    }
+
+    #[test]
+    fn should_reject_oversized_frame() {
+        let mut buffer = BytesMut::from(&[0, 0, 16, 0, 2][..]);
+        assert!(matches!(
+            PacketCodec.decode(&mut buffer),
+            Err(CodecError::Decode(DecodeError::FrameTooLarge(4096)))
+        ));
+
+        let packet = Packet::new(AudioPacket {
+            track: vec![0; MAX_PACKET_SIZE],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            PacketCodec.encode(packet, &mut BytesMut::new()),
+            Err(CodecError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn should_reject_unknown_packet_id() {
+        let mut buffer = BytesMut::from(&[0, 0, 0, 1, 255][..]);
+        assert!(matches!(
+            PacketCodec.decode(&mut buffer),
+            Err(CodecError::Decode(DecodeError::UnknownPacketId(255)))
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+use super::{ids::PacketId, packet_type::PacketType};
+use std::collections::HashMap;
+
+/// Handlers keyed by the packet type they take care of.
+///
+/// Ids come from the registry in [`PacketId`], so registering a handler only
+/// names the packet type, never its wire id.
+pub struct Dispatcher<H> {
+    handlers: HashMap<PacketId, H>,
+}
+
+impl<H> Dispatcher<H> {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Registers the handler for `P`, returning the one it replaces, if any.
+    pub fn register<P: PacketType>(&mut self, handler: H) -> Option<H> {
+        self.handlers.insert(P::packet_id(), handler)
+    }
+
+    pub fn handler(&self, packet_id: PacketId) -> Option<&H> {
+        self.handlers.get(&packet_id)
+    }
+
+    /// Registered packet ids without a handler, in registry order.
+    pub fn unhandled(&self) -> Vec<PacketId> {
+        PacketId::ALL
+            .iter()
+            .filter(|packet_id| !self.handlers.contains_key(packet_id))
+            .copied()
+            .collect()
+    }
+}
+
+impl<H> Default for Dispatcher<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{ConnectPacket, PingPacket};
+
+    #[test]
+    fn should_dispatch_by_packet_type() {
+        let mut dispatcher = Dispatcher::new();
+        assert_eq!(dispatcher.register::<ConnectPacket>("connect"), None);
+        assert_eq!(dispatcher.register::<PingPacket>("ping"), None);
+        assert_eq!(
+            dispatcher.register::<PingPacket>("pong"),
+            Some("ping"),
+            "expected re-registering to replace the handler"
+        );
+
+        assert_eq!(
+            dispatcher.handler(PacketId::ConnectPacket),
+            Some(&"connect")
+        );
+        assert_eq!(dispatcher.handler(PacketId::PingPacket), Some(&"pong"));
+        assert_eq!(dispatcher.handler(PacketId::AudioPacket), None);
+    }
+
+    #[test]
+    fn should_list_unhandled_packets() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.register::<ConnectPacket>(());
+
+        let unhandled = dispatcher.unhandled();
+        assert_eq!(unhandled.len(), PacketId::ALL.len() - 1);
+        assert!(!unhandled.contains(&PacketId::ConnectPacket));
+    }
+}
@@ -1,26 +1,131 @@
+use super::format::FormatError;
 use std::fmt::Display;
 
-#[derive(Debug)]
-pub struct DecodeError(pub String);
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DecodeError {
+    /// The buffer does not hold a complete frame yet.
+    NeedMoreData,
+    /// The frame header announces a payload larger than `MAX_PACKET_SIZE`.
+    FrameTooLarge(usize),
+    /// The frame carries a packet id this build does not know.
+    UnknownPacketId(u8),
+    /// The payload could not be deserialized into its packet type.
+    BadPayload(String),
+    /// A fragmented message would reassemble to more than the configured limit.
+    MessageTooLarge(usize),
+    /// Too many bytes of incomplete messages are already buffered.
+    ReassemblyBufferFull,
+    /// A fragment contradicts the others of its message.
+    BadFragment(String),
+    /// An encrypted record failed authentication.
+    Unauthenticated,
+    /// An encrypted record reused a nonce, or is too old to tell.
+    Replayed(u64),
+    /// An encrypted record skipped ahead of the next nonce expected, or
+    /// carries one of the other channel.
+    UnexpectedNonce(u64),
+    /// A datagram is not a single packet of the connection it claims to be from.
+    BadDatagram(usize),
+}
 
 impl Display for DecodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Failed to decode packet: {}", self.0)
+        match self {
+            DecodeError::NeedMoreData => write!(f, "Failed to decode packet: need more data"),
+            DecodeError::FrameTooLarge(length) => {
+                write!(
+                    f,
+                    "Failed to decode packet: frame of {} bytes is too large",
+                    length
+                )
+            }
+            DecodeError::UnknownPacketId(id) => {
+                write!(f, "Failed to decode packet: unknown packet id {}", id)
+            }
+            DecodeError::BadPayload(reason) => {
+                write!(f, "Failed to decode packet: bad payload: {}", reason)
+            }
+            DecodeError::MessageTooLarge(length) => {
+                write!(
+                    f,
+                    "Failed to decode packet: message of {} bytes is too large",
+                    length
+                )
+            }
+            DecodeError::ReassemblyBufferFull => {
+                write!(f, "Failed to decode packet: reassembly buffer is full")
+            }
+            DecodeError::BadFragment(reason) => {
+                write!(f, "Failed to decode packet: bad fragment: {}", reason)
+            }
+            DecodeError::Unauthenticated => {
+                write!(f, "Failed to decode packet: record failed authentication")
+            }
+            DecodeError::Replayed(nonce) => {
+                write!(f, "Failed to decode packet: record {} was replayed", nonce)
+            }
+            DecodeError::UnexpectedNonce(nonce) => {
+                write!(
+                    f,
+                    "Failed to decode packet: record {} was not expected on this channel",
+                    nonce
+                )
+            }
+            DecodeError::BadDatagram(length) => {
+                write!(
+                    f,
+                    "Failed to decode packet: datagram of {} bytes is not a single packet",
+                    length
+                )
+            }
+        }
     }
 }
 
 impl std::error::Error for DecodeError {}
 
+impl From<Box<bincode::ErrorKind>> for DecodeError {
+    fn from(error: Box<bincode::ErrorKind>) -> Self {
+        DecodeError::BadPayload(error.to_string())
+    }
+}
+
+impl From<FormatError> for DecodeError {
+    fn from(error: FormatError) -> Self {
+        DecodeError::BadPayload(error.to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn should_display_decode_error() {
-        let error = DecodeError("Buffer is too small".to_string());
         assert_eq!(
-            error.to_string(),
-            "Failed to decode packet: Buffer is too small"
+            DecodeError::NeedMoreData.to_string(),
+            "Failed to decode packet: need more data"
+        );
+        assert_eq!(
+            DecodeError::FrameTooLarge(4096).to_string(),
+            "Failed to decode packet: frame of 4096 bytes is too large"
+        );
+        assert_eq!(
+            DecodeError::UnknownPacketId(18).to_string(),
+            "Failed to decode packet: unknown packet id 18"
+        );
+        assert_eq!(
+            DecodeError::BadPayload("eof".to_string()).to_string(),
+            "Failed to decode packet: bad payload: eof"
         );
     }
+
+    #[test]
+    fn should_convert_bincode_error_to_bad_payload() {
+        let error = bincode::deserialize::<u32>(&[0]).unwrap_err();
+        assert!(matches!(
+            DecodeError::from(error),
+            DecodeError::BadPayload(_)
+        ));
+    }
 }
@@ -0,0 +1,375 @@
+use bincode::Options;
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed, SeqAccess, Visitor},
+    Deserializer, Serialize,
+};
+use std::{cell::Cell, fmt::Display, io};
+
+/// Serialization used for packet payloads on a connection.
+///
+/// The connect exchange is always encoded with [`WireFormat::HANDSHAKE`]; both
+/// sides switch to the format picked from the negotiated capabilities once it
+/// is accepted.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum WireFormat {
+    /// Fixed layout, every field in declaration order. Compact, and reads
+    /// packets lacking `#[serde(default)]` fields at their end as if those
+    /// were defaulted.
+    Bincode,
+    /// MessagePack maps keyed by field name. Unknown fields are ignored and
+    /// fields marked `#[serde(default)]` may be missing, so packets can grow
+    /// new fields without breaking older peers.
+    MessagePack,
+}
+
+impl WireFormat {
+    pub const HANDSHAKE: WireFormat = WireFormat::Bincode;
+
+    /// Supported formats, most preferred first.
+    pub const ALL: &'static [WireFormat] = &[WireFormat::MessagePack, WireFormat::Bincode];
+
+    /// Capability bit advertising support for this format.
+    pub const fn bit(self) -> u32 {
+        match self {
+            WireFormat::Bincode => 1 << 0,
+            WireFormat::MessagePack => 1 << 1,
+        }
+    }
+
+    /// Picks the most preferred format out of a capability bitset.
+    pub fn negotiate(formats: u32) -> Option<WireFormat> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| formats & format.bit() != 0)
+    }
+
+    pub fn serialize<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, FormatError> {
+        match self {
+            WireFormat::Bincode => bincode::serialize(value).map_err(FormatError::Bincode),
+            WireFormat::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(FormatError::MessagePackEncode)
+            }
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, FormatError> {
+        match self {
+            WireFormat::Bincode => bincode_deserialize(data).map_err(FormatError::Bincode),
+            WireFormat::MessagePack => {
+                rmp_serde::from_slice(data).map_err(FormatError::MessagePackDecode)
+            }
+        }
+    }
+}
+
+/// Reads a bincode packet written by a peer with more or fewer fields at its
+/// end, as packets grow with new `#[serde(default)]` fields.
+///
+/// Fields it does not know are trailing bytes, which are ignored. Fields it
+/// expects are only left to their default if the payload ends right before
+/// them and they are `#[serde(default)]`, as are all the fields after them.
+/// Anything else missing, down to an empty payload, fails to decode.
+pub(crate) fn bincode_deserialize<T: DeserializeOwned>(
+    data: &[u8],
+) -> Result<T, Box<bincode::ErrorKind>> {
+    let remaining = Cell::new(data);
+    let options = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes();
+    let mut deserializer = bincode::Deserializer::with_reader(Remaining(&remaining), options);
+    T::deserialize(MissingFields {
+        inner: &mut deserializer,
+        remaining: &remaining,
+    })
+}
+
+/// Reads from a slice that stays visible to [`MissingFields`] meanwhile.
+struct Remaining<'a, 'b>(&'a Cell<&'b [u8]>);
+
+impl io::Read for Remaining<'_, '_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut data = self.0.get();
+        let read = data.read(out)?;
+        self.0.set(data);
+        Ok(read)
+    }
+}
+
+macro_rules! forward_to_inner {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            self.inner.$method(visitor)
+        }
+    )*};
+}
+
+/// Deserializer for a whole packet, which ends the fields of its top-level
+/// struct where the payload ends. Serde then defaults the missing ones, or
+/// fails on the first that has no default.
+struct MissingFields<'a, 'b, D> {
+    inner: D,
+    remaining: &'a Cell<&'b [u8]>,
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for MissingFields<'_, '_, D> {
+    type Error = D::Error;
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let visitor = MissingFields {
+            inner: visitor,
+            remaining: self.remaining,
+        };
+        self.inner.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_inner! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32
+        deserialize_u64 deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+        deserialize_option deserialize_unit deserialize_seq deserialize_map
+        deserialize_identifier deserialize_ignored_any
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for MissingFields<'_, '_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, fields: A) -> Result<Self::Value, A::Error> {
+        self.inner.visit_seq(MissingFields {
+            inner: fields,
+            remaining: self.remaining,
+        })
+    }
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for MissingFields<'_, '_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        if self.remaining.get().is_empty() {
+            return Ok(None);
+        }
+        self.inner.next_element_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl Display for WireFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireFormat::Bincode => write!(f, "bincode"),
+            WireFormat::MessagePack => write!(f, "messagepack"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Bincode(Box<bincode::ErrorKind>),
+    MessagePackEncode(rmp_serde::encode::Error),
+    MessagePackDecode(rmp_serde::decode::Error),
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Bincode(e) => write!(f, "bincode error: {}", e),
+            FormatError::MessagePackEncode(e) => write!(f, "messagepack encode error: {}", e),
+            FormatError::MessagePackDecode(e) => write!(f, "messagepack decode error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Before {
+        sequence: u16,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct After {
+        sequence: u16,
+        #[serde(default)]
+        volume: u8,
+    }
+
+    #[test]
+    fn should_prefer_messagepack() {
+        let both = WireFormat::Bincode.bit() | WireFormat::MessagePack.bit();
+        assert_eq!(WireFormat::negotiate(both), Some(WireFormat::MessagePack));
+        assert_eq!(
+            WireFormat::negotiate(WireFormat::Bincode.bit()),
+            Some(WireFormat::Bincode)
+        );
+        assert_eq!(WireFormat::negotiate(1 << 31), None);
+    }
+
+    #[test]
+    fn should_roundtrip_in_every_format() {
+        for format in WireFormat::ALL {
+            let value = After {
+                sequence: 7,
+                volume: 3,
+            };
+            let data = format.serialize(&value).unwrap();
+            assert_eq!(format.deserialize::<After>(&data).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn should_tolerate_added_fields_in_messagepack() {
+        let format = WireFormat::MessagePack;
+
+        let newer = format
+            .serialize(&After {
+                sequence: 7,
+                volume: 3,
+            })
+            .unwrap();
+        assert_eq!(
+            format.deserialize::<Before>(&newer).unwrap(),
+            Before { sequence: 7 },
+            "expected unknown fields to be skipped"
+        );
+
+        let older = format.serialize(&Before { sequence: 7 }).unwrap();
+        assert_eq!(
+            format.deserialize::<After>(&older).unwrap(),
+            After {
+                sequence: 7,
+                volume: 0
+            },
+            "expected missing fields to be defaulted"
+        );
+    }
+
+    #[test]
+    fn should_tolerate_added_fields_in_bincode() {
+        let format = WireFormat::Bincode;
+
+        let newer = format
+            .serialize(&After {
+                sequence: 7,
+                volume: 3,
+            })
+            .unwrap();
+        assert_eq!(
+            format.deserialize::<Before>(&newer).unwrap(),
+            Before { sequence: 7 }
+        );
+
+        let older = format.serialize(&Before { sequence: 7 }).unwrap();
+        assert_eq!(
+            format.deserialize::<After>(&older).unwrap(),
+            After {
+                sequence: 7,
+                volume: 0
+            }
+        );
+    }
+
+    #[test]
+    fn should_not_read_truncated_bincode() {
+        let truncated = WireFormat::Bincode
+            .serialize(&"a long enough string")
+            .unwrap();
+        assert!(matches!(
+            WireFormat::Bincode.deserialize::<String>(&truncated[..12]),
+            Err(FormatError::Bincode(_))
+        ));
+    }
+
+    #[test]
+    fn should_not_read_empty_bincode() {
+        assert!(matches!(
+            WireFormat::Bincode.deserialize::<After>(&[]),
+            Err(FormatError::Bincode(_))
+        ));
+    }
+
+    #[test]
+    fn should_not_default_missing_fields_without_serde_default() {
+        #[derive(Debug, Deserialize)]
+        struct Required {
+            _sequence: u16,
+            _volume: u8,
+        }
+
+        let older = WireFormat::Bincode
+            .serialize(&Before { sequence: 7 })
+            .unwrap();
+        assert!(matches!(
+            WireFormat::Bincode.deserialize::<Required>(&older),
+            Err(FormatError::Bincode(_))
+        ));
+    }
+}
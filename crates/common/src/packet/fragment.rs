@@ -0,0 +1,436 @@
+//! Splits messages too large for a single frame into [`FragmentPacket`]s and
+//! puts them back together on the other end.
+//!
+//! Transports opt in by framing with [`FragmentCodec`] instead of
+//! [`PacketCodec`]. Packets that fit in a frame, audio included, go through
+//! untouched, so only oversized messages pay for fragmentation.
+
+use super::{
+    codec::{CodecError, PacketCodec},
+    error::DecodeError,
+    ids::PacketId,
+    packet_type::PacketType,
+    FragmentPacket, Packet, MAX_PACKET_SIZE,
+};
+use bytes::{Bytes, BytesMut};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Bytes of a [`FragmentPacket`] payload taken up by everything but the chunk.
+const FRAGMENT_OVERHEAD: usize = 17;
+
+/// Largest chunk that still fits a [`FragmentPacket`] in a single frame.
+pub const FRAGMENT_CHUNK_SIZE: usize = MAX_PACKET_SIZE - FRAGMENT_OVERHEAD;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyConfig {
+    /// How long an incomplete message is kept before its fragments are dropped.
+    pub timeout: Duration,
+    /// Largest message that will be reassembled.
+    pub max_message_size: usize,
+    /// Cap on the bytes held by all incomplete messages together.
+    pub max_buffered: usize,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_message_size: 64 * 1024,
+            max_buffered: 256 * 1024,
+        }
+    }
+}
+
+/// Splits oversized packets, numbering each message so fragments of
+/// different messages can interleave on the wire.
+#[derive(Debug, Default)]
+pub struct Fragmenter {
+    next_message_id: u32,
+}
+
+impl Fragmenter {
+    /// Returns the frames to send for `packet`: the packet itself if it fits,
+    /// its fragments otherwise.
+    pub fn split(&mut self, packet: Packet) -> Result<Vec<Packet>, CodecError> {
+        if packet.data.len() <= MAX_PACKET_SIZE {
+            return Ok(vec![packet]);
+        }
+
+        let count =
+            u16::try_from(packet.data.len().div_ceil(FRAGMENT_CHUNK_SIZE)).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "message too large to fragment",
+                )
+            })?;
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+
+        packet
+            .data
+            .chunks(FRAGMENT_CHUNK_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| {
+                Packet::new(FragmentPacket {
+                    message_id,
+                    index: index as u16,
+                    count,
+                    packet_id: packet.packet_id,
+                    chunk: chunk.to_vec(),
+                })
+                .map_err(|e| CodecError::Io(std::io::Error::other(e)))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    packet_id: PacketId,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Collects fragments until their message is complete.
+#[derive(Debug)]
+pub struct Reassembler {
+    config: ReassemblyConfig,
+    pending: HashMap<u32, PartialMessage>,
+    buffered: usize,
+}
+
+impl Reassembler {
+    pub fn new(config: ReassemblyConfig) -> Self {
+        Self {
+            config,
+            pending: HashMap::new(),
+            buffered: 0,
+        }
+    }
+
+    /// Adds a fragment and returns the message it completes, if any.
+    pub fn push(&mut self, fragment: FragmentPacket) -> Result<Option<Packet>, DecodeError> {
+        self.push_at(fragment, Instant::now())
+    }
+
+    /// Number of messages still waiting for fragments.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn push_at(
+        &mut self,
+        fragment: FragmentPacket,
+        now: Instant,
+    ) -> Result<Option<Packet>, DecodeError> {
+        self.expire(now);
+
+        let count = fragment.count as usize;
+        let index = fragment.index as usize;
+        if index >= count {
+            return Err(DecodeError::BadFragment(format!(
+                "index {} out of {}",
+                index, count
+            )));
+        }
+
+        // Every chunk but the last is full, which bounds the message size up front.
+        let is_last = index == count - 1;
+        if (is_last && fragment.chunk.is_empty())
+            || fragment.chunk.len() > FRAGMENT_CHUNK_SIZE
+            || (!is_last && fragment.chunk.len() != FRAGMENT_CHUNK_SIZE)
+        {
+            return Err(DecodeError::BadFragment(format!(
+                "chunk of {} bytes at index {}",
+                fragment.chunk.len(),
+                index
+            )));
+        }
+        let min_size = (count - 1) * FRAGMENT_CHUNK_SIZE + 1;
+        if min_size > self.config.max_message_size {
+            return Err(DecodeError::MessageTooLarge(min_size));
+        }
+
+        let packet_id = match PacketId::from_u8(fragment.packet_id) {
+            Some(PacketId::FragmentPacket) => {
+                return Err(DecodeError::BadFragment("nested fragment".to_string()))
+            }
+            Some(packet_id) => packet_id,
+            None => return Err(DecodeError::UnknownPacketId(fragment.packet_id)),
+        };
+
+        let message = self
+            .pending
+            .entry(fragment.message_id)
+            .or_insert_with(|| PartialMessage {
+                packet_id,
+                chunks: vec![None; count],
+                received: 0,
+                bytes: 0,
+                started: now,
+            });
+        if message.packet_id != packet_id || message.chunks.len() != count {
+            return Err(DecodeError::BadFragment(format!(
+                "message {} changed shape",
+                fragment.message_id
+            )));
+        }
+        if message.chunks[index].is_some() {
+            return Ok(None);
+        }
+        if self.buffered + fragment.chunk.len() > self.config.max_buffered {
+            return Err(DecodeError::ReassemblyBufferFull);
+        }
+
+        self.buffered += fragment.chunk.len();
+        message.bytes += fragment.chunk.len();
+        message.received += 1;
+        message.chunks[index] = Some(fragment.chunk);
+        if message.received < count {
+            return Ok(None);
+        }
+
+        let message = self
+            .pending
+            .remove(&fragment.message_id)
+            .expect("message was just updated");
+        self.buffered -= message.bytes;
+
+        let mut data = Vec::with_capacity(message.bytes);
+        for chunk in message.chunks.into_iter().flatten() {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(Some(Packet::from_parts(
+            message.packet_id,
+            Bytes::from(data),
+        )))
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let timeout = self.config.timeout;
+        let mut expired = 0;
+        self.pending.retain(|_, message| {
+            let keep = now.duration_since(message.started) < timeout;
+            if !keep {
+                expired += message.bytes;
+            }
+            keep
+        });
+        self.buffered -= expired;
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(ReassemblyConfig::default())
+    }
+}
+
+/// [`PacketCodec`] with fragmentation on top: oversized packets are split on
+/// encode, and the decoder only yields whole messages.
+#[derive(Debug, Default)]
+pub struct FragmentCodec {
+    inner: PacketCodec,
+    fragmenter: Fragmenter,
+    reassembler: Reassembler,
+}
+
+impl FragmentCodec {
+    pub fn new(config: ReassemblyConfig) -> Self {
+        Self {
+            inner: PacketCodec,
+            fragmenter: Fragmenter::default(),
+            reassembler: Reassembler::new(config),
+        }
+    }
+}
+
+impl Decoder for FragmentCodec {
+    type Item = Packet;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, CodecError> {
+        while let Some(packet) = self.inner.decode(src)? {
+            if packet.packet_id != PacketId::FragmentPacket.to_u8() {
+                return Ok(Some(packet));
+            }
+
+            let fragment = FragmentPacket::decode(&packet.data)
+                .map_err(|e| CodecError::Decode(DecodeError::from(e)))?;
+            if let Some(message) = self
+                .reassembler
+                .push(fragment)
+                .map_err(CodecError::Decode)?
+            {
+                return Ok(Some(message));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Encoder<Packet> for FragmentCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> Result<(), CodecError> {
+        for frame in self.fragmenter.split(packet)? {
+            self.inner.encode(frame, dst)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{AudioPacket, ConnectPacket};
+
+    fn large_packet(size: usize) -> Packet {
+        Packet::from_parts(PacketId::SpeakerMapPacket, Bytes::from(vec![7; size]))
+    }
+
+    fn fragments(packet: Packet) -> Vec<FragmentPacket> {
+        Fragmenter::default()
+            .split(packet)
+            .unwrap()
+            .iter()
+            .map(|frame| FragmentPacket::decode(&frame.data).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn should_fit_full_chunk_in_one_frame() {
+        let packet = Packet::new(FragmentPacket {
+            chunk: vec![0; FRAGMENT_CHUNK_SIZE],
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(packet.data.len(), MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn should_not_fragment_small_packets() {
+        let packet = Packet::new(AudioPacket {
+            track: vec![1; 64],
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            Fragmenter::default().split(packet.clone()).unwrap(),
+            vec![packet]
+        );
+    }
+
+    #[test]
+    fn should_reassemble_out_of_order_fragments() {
+        let packet = large_packet(FRAGMENT_CHUNK_SIZE * 2 + 10);
+        let mut fragments = fragments(packet.clone());
+        assert_eq!(fragments.len(), 3);
+        fragments.reverse();
+
+        let mut reassembler = Reassembler::default();
+        let last = fragments.pop().unwrap();
+        for fragment in fragments {
+            assert_eq!(reassembler.push(fragment).unwrap(), None);
+        }
+        assert_eq!(reassembler.push(last).unwrap(), Some(packet));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.buffered, 0);
+    }
+
+    #[test]
+    fn should_drop_expired_messages() {
+        let mut reassembler = Reassembler::new(ReassemblyConfig {
+            timeout: Duration::from_millis(10),
+            ..Default::default()
+        });
+        let mut fragments = fragments(large_packet(MAX_PACKET_SIZE + 1));
+
+        let start = Instant::now();
+        reassembler.push_at(fragments.remove(0), start).unwrap();
+        assert_eq!(reassembler.pending(), 1);
+
+        let late = start + Duration::from_millis(20);
+        assert_eq!(
+            reassembler.push_at(fragments.remove(0), late).unwrap(),
+            None
+        );
+        assert_eq!(
+            reassembler.buffered,
+            MAX_PACKET_SIZE + 1 - FRAGMENT_CHUNK_SIZE
+        );
+    }
+
+    #[test]
+    fn should_enforce_memory_limits() {
+        let mut reassembler = Reassembler::new(ReassemblyConfig {
+            max_message_size: FRAGMENT_CHUNK_SIZE * 2,
+            max_buffered: FRAGMENT_CHUNK_SIZE,
+            ..Default::default()
+        });
+
+        let mut too_large = fragments(large_packet(FRAGMENT_CHUNK_SIZE * 3));
+        assert!(matches!(
+            reassembler.push(too_large.remove(0)),
+            Err(DecodeError::MessageTooLarge(_))
+        ));
+
+        let mut first = fragments(large_packet(MAX_PACKET_SIZE + 1));
+        let mut second = first.clone();
+        second
+            .iter_mut()
+            .for_each(|fragment| fragment.message_id = 1);
+        reassembler.push(first.remove(0)).unwrap();
+        assert_eq!(
+            reassembler.push(second.remove(0)),
+            Err(DecodeError::ReassemblyBufferFull)
+        );
+    }
+
+    #[test]
+    fn should_reject_inconsistent_fragments() {
+        let mut reassembler = Reassembler::default();
+        let mut fragments = fragments(large_packet(MAX_PACKET_SIZE + 1));
+        reassembler.push(fragments[0].clone()).unwrap();
+
+        fragments[1].count = 3;
+        fragments[1].chunk = vec![0; FRAGMENT_CHUNK_SIZE];
+        assert!(matches!(
+            reassembler.push(fragments[1].clone()),
+            Err(DecodeError::BadFragment(_))
+        ));
+
+        let nested = FragmentPacket {
+            count: 1,
+            packet_id: PacketId::FragmentPacket.to_u8(),
+            chunk: vec![0],
+            ..Default::default()
+        };
+        assert!(matches!(
+            reassembler.push(nested),
+            Err(DecodeError::BadFragment(_))
+        ));
+    }
+
+    #[test]
+    fn should_pass_through_codec() {
+        let small = Packet::new(ConnectPacket::default()).unwrap();
+        let large = large_packet(MAX_PACKET_SIZE * 3);
+        let mut codec = FragmentCodec::default();
+
+        let mut buffer = BytesMut::new();
+        codec.encode(large.clone(), &mut buffer).unwrap();
+        codec.encode(small.clone(), &mut buffer).unwrap();
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(large));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(small));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! Pins the wire format of every packet type.
+//!
+//! If one of these fails, the change breaks peers already deployed. Either
+//! revert it, or make it compatible: new fields go last and are
+//! `#[serde(default)]`, which both formats read without bumping
+//! [`PROTOCOL_VERSION`].
+
+use super::{format::WireFormat, ids::PacketId, packet_type::PacketType, *};
+use std::fmt::Debug;
+use uuid::Uuid;
+
+fn golden<P: PacketType + Debug + PartialEq>(
+    packet: P,
+    bincode: &[u8],
+    messagepack: &[u8],
+) -> PacketId {
+    for (format, bytes) in [
+        (WireFormat::Bincode, bincode),
+        (WireFormat::MessagePack, messagepack),
+    ] {
+        let name = std::any::type_name::<P>();
+        assert_eq!(
+            packet.encode_as(format).unwrap(),
+            bytes,
+            "{} no longer encodes to its golden {} bytes",
+            name,
+            format
+        );
+        assert_eq!(
+            P::decode_as(bytes, format).unwrap(),
+            packet,
+            "{} no longer decodes from its golden {} bytes",
+            name,
+            format
+        );
+    }
+
+    P::packet_id()
+}
+
+fn audio() -> AudioPacket {
+    AudioPacket {
+        sequence: 1,
+        timestamp: 480,
+        track: vec![1, 2, 255],
+    }
+}
+
+fn chat_message() -> RelayedChatPacket {
+    RelayedChatPacket {
+        sender: Uuid::from_u128(1),
+        display_name: "Teemo".to_string(),
+        sent_at: 1000,
+        text: "gg".to_string(),
+    }
+}
+
+fn summoner(champion: &str) -> Summoner {
+    Summoner {
+        riot_id: "Teemo#EUW".to_string(),
+        champion: Some(champion.to_string()),
+    }
+}
+
+#[test]
+fn should_match_golden_bytes_of_every_packet() {
+    let pinned = vec![
+        golden(
+            ConnectPacket {
+                protocol_version: 8,
+                capabilities: Capabilities::default(),
+                room: "lobby".to_string(),
+                display_name: "Teemo".to_string(),
+                summoner: None,
+            },
+            &[
+                8, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0,
+                0, 0, 108, 111, 98, 98, 121, 5, 0, 0, 0, 0, 0, 0, 0, 84, 101, 101, 109, 111, 0,
+            ],
+            b"\x85\xb0protocol_version\x08\xaccapabilities\x85\xa6codecs\x01\xaatransports\x01\
+              \xaaencryption\x01\xa8features\x00\xa7formats\x03\xa4room\xa5lobby\
+              \xacdisplay_name\xa5Teemo\xa8summoner\xc0",
+        ),
+        golden(DisconnectPacket, &[], b"\x90"),
+        golden(
+            audio(),
+            &[1, 0, 224, 1, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 255],
+            b"\x83\xa8sequence\x01\xa9timestamp\xcd\x01\xe0\xa5track\xc4\x03\x01\x02\xff",
+        ),
+        golden(
+            ConnectResponsePacket {
+                status: ConnectStatus::Accepted,
+                protocol_version: 8,
+                capabilities: Capabilities::default(),
+            },
+            &[
+                0, 0, 0, 0, 8, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0,
+            ],
+            b"\x83\xa6status\xa8Accepted\xb0protocol_version\x08\xaccapabilities\x85\
+              \xa6codecs\x01\xaatransports\x01\xaaencryption\x01\xa8features\x00\xa7formats\x03",
+        ),
+        golden(
+            RelayedAudioPacket {
+                speaker: 3,
+                audio: audio(),
+            },
+            &[3, 0, 1, 0, 224, 1, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 255],
+            b"\x82\xa7speaker\x03\xa5audio\
+              \x83\xa8sequence\x01\xa9timestamp\xcd\x01\xe0\xa5track\xc4\x03\x01\x02\xff",
+        ),
+        golden(
+            SpeakerMapPacket {
+                added: vec![Speaker {
+                    id: 3,
+                    client_id: Uuid::from_u128(1),
+                    display_name: "Teemo".to_string(),
+                }],
+                removed: vec![1],
+            },
+            &[
+                1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 1, 5, 0, 0, 0, 0, 0, 0, 0, 84, 101, 101, 109, 111, 1, 0, 0, 0, 0,
+                0, 0, 0, 1, 0,
+            ],
+            b"\x82\xa5added\x91\x83\xa2id\x03\xa9client_id\
+              \xc4\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\
+              \xacdisplay_name\xa5Teemo\xa7removed\x91\x01",
+        ),
+        golden(
+            PingPacket { timestamp: 42 },
+            &[42, 0, 0, 0, 0, 0, 0, 0],
+            b"\x81\xa9timestamp\x2a",
+        ),
+        golden(
+            PongPacket { timestamp: 42 },
+            &[42, 0, 0, 0, 0, 0, 0, 0],
+            b"\x81\xa9timestamp\x2a",
+        ),
+        golden(
+            ClosePacket::new(CloseReason::Kicked, "bye"),
+            &[1, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 98, 121, 101],
+            b"\x82\xa6reason\xa6Kicked\xa7message\xa3bye",
+        ),
+        golden(
+            FragmentPacket {
+                message_id: 1,
+                index: 0,
+                count: 2,
+                packet_id: 2,
+                chunk: vec![1, 2],
+            },
+            &[1, 0, 0, 0, 0, 0, 2, 0, 2, 2, 0, 0, 0, 0, 0, 0, 0, 1, 2],
+            b"\x85\xaamessage_id\x01\xa5index\x00\xa5count\x02\xa9packet_id\x02\
+              \xa5chunk\x92\x01\x02",
+        ),
+        golden(
+            RosterPacket::UserJoined(RosterUser {
+                client_id: Uuid::from_u128(1),
+                speaker: 3,
+                display_name: "Teemo".to_string(),
+                summoner: Some(summoner("Teemo")),
+                flags: 1,
+            }),
+            &[
+                1, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                1, 3, 0, 5, 0, 0, 0, 0, 0, 0, 0, 84, 101, 101, 109, 111, 1, 9, 0, 0, 0, 0, 0, 0, 0,
+                84, 101, 101, 109, 111, 35, 69, 85, 87, 1, 5, 0, 0, 0, 0, 0, 0, 0, 84, 101, 101,
+                109, 111, 1, 0, 0, 0,
+            ],
+            b"\x81\xaaUserJoined\x85\xa9client_id\
+              \xc4\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\
+              \xa7speaker\x03\xacdisplay_name\xa5Teemo\
+              \xa8summoner\x82\xa7riot_id\xa9Teemo#EUW\xa8champion\xa5Teemo\xa5flags\x01",
+        ),
+        golden(
+            UserUpdatePacket {
+                summoner: Some(Summoner {
+                    champion: None,
+                    ..summoner("")
+                }),
+                flags: 1,
+            },
+            &[
+                1, 9, 0, 0, 0, 0, 0, 0, 0, 84, 101, 101, 109, 111, 35, 69, 85, 87, 0, 1, 0, 0, 0,
+            ],
+            b"\x82\xa8summoner\x82\xa7riot_id\xa9Teemo#EUW\xa8champion\xc0\xa5flags\x01",
+        ),
+        golden(
+            SpeakingPacket { speaking: true },
+            &[1],
+            b"\x81\xa8speaking\xc3",
+        ),
+        golden(
+            RelayedSpeakingPacket {
+                speaker: 3,
+                speaking: true,
+            },
+            &[3, 0, 1],
+            b"\x82\xa7speaker\x03\xa8speaking\xc3",
+        ),
+        golden(
+            ChatPacket {
+                text: "gg".to_string(),
+            },
+            &[2, 0, 0, 0, 0, 0, 0, 0, 103, 103],
+            b"\x81\xa4text\xa2gg",
+        ),
+        golden(
+            chat_message(),
+            &[
+                16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 5, 0, 0,
+                0, 0, 0, 0, 0, 84, 101, 101, 109, 111, 232, 3, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0,
+                0, 0, 103, 103,
+            ],
+            b"\x84\xa6sender\
+              \xc4\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\
+              \xacdisplay_name\xa5Teemo\xa7sent_at\xcd\x03\xe8\xa4text\xa2gg",
+        ),
+        golden(
+            ChatHistoryPacket {
+                messages: vec![chat_message()],
+            },
+            &[
+                1, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 1, 5, 0, 0, 0, 0, 0, 0, 0, 84, 101, 101, 109, 111, 232, 3, 0, 0, 0, 0,
+                0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 103, 103,
+            ],
+            b"\x81\xa8messages\x91\x84\xa6sender\
+              \xc4\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\
+              \xacdisplay_name\xa5Teemo\xa7sent_at\xcd\x03\xe8\xa4text\xa2gg",
+        ),
+        golden(
+            CalloutPacket {
+                callout: Callout::Missing,
+                target: Some("mid".to_string()),
+            },
+            &[3, 0, 0, 0, 1, 3, 0, 0, 0, 0, 0, 0, 0, 109, 105, 100],
+            b"\x82\xa7callout\xa7Missing\xa6target\xa3mid",
+        ),
+        golden(
+            RelayedCalloutPacket {
+                speaker: 3,
+                callout: Callout::DragonSoon,
+                target: None,
+            },
+            &[3, 0, 4, 0, 0, 0, 0],
+            b"\x83\xa7speaker\x03\xa7callout\xaaDragonSoon\xa6target\xc0",
+        ),
+        golden(
+            UdpOfferPacket {
+                port: 1337,
+                token: Uuid::from_u128(1),
+            },
+            &[
+                57, 5, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+            ],
+            b"\x82\xa4port\xcd\x059\xa5token\xc4\x10\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01",
+        ),
+        golden(UdpProbePacket { attempt: 2 }, &[2], b"\x81\xa7attempt\x02"),
+        golden(
+            MeshHelloPacket {
+                protocol_version: 1,
+                member: Uuid::from_u128(1),
+                display_name: "Teemo".to_string(),
+                port: 1337,
+            },
+            &[
+                1, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 5,
+                0, 0, 0, 0, 0, 0, 0, 84, 101, 101, 109, 111, 57, 5,
+            ],
+            b"\x84\xb0protocol_version\x01\xa6member\xc4\x10\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\
+              \xacdisplay_name\xa5Teemo\xa4port\xcd\x059",
+        ),
+        golden(
+            MeshMembersPacket {
+                members: vec![MeshMember {
+                    member: Uuid::from_u128(1),
+                    addr: "10.0.0.2:1337".to_string(),
+                }],
+            },
+            &[
+                1, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 1, 13, 0, 0, 0, 0, 0, 0, 0, 49, 48, 46, 48, 46, 48, 46, 50, 58, 49, 51,
+                51, 55,
+            ],
+            b"\x81\xa7members\x91\x82\xa6member\xc4\x10\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\
+              \xa4addr\xad10.0.0.2:1337",
+        ),
+        golden(
+            RejectedPacket::new(
+                PacketId::ChatPacket,
+                RejectReason::ChatMessageTooLong,
+                "long",
+            ),
+            &[14, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 108, 111, 110, 103],
+            b"\x83\xa9packet_id\x0e\xa6reason\xb2ChatMessageTooLong\xa7message\xa4long",
+        ),
+        golden(UdpReadyPacket { ready: true }, &[1], b"\x81\xa5ready\xc3"),
+    ];
+
+    assert_eq!(
+        pinned,
+        PacketId::ALL,
+        "expected golden bytes for every registered packet"
+    );
+}
@@ -1,26 +1,73 @@
+use super::{packet_type::PacketType, *};
 use serde::{Deserialize, Serialize};
 
-#[repr(u8)]
-#[derive(Deserialize, Serialize, PartialEq, Hash, Eq, Debug, Clone)]
-pub enum PacketId {
-    ConnectPacket = 0,
-    DisconnectPacket = 1,
-    AudioPacket = 2,
+/// Declares every packet type along with its wire id.
+///
+/// This generates the [`PacketId`] enum, [`PacketId::from_u8`], [`PacketId::ALL`]
+/// and the [`PacketType`] impl of each packet struct. Ids are the enum's
+/// discriminants, so reusing one is a compile error.
+macro_rules! packets {
+    ($($id:literal => $packet:ident,)*) => {
+        #[repr(u8)]
+        #[derive(Deserialize, Serialize, PartialEq, Hash, Eq, Debug, Clone, Copy)]
+        pub enum PacketId {
+            $($packet = $id,)*
+        }
+
+        impl PacketId {
+            /// Every registered packet id, in declaration order.
+            pub const ALL: &'static [PacketId] = &[$(PacketId::$packet,)*];
+
+            pub fn from_u8(value: u8) -> Option<Self> {
+                match value {
+                    $($id => Some(PacketId::$packet),)*
+                    _ => None,
+                }
+            }
+        }
+
+        $(
+            impl PacketType for $packet {
+                fn packet_id() -> PacketId {
+                    PacketId::$packet
+                }
+            }
+        )*
+    };
+}
+
+packets! {
+    0 => ConnectPacket,
+    1 => DisconnectPacket,
+    2 => AudioPacket,
+    3 => ConnectResponsePacket,
+    4 => RelayedAudioPacket,
+    5 => SpeakerMapPacket,
+    6 => PingPacket,
+    7 => PongPacket,
+    8 => ClosePacket,
+    9 => FragmentPacket,
+    10 => RosterPacket,
+    11 => UserUpdatePacket,
+    12 => SpeakingPacket,
+    13 => RelayedSpeakingPacket,
+    14 => ChatPacket,
+    15 => RelayedChatPacket,
+    16 => ChatHistoryPacket,
+    17 => CalloutPacket,
+    18 => RelayedCalloutPacket,
+    19 => UdpOfferPacket,
+    20 => UdpProbePacket,
+    21 => MeshHelloPacket,
+    22 => MeshMembersPacket,
+    23 => RejectedPacket,
+    24 => UdpReadyPacket,
 }
 
 impl PacketId {
     pub fn to_u8(self) -> u8 {
         self as u8
     }
-
-    pub fn from_u8(value: u8) -> Option<Self> {
-        match value {
-            0 => Some(PacketId::ConnectPacket),
-            1 => Some(PacketId::DisconnectPacket),
-            2 => Some(PacketId::AudioPacket),
-            _ => None,
-        }
-    }
 }
 
 #[cfg(test)]
@@ -32,6 +79,28 @@ mod tests {
         assert_eq!(PacketId::ConnectPacket.to_u8(), 0);
         assert_eq!(PacketId::DisconnectPacket.to_u8(), 1);
         assert_eq!(PacketId::AudioPacket.to_u8(), 2);
+        assert_eq!(PacketId::ConnectResponsePacket.to_u8(), 3);
+        assert_eq!(PacketId::RelayedAudioPacket.to_u8(), 4);
+        assert_eq!(PacketId::SpeakerMapPacket.to_u8(), 5);
+        assert_eq!(PacketId::PingPacket.to_u8(), 6);
+        assert_eq!(PacketId::PongPacket.to_u8(), 7);
+        assert_eq!(PacketId::ClosePacket.to_u8(), 8);
+        assert_eq!(PacketId::FragmentPacket.to_u8(), 9);
+        assert_eq!(PacketId::RosterPacket.to_u8(), 10);
+        assert_eq!(PacketId::UserUpdatePacket.to_u8(), 11);
+        assert_eq!(PacketId::SpeakingPacket.to_u8(), 12);
+        assert_eq!(PacketId::RelayedSpeakingPacket.to_u8(), 13);
+        assert_eq!(PacketId::ChatPacket.to_u8(), 14);
+        assert_eq!(PacketId::RelayedChatPacket.to_u8(), 15);
+        assert_eq!(PacketId::ChatHistoryPacket.to_u8(), 16);
+        assert_eq!(PacketId::CalloutPacket.to_u8(), 17);
+        assert_eq!(PacketId::RelayedCalloutPacket.to_u8(), 18);
+        assert_eq!(PacketId::UdpOfferPacket.to_u8(), 19);
+        assert_eq!(PacketId::UdpProbePacket.to_u8(), 20);
+        assert_eq!(PacketId::MeshHelloPacket.to_u8(), 21);
+        assert_eq!(PacketId::MeshMembersPacket.to_u8(), 22);
+        assert_eq!(PacketId::RejectedPacket.to_u8(), 23);
+        assert_eq!(PacketId::UdpReadyPacket.to_u8(), 24);
     }
 
     #[test]
@@ -39,6 +108,34 @@ mod tests {
         assert_eq!(PacketId::from_u8(0), Some(PacketId::ConnectPacket));
         assert_eq!(PacketId::from_u8(1), Some(PacketId::DisconnectPacket));
         assert_eq!(PacketId::from_u8(2), Some(PacketId::AudioPacket));
-        assert_eq!(PacketId::from_u8(3), None);
+        assert_eq!(PacketId::from_u8(3), Some(PacketId::ConnectResponsePacket));
+        assert_eq!(PacketId::from_u8(4), Some(PacketId::RelayedAudioPacket));
+        assert_eq!(PacketId::from_u8(5), Some(PacketId::SpeakerMapPacket));
+        assert_eq!(PacketId::from_u8(6), Some(PacketId::PingPacket));
+        assert_eq!(PacketId::from_u8(9), Some(PacketId::FragmentPacket));
+        assert_eq!(PacketId::from_u8(11), Some(PacketId::UserUpdatePacket));
+        assert_eq!(PacketId::from_u8(13), Some(PacketId::RelayedSpeakingPacket));
+        assert_eq!(PacketId::from_u8(16), Some(PacketId::ChatHistoryPacket));
+        assert_eq!(PacketId::from_u8(18), Some(PacketId::RelayedCalloutPacket));
+        assert_eq!(PacketId::from_u8(20), Some(PacketId::UdpProbePacket));
+        assert_eq!(PacketId::from_u8(22), Some(PacketId::MeshMembersPacket));
+        assert_eq!(PacketId::from_u8(23), Some(PacketId::RejectedPacket));
+        assert_eq!(PacketId::from_u8(24), Some(PacketId::UdpReadyPacket));
+        assert_eq!(PacketId::from_u8(25), None);
+    }
+
+    #[test]
+    fn should_list_every_packet_id() {
+        assert_eq!(PacketId::ALL.len(), 25);
+        for (index, id) in PacketId::ALL.iter().enumerate() {
+            assert_eq!(PacketId::from_u8(id.to_u8()), Some(*id));
+            assert_eq!(id.to_u8() as usize, index);
+        }
+    }
+
+    #[test]
+    fn should_derive_packet_type_from_registry() {
+        assert_eq!(ConnectPacket::packet_id(), PacketId::ConnectPacket);
+        assert_eq!(FragmentPacket::packet_id(), PacketId::FragmentPacket);
     }
 }
@@ -1,21 +1,50 @@
+pub mod codec;
+pub mod dispatch;
 pub mod error;
+pub mod format;
+pub mod fragment;
+#[cfg(test)]
+mod golden;
 pub mod ids;
 pub mod packet_type;
 pub mod types;
 
-pub use types::{audio::AudioPacket, connect::ConnectPacket, disconnect::DisconnectPacket};
-
+pub use types::{
+    audio::{AudioPacket, RelayedAudioPacket},
+    callout::{Callout, CalloutPacket, RelayedCalloutPacket},
+    chat::{ChatHistoryPacket, ChatPacket, RelayedChatPacket},
+    close::{ClosePacket, CloseReason},
+    connect::{
+        Capabilities, ConnectPacket, ConnectResponsePacket, ConnectStatus, PROTOCOL_VERSION,
+    },
+    disconnect::DisconnectPacket,
+    fragment::FragmentPacket,
+    heartbeat::{PingPacket, PongPacket},
+    mesh::{MeshHelloPacket, MeshMember, MeshMembersPacket, MESH_PROTOCOL_VERSION},
+    rejected::{RejectReason, RejectedPacket},
+    roster::{RosterPacket, RosterUser, Summoner, UserUpdatePacket},
+    speaker::{Speaker, SpeakerId, SpeakerMapPacket},
+    speaking::{RelayedSpeakingPacket, SpeakingPacket},
+    udp::{UdpOfferPacket, UdpProbePacket, UdpReadyPacket},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
 use error::DecodeError;
+use format::{FormatError, WireFormat};
+use ids::PacketId;
 use packet_type::PacketType;
 use serde::{Deserialize, Serialize};
 
 pub const MAX_PACKET_SIZE: usize = 1024;
 
+/// Size of the `length` + `packet_id` prefix preceding every payload.
+pub const HEADER_SIZE: usize = 5;
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct Packet {
     pub length: u32,
     pub packet_id: u8,
-    pub data: Vec<u8>,
+    pub data: Bytes,
 }
 
 impl Packet {
@@ -24,10 +53,25 @@ impl Packet {
         Ok(Self {
             length: data.len() as u32,
             packet_id: P::packet_id().to_u8(),
-            data,
+            data: Bytes::from(data),
         })
     }
 
+    /// Like [`Packet::new`], but encodes the payload with `format`.
+    pub fn new_as<P: PacketType>(packet_type: P, format: WireFormat) -> Result<Self, FormatError> {
+        let data = packet_type.encode_as(format)?;
+        Ok(Self::from_parts(P::packet_id(), Bytes::from(data)))
+    }
+
+    /// Builds a packet around an already encoded payload without copying it.
+    pub fn from_parts(packet_id: PacketId, data: Bytes) -> Self {
+        Self {
+            length: data.len() as u32,
+            packet_id: packet_id.to_u8(),
+            data,
+        }
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut vec: Vec<u8> = Vec::new();
         vec.extend_from_slice(&self.length.to_be_bytes());
@@ -36,24 +80,44 @@ impl Packet {
         vec
     }
 
-    pub fn decode(buffer: &mut Vec<u8>) -> Result<Self, DecodeError> {
-        if buffer.len() < 5 {
-            return Err(DecodeError("Buffer is too small".to_string()));
+    /// Validates a frame header and returns the size of the whole frame.
+    ///
+    /// Oversized frames and unknown packet ids are rejected as soon as the
+    /// header is available, before any payload is buffered.
+    pub fn frame_length(buffer: &[u8]) -> Result<usize, DecodeError> {
+        if buffer.len() < HEADER_SIZE {
+            return Err(DecodeError::NeedMoreData);
         }
 
         let length = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
-        if buffer.len() < length + 5 {
-            return Err(DecodeError("Buffer is too small".to_string()));
+        if length > MAX_PACKET_SIZE {
+            return Err(DecodeError::FrameTooLarge(length));
         }
 
-        buffer.drain(0..4);
-        let packet_id = u8::from_be_bytes([buffer[0]]);
-        buffer.drain(0..1);
+        if PacketId::from_u8(buffer[4]).is_none() {
+            return Err(DecodeError::UnknownPacketId(buffer[4]));
+        }
+
+        Ok(HEADER_SIZE + length)
+    }
+
+    /// Splits the next frame off the front of `buffer`.
+    ///
+    /// The payload shares the buffer's allocation, so no bytes are moved or copied.
+    pub fn decode(buffer: &mut BytesMut) -> Result<Self, DecodeError> {
+        let frame_length = Self::frame_length(buffer)?;
+        if buffer.len() < frame_length {
+            return Err(DecodeError::NeedMoreData);
+        }
+
+        let mut frame = buffer.split_to(frame_length);
+        let length = frame.get_u32();
+        let packet_id = frame.get_u8();
 
         Ok(Self {
-            length: length as u32,
+            length,
             packet_id,
-            data: buffer.drain(..length).collect::<Vec<u8>>(),
+            data: frame.freeze(),
         })
     }
 }
@@ -70,41 +134,111 @@ mod tests {
 
     #[test]
     fn should_create_new_packet() {
-        let packet = Packet::new(ConnectPacket).unwrap();
-        assert_eq!(packet.length, 0);
+        let packet = Packet::new(ConnectPacket::default()).unwrap();
+        assert_eq!(packet.length, 39);
         assert_eq!(packet.packet_id, 0);
-        assert_eq!(packet.data, vec![]);
 
         let packet = Packet::new(DisconnectPacket).unwrap();
         assert_eq!(packet.length, 0);
         assert_eq!(packet.packet_id, 1);
         assert_eq!(packet.data, vec![]);
 
-        let packet = Packet::new(AudioPacket { track: vec![1] }).unwrap();
-        assert_eq!(packet.length, 9);
+        let packet = Packet::new(AudioPacket {
+            sequence: 1,
+            timestamp: 480,
+            track: vec![1],
+        })
+        .unwrap();
+        assert_eq!(packet.length, 15);
         assert_eq!(packet.packet_id, 2);
-        assert_eq!(packet.data, vec![1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(
+            packet.data,
+            vec![1, 0, 224, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
     }
 
     #[test]
     fn should_encode_and_encode_packet() {
-        let packet = Packet::new(ConnectPacket).unwrap();
-        assert_eq!(packet, Packet::decode(&mut packet.encode()).unwrap());
+        let packet = Packet::new(ConnectPacket::default()).unwrap();
+        let mut buffer = BytesMut::from(packet.encode().as_slice());
+        assert_eq!(packet, Packet::decode(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
     }
 
     #[test]
     fn test_packet_decode_small_buffer() {
-        assert!(Packet::decode(&mut vec![0, 0, 0]).is_err());
+        assert_eq!(
+            Packet::decode(&mut BytesMut::from(&[0, 0, 0][..])),
+            Err(DecodeError::NeedMoreData)
+        );
     }
 
     #[test]
     fn test_packet_decode_large_buffer() {
-        assert!(Packet::decode(&mut vec![0, 0, 0, 4, 0, 0]).is_err());
+        assert_eq!(
+            Packet::decode(&mut BytesMut::from(&[0, 0, 0, 4, 0, 0][..])),
+            Err(DecodeError::NeedMoreData)
+        );
+    }
+
+    #[test]
+    fn test_packet_decode_frame_too_large() {
+        let mut buffer = BytesMut::from(&[255, 255, 255, 255, 2][..]);
+        assert_eq!(
+            Packet::decode(&mut buffer),
+            Err(DecodeError::FrameTooLarge(u32::MAX as usize))
+        );
+        assert_eq!(buffer.len(), 5, "expected buffer to be left untouched");
+    }
+
+    #[test]
+    fn test_packet_decode_unknown_packet_id() {
+        assert_eq!(
+            Packet::decode(&mut BytesMut::from(&[0, 0, 0, 0, 255][..])),
+            Err(DecodeError::UnknownPacketId(255))
+        );
+    }
+
+    #[test]
+    fn should_decode_consecutive_frames_without_copying() {
+        let first = Packet::new(AudioPacket {
+            track: vec![1],
+            ..Default::default()
+        })
+        .unwrap();
+        let second = Packet::new(AudioPacket {
+            track: vec![2],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&first.encode());
+        buffer.extend_from_slice(&second.encode());
+        let start = buffer.as_ptr() as usize;
+
+        let decoded = Packet::decode(&mut buffer).unwrap();
+        assert_eq!(decoded, first);
+        assert_eq!(decoded.data.as_ptr() as usize, start + HEADER_SIZE);
+        assert_eq!(Packet::decode(&mut buffer).unwrap(), second);
+    }
+
+    #[test]
+    fn should_create_packet_from_parts() {
+        let packet = Packet::new(AudioPacket {
+            track: vec![1],
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            packet,
+            Packet::from_parts(PacketId::AudioPacket, packet.data.clone())
+        );
     }
 
     #[test]
     fn test_packet_to_vec() {
-        let packet = Packet::new(ConnectPacket).unwrap();
+        let packet = Packet::new(ConnectPacket::default()).unwrap();
         assert_eq!(packet.encode(), Vec::from(packet));
     }
 }
@@ -1,13 +1,30 @@
-use super::ids::PacketId;
+use super::{
+    format::{bincode_deserialize, FormatError, WireFormat},
+    ids::PacketId,
+};
 use serde::{de::DeserializeOwned, Serialize};
 
+/// A packet struct that can be sent on the wire.
+///
+/// Fields added to an existing packet go last and must be `#[serde(default)]`,
+/// so that peers can still read what older peers send in either format.
 pub trait PacketType: DeserializeOwned + Serialize {
+    /// Encodes with [`WireFormat::HANDSHAKE`], the only format both sides
+    /// understand before the connect exchange completes.
     fn encode(&self) -> Result<Vec<u8>, Box<bincode::ErrorKind>> {
         bincode::serialize(self)
     }
 
     fn decode(data: &[u8]) -> Result<Self, Box<bincode::ErrorKind>> {
-        bincode::deserialize(data)
+        bincode_deserialize(data)
+    }
+
+    fn encode_as(&self, format: WireFormat) -> Result<Vec<u8>, FormatError> {
+        format.serialize(self)
+    }
+
+    fn decode_as(data: &[u8], format: WireFormat) -> Result<Self, FormatError> {
+        format.deserialize(data)
     }
 
     fn packet_id() -> PacketId;
@@ -20,8 +37,20 @@ mod tests {
 
     #[test]
     fn should_encode_and_decode_packet_type() {
-        let packet_type = ConnectPacket;
+        let packet_type = ConnectPacket::default();
         let deserialized = PacketType::decode(&PacketType::encode(&packet_type).unwrap()).unwrap();
         assert_eq!(packet_type, deserialized);
     }
+
+    #[test]
+    fn should_encode_and_decode_packet_type_as_format() {
+        let packet_type = ConnectPacket::default();
+        for format in WireFormat::ALL {
+            let data = packet_type.encode_as(*format).unwrap();
+            assert_eq!(
+                ConnectPacket::decode_as(&data, *format).unwrap(),
+                packet_type
+            );
+        }
+    }
 }
@@ -1,13 +1,61 @@
-use crate::packet::{ids::PacketId, packet_type::PacketType};
+use super::speaker::SpeakerId;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default)]
+/// One encoded audio frame.
+///
+/// `sequence` increments by one per packet of a stream and `timestamp` counts
+/// samples at the codec clock rate since capture started. Both wrap around, so
+/// compare them with [`AudioPacket::sequence_delta`] rather than directly.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
 pub struct AudioPacket {
+    pub sequence: u16,
+    pub timestamp: u32,
+    /// Kept as a byte string so self-describing formats do not tag every byte.
+    #[serde(with = "serde_bytes")]
     pub track: Vec<u8>,
 }
 
-impl PacketType for AudioPacket {
-    fn packet_id() -> PacketId {
-        PacketId::AudioPacket
+impl AudioPacket {
+    /// Wrapping distance from `previous` to this packet's sequence number.
+    ///
+    /// Positive values mean this packet is newer, zero is a duplicate and
+    /// negative values mean it arrived out of order.
+    pub fn sequence_delta(&self, previous: u16) -> i16 {
+        self.sequence.wrapping_sub(previous) as i16
+    }
+}
+
+/// Audio relayed by the server, stamped with the speaker id of the client that sent it.
+///
+/// Only the server produces this packet; clients send plain [`AudioPacket`]s and
+/// never get to claim an identity themselves.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct RelayedAudioPacket {
+    pub speaker: SpeakerId,
+    pub audio: AudioPacket,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence: u16) -> AudioPacket {
+        AudioPacket {
+            sequence,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_compute_sequence_delta() {
+        assert_eq!(packet(5).sequence_delta(4), 1);
+        assert_eq!(packet(4).sequence_delta(4), 0);
+        assert_eq!(packet(3).sequence_delta(4), -1);
+    }
+
+    #[test]
+    fn should_compute_sequence_delta_across_wraparound() {
+        assert_eq!(packet(0).sequence_delta(u16::MAX), 1);
+        assert_eq!(packet(u16::MAX).sequence_delta(0), -1);
     }
 }
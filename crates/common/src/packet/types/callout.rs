@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::speaker::SpeakerId;
+
+/// Predefined quick callouts, bound to a key instead of said out loud.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub enum Callout {
+    #[default]
+    OnMyWay,
+    Help,
+    BackOff,
+    /// "Care, missing", the target naming the lane.
+    Missing,
+    DragonSoon,
+    BaronSoon,
+    HeraldSoon,
+}
+
+impl Callout {
+    pub const ALL: [Callout; 7] = [
+        Callout::OnMyWay,
+        Callout::Help,
+        Callout::BackOff,
+        Callout::Missing,
+        Callout::DragonSoon,
+        Callout::BaronSoon,
+        Callout::HeraldSoon,
+    ];
+
+    /// Least time between two of the same callout, whether sent or played.
+    pub const COOLDOWN: Duration = Duration::from_secs(3);
+}
+
+/// Callout a client sends to its room.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct CalloutPacket {
+    pub callout: Callout,
+    /// What the callout is about, like a lane or a champion.
+    pub target: Option<String>,
+}
+
+impl CalloutPacket {
+    /// Longest accepted target, in characters.
+    pub const MAX_TARGET_LENGTH: usize = 32;
+}
+
+/// A [`CalloutPacket`] relayed to everyone in the room, its sender included.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct RelayedCalloutPacket {
+    pub speaker: SpeakerId,
+    pub callout: Callout,
+    pub target: Option<String>,
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Text message a client sends to its room.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct ChatPacket {
+    pub text: String,
+}
+
+impl ChatPacket {
+    /// Longest accepted message, in characters.
+    pub const MAX_LENGTH: usize = 500;
+}
+
+/// A [`ChatPacket`] relayed to everyone in the room, its sender included.
+///
+/// Everything but the text is filled in by the server.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct RelayedChatPacket {
+    pub sender: Uuid,
+    /// Name of the sender when the message was sent; it may have left since.
+    pub display_name: String,
+    /// Milliseconds since the Unix epoch, by the server's clock.
+    pub sent_at: u64,
+    pub text: String,
+}
+
+/// Latest messages of a room, oldest first, sent once after joining.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct ChatHistoryPacket {
+    pub messages: Vec<RelayedChatPacket>,
+}
+
+impl ChatHistoryPacket {
+    /// Messages the server keeps per room.
+    pub const MAX_MESSAGES: usize = 50;
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// Machine-readable reason a peer is closing the connection.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum CloseReason {
+    ProtocolError,
+    Kicked,
+    Banned,
+    ServerShutdown,
+    VersionMismatch,
+    Timeout,
+}
+
+impl Display for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloseReason::ProtocolError => write!(f, "protocol error"),
+            CloseReason::Kicked => write!(f, "kicked"),
+            CloseReason::Banned => write!(f, "banned"),
+            CloseReason::ServerShutdown => write!(f, "server shutting down"),
+            CloseReason::VersionMismatch => write!(f, "protocol version mismatch"),
+            CloseReason::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+/// Last packet sent before a connection is torn down, telling the peer why.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone)]
+pub struct ClosePacket {
+    pub reason: CloseReason,
+    /// Human readable detail, meant to be shown to the user as is.
+    pub message: String,
+}
+
+impl ClosePacket {
+    pub fn new(reason: CloseReason, message: impl Into<String>) -> Self {
+        Self {
+            reason,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::packet_type::PacketType;
+
+    #[test]
+    fn should_roundtrip_close_packet() {
+        let packet = ClosePacket::new(CloseReason::Kicked, "bye");
+        let decoded = ClosePacket::decode(&packet.encode().unwrap()).unwrap();
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn should_display_close_reason() {
+        assert_eq!(
+            CloseReason::ServerShutdown.to_string(),
+            "server shutting down"
+        );
+    }
+}
@@ -1,11 +1,359 @@
-use crate::packet::{ids::PacketId, packet_type::PacketType};
+use super::roster::Summoner;
+use crate::packet::format::WireFormat;
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 
-#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default)]
-pub struct ConnectPacket;
+/// Version of the wire protocol spoken by this build.
+///
+/// Bump it only when a packet layout changes in a way older peers cannot
+/// read. New packets and new `#[serde(default)]` fields at the end of one
+/// are read by both sides, see [`PacketType`](crate::packet::packet_type::PacketType).
+pub const PROTOCOL_VERSION: u16 = 8;
 
-impl PacketType for ConnectPacket {
-    fn packet_id() -> PacketId {
-        PacketId::ConnectPacket
+/// Capability bitsets advertised by a peer during the connect exchange.
+///
+/// Unknown bits are ignored, so newer peers can advertise capabilities an older
+/// peer has never heard of without breaking the handshake.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct Capabilities {
+    pub codecs: u32,
+    pub transports: u32,
+    pub encryption: u32,
+    pub features: u32,
+    /// Payload serializations, see [`WireFormat::bit`].
+    pub formats: u32,
+}
+
+impl Capabilities {
+    pub const CODEC_OPUS: u32 = 1 << 0;
+
+    pub const TRANSPORT_TCP: u32 = 1 << 0;
+    /// Audio may also travel over UDP, see [`UdpOfferPacket`](super::udp::UdpOfferPacket).
+    pub const TRANSPORT_UDP: u32 = 1 << 1;
+
+    pub const ENCRYPTION_NONE: u32 = 1 << 0;
+
+    pub const fn empty() -> Self {
+        Self {
+            codecs: 0,
+            transports: 0,
+            encryption: 0,
+            features: 0,
+            formats: 0,
+        }
+    }
+
+    /// Returns the capabilities supported by both sides.
+    pub fn intersect(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            codecs: self.codecs & other.codecs,
+            transports: self.transports & other.transports,
+            encryption: self.encryption & other.encryption,
+            features: self.features & other.features,
+            formats: self.formats & other.formats,
+        }
+    }
+
+    pub fn has_transport(&self, transport: u32) -> bool {
+        self.transports & transport == transport
+    }
+
+    pub fn has_feature(&self, feature: u32) -> bool {
+        self.features & feature == feature
+    }
+
+    /// Payload format to use once these capabilities have been negotiated.
+    pub fn format(&self) -> Option<WireFormat> {
+        WireFormat::negotiate(self.formats)
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            codecs: Self::CODEC_OPUS,
+            transports: Self::TRANSPORT_TCP,
+            encryption: Self::ENCRYPTION_NONE,
+            features: 0,
+            formats: WireFormat::ALL
+                .iter()
+                .fold(0, |formats, format| formats | format.bit()),
+        }
+    }
+}
+
+/// First packet sent by a client.
+///
+/// `protocol_version` must stay the first field so that any future layout can
+/// still be read far enough to reject it cleanly.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone)]
+pub struct ConnectPacket {
+    pub protocol_version: u16,
+    pub capabilities: Capabilities,
+    /// Name of the room to join; clients in the same room hear each other.
+    pub room: String,
+    pub display_name: String,
+    /// Shown in the roster until the client sends a [`UserUpdatePacket`](super::roster::UserUpdatePacket).
+    #[serde(default)]
+    pub summoner: Option<Summoner>,
+}
+
+impl Default for ConnectPacket {
+    fn default() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            room: String::new(),
+            display_name: String::new(),
+            summoner: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum ConnectStatus {
+    Accepted,
+    UnsupportedVersion,
+    NoCommonCodec,
+    NoCommonTransport,
+    NoCommonEncryption,
+    RoomFull,
+    NoCommonFormat,
+}
+
+impl Display for ConnectStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectStatus::Accepted => write!(f, "accepted"),
+            ConnectStatus::UnsupportedVersion => write!(f, "unsupported protocol version"),
+            ConnectStatus::NoCommonCodec => write!(f, "no common audio codec"),
+            ConnectStatus::NoCommonTransport => write!(f, "no common transport"),
+            ConnectStatus::NoCommonEncryption => write!(f, "no common encryption scheme"),
+            ConnectStatus::RoomFull => write!(f, "room is full"),
+            ConnectStatus::NoCommonFormat => write!(f, "no common wire format"),
+        }
+    }
+}
+
+/// Server reply to a [`ConnectPacket`], naming the negotiated capabilities.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone)]
+pub struct ConnectResponsePacket {
+    pub status: ConnectStatus,
+    pub protocol_version: u16,
+    pub capabilities: Capabilities,
+}
+
+impl ConnectResponsePacket {
+    /// Negotiates a connect request against the capabilities supported locally.
+    ///
+    /// Clients newer than this build are accepted at [`PROTOCOL_VERSION`],
+    /// it is up to them whether they still speak it.
+    pub fn negotiate(request: &ConnectPacket, supported: &Capabilities) -> Self {
+        let capabilities = request.capabilities.intersect(supported);
+        let status = if request.protocol_version < PROTOCOL_VERSION {
+            ConnectStatus::UnsupportedVersion
+        } else if capabilities.codecs == 0 {
+            ConnectStatus::NoCommonCodec
+        } else if capabilities.transports == 0 {
+            ConnectStatus::NoCommonTransport
+        } else if capabilities.encryption == 0 {
+            ConnectStatus::NoCommonEncryption
+        } else if capabilities.format().is_none() {
+            ConnectStatus::NoCommonFormat
+        } else {
+            ConnectStatus::Accepted
+        };
+
+        match status {
+            ConnectStatus::Accepted => Self {
+                status,
+                protocol_version: PROTOCOL_VERSION,
+                capabilities,
+            },
+            _ => Self::rejected(status),
+        }
+    }
+
+    pub fn rejected(status: ConnectStatus) -> Self {
+        Self {
+            status,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::empty(),
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.status == ConnectStatus::Accepted
+    }
+
+    /// Whether the server answered with the version this build speaks.
+    pub fn is_supported_version(&self) -> bool {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::packet_type::PacketType;
+
+    #[test]
+    fn should_accept_matching_connect_packet() {
+        let response =
+            ConnectResponsePacket::negotiate(&ConnectPacket::default(), &Capabilities::default());
+        assert!(response.is_accepted());
+        assert_eq!(response.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(response.capabilities, Capabilities::default());
+    }
+
+    #[test]
+    fn should_reject_protocol_version_mismatch() {
+        let request = ConnectPacket {
+            protocol_version: PROTOCOL_VERSION - 1,
+            ..Default::default()
+        };
+        let response = ConnectResponsePacket::negotiate(&request, &Capabilities::default());
+        assert_eq!(response.status, ConnectStatus::UnsupportedVersion);
+        assert_eq!(response.capabilities, Capabilities::empty());
+    }
+
+    #[test]
+    fn should_accept_newer_clients_at_own_version() {
+        let request = ConnectPacket {
+            protocol_version: PROTOCOL_VERSION + 1,
+            ..Default::default()
+        };
+        let response = ConnectResponsePacket::negotiate(&request, &Capabilities::default());
+        assert!(response.is_accepted());
+        assert_eq!(response.protocol_version, PROTOCOL_VERSION);
+        assert!(response.is_supported_version());
+    }
+
+    #[test]
+    fn should_read_connect_packets_of_older_and_newer_peers() {
+        #[derive(Serialize)]
+        struct Older {
+            protocol_version: u16,
+            capabilities: Capabilities,
+            room: String,
+            display_name: String,
+        }
+        #[derive(Serialize)]
+        struct Newer {
+            packet: ConnectPacket,
+            added: Option<u32>,
+        }
+
+        let older = Older {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            room: "lobby".to_string(),
+            display_name: "Teemo".to_string(),
+        };
+        let packet = ConnectPacket::decode(&bincode::serialize(&older).unwrap()).unwrap();
+        assert_eq!(packet.room, "lobby");
+        assert_eq!(packet.summoner, None);
+
+        let newer = Newer {
+            packet: packet.clone(),
+            added: Some(7),
+        };
+        assert_eq!(
+            ConnectPacket::decode(&bincode::serialize(&newer).unwrap()).unwrap(),
+            packet
+        );
+    }
+
+    #[test]
+    fn should_not_read_empty_or_truncated_connect_packets() {
+        #[derive(Serialize)]
+        struct Truncated {
+            protocol_version: u16,
+            capabilities: Capabilities,
+            room: String,
+        }
+
+        assert!(ConnectPacket::decode(&[]).is_err());
+
+        let truncated = bincode::serialize(&Truncated {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            room: "lobby".to_string(),
+        })
+        .unwrap();
+        assert!(
+            ConnectPacket::decode(&truncated).is_err(),
+            "expected a missing display name to be refused"
+        );
+        assert!(
+            ConnectPacket::decode(&truncated[..truncated.len() - 2]).is_err(),
+            "expected a cut off room to be refused"
+        );
+    }
+
+    #[test]
+    fn should_reject_without_common_codec() {
+        let request = ConnectPacket {
+            capabilities: Capabilities {
+                codecs: 1 << 7,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = ConnectResponsePacket::negotiate(&request, &Capabilities::default());
+        assert_eq!(response.status, ConnectStatus::NoCommonCodec);
+    }
+
+    #[test]
+    fn should_reject_without_common_format() {
+        let request = ConnectPacket {
+            capabilities: Capabilities {
+                formats: 1 << 7,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = ConnectResponsePacket::negotiate(&request, &Capabilities::default());
+        assert_eq!(response.status, ConnectStatus::NoCommonFormat);
+    }
+
+    #[test]
+    fn should_negotiate_format() {
+        let request = ConnectPacket {
+            capabilities: Capabilities {
+                formats: WireFormat::Bincode.bit(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = ConnectResponsePacket::negotiate(&request, &Capabilities::default());
+        assert!(response.is_accepted());
+        assert_eq!(response.capabilities.format(), Some(WireFormat::Bincode));
+        assert_eq!(
+            Capabilities::default().format(),
+            Some(WireFormat::MessagePack)
+        );
+    }
+
+    #[test]
+    fn should_ignore_unknown_capabilities() {
+        let request = ConnectPacket {
+            capabilities: Capabilities {
+                features: 1 << 31,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = ConnectResponsePacket::negotiate(&request, &Capabilities::default());
+        assert!(response.is_accepted());
+        assert!(!response.capabilities.has_feature(1 << 31));
+    }
+
+    #[test]
+    fn should_display_connect_status() {
+        assert_eq!(
+            ConnectStatus::UnsupportedVersion.to_string(),
+            "unsupported protocol version"
+        );
     }
 }
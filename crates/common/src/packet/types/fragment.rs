@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+/// One chunk of a logical message too large to fit in a single frame.
+///
+/// See [`crate::packet::fragment`] for how messages are split and put back together.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct FragmentPacket {
+    pub message_id: u32,
+    pub index: u16,
+    pub count: u16,
+    /// Packet id of the reassembled message.
+    pub packet_id: u8,
+    pub chunk: Vec<u8>,
+}
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// Liveness probe, answered with a [`PongPacket`] echoing `timestamp`.
+///
+/// The timestamp is only meaningful to the sender, so peers never need
+/// synchronised clocks.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub struct PingPacket {
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub struct PongPacket {
+    pub timestamp: u64,
+}
+
+impl From<PingPacket> for PongPacket {
+    fn from(ping: PingPacket) -> Self {
+        Self {
+            timestamp: ping.timestamp,
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Version of the protocol members of a mesh speak to each other. It moves
+/// apart from [`PROTOCOL_VERSION`](super::connect::PROTOCOL_VERSION), as no
+/// server is ever part of a mesh.
+pub const MESH_PROTOCOL_VERSION: u16 = 1;
+
+/// Sent first by both ends of a connection between members of a mesh, where
+/// teammates talk to each other directly rather than through a server.
+///
+/// Members are told apart by `member`, picked at random when joining. The
+/// connection is dropped if the other end already is connected to it, or
+/// speaks another [`MESH_PROTOCOL_VERSION`].
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct MeshHelloPacket {
+    pub protocol_version: u16,
+    pub member: Uuid,
+    pub display_name: String,
+    /// Port the sender accepts other members on, at the address its
+    /// connections come from.
+    pub port: u16,
+}
+
+/// A member of a mesh as another one reaches it.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct MeshMember {
+    pub member: Uuid,
+    /// `ip:port` the member accepts connections on, at the IP the sender saw
+    /// its connection come from. Host names are not dialed.
+    pub addr: String,
+}
+
+/// Members the sender is connected to, sent after the hello and whenever
+/// another one joins, so the receiver connects to those it is not yet.
+///
+/// Of each pair of members, the one with the lower id connects, so two never
+/// connect to each other at once.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct MeshMembersPacket {
+    pub members: Vec<MeshMember>,
+}
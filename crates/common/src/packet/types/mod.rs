@@ -1,3 +1,14 @@
 pub mod audio;
+pub mod callout;
+pub mod chat;
+pub mod close;
 pub mod connect;
 pub mod disconnect;
+pub mod fragment;
+pub mod heartbeat;
+pub mod mesh;
+pub mod rejected;
+pub mod roster;
+pub mod speaker;
+pub mod speaking;
+pub mod udp;
@@ -0,0 +1,58 @@
+use crate::packet::ids::PacketId;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// Machine-readable reason a peer refused a packet without closing the
+/// connection over it.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum RejectReason {
+    ChatMessageTooLong,
+    CalloutTargetTooLong,
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::ChatMessageTooLong => write!(f, "chat message too long"),
+            RejectReason::CalloutTargetTooLong => write!(f, "callout target too long"),
+        }
+    }
+}
+
+/// Tells the peer one of its packets was dropped, and why.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone)]
+pub struct RejectedPacket {
+    /// Wire id of the packet that was dropped.
+    pub packet_id: u8,
+    pub reason: RejectReason,
+    /// Human readable detail, meant to be shown to the user as is.
+    pub message: String,
+}
+
+impl RejectedPacket {
+    pub fn new(packet_id: PacketId, reason: RejectReason, message: impl Into<String>) -> Self {
+        Self {
+            packet_id: packet_id.to_u8(),
+            reason,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::packet_type::PacketType;
+
+    #[test]
+    fn should_roundtrip_rejected_packet() {
+        let packet = RejectedPacket::new(
+            PacketId::ChatPacket,
+            RejectReason::ChatMessageTooLong,
+            "too long",
+        );
+        let decoded = RejectedPacket::decode(&packet.encode().unwrap()).unwrap();
+        assert_eq!(decoded, packet);
+        assert_eq!(decoded.packet_id, PacketId::ChatPacket.to_u8());
+    }
+}
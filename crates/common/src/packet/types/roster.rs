@@ -0,0 +1,60 @@
+use super::speaker::SpeakerId;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// League of Legends identity a user shares with the room.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct Summoner {
+    /// Riot id, `name#tag`.
+    pub riot_id: String,
+    /// Champion currently picked, if any.
+    pub champion: Option<String>,
+}
+
+/// Everything the room knows about one connected user.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct RosterUser {
+    pub client_id: Uuid,
+    /// Id the user's audio is relayed under.
+    pub speaker: SpeakerId,
+    pub display_name: String,
+    pub summoner: Option<Summoner>,
+    /// State bitset set by the user, see [`RosterUser::MUTED`] and
+    /// [`RosterUser::DEAFENED`]. Bits this build does not know are relayed as
+    /// they are.
+    pub flags: u32,
+}
+
+impl RosterUser {
+    /// The user's audio is not relayed to anyone.
+    pub const MUTED: u32 = 1 << 0;
+    /// No audio is relayed to the user.
+    pub const DEAFENED: u32 = 1 << 1;
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag == flag
+    }
+}
+
+/// Changes to the users of the room the receiver is in.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone)]
+pub enum RosterPacket {
+    /// Everyone in the room, the receiver included. Sent once after joining and
+    /// replaces whatever the receiver knew.
+    Snapshot(Vec<RosterUser>),
+    UserJoined(RosterUser),
+    UserLeft(Uuid),
+    /// Replaces the entry of a user already in the room.
+    UserUpdated(RosterUser),
+}
+
+/// Sent by a client to change how it appears in the roster.
+///
+/// Replaces the previous values as a whole, it is announced to the room as a
+/// [`RosterPacket::UserUpdated`].
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct UserUpdatePacket {
+    pub summoner: Option<Summoner>,
+    /// See [`RosterUser::flags`]; the server enforces the ones it knows.
+    pub flags: u32,
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Compact per-room id the server uses in place of a client's [`Uuid`] on audio frames.
+pub type SpeakerId = u16;
+
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct Speaker {
+    pub id: SpeakerId,
+    pub client_id: Uuid,
+    pub display_name: String,
+}
+
+/// Changes to the speaker id mapping of the room the receiver is in.
+///
+/// Removed ids may be handed to a different client afterwards, so receivers
+/// must drop any state they keep for them.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone)]
+pub struct SpeakerMapPacket {
+    pub added: Vec<Speaker>,
+    pub removed: Vec<SpeakerId>,
+}
@@ -0,0 +1,16 @@
+use super::speaker::SpeakerId;
+use serde::{Deserialize, Serialize};
+
+/// Sent by a client when its voice activity starts or stops.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub struct SpeakingPacket {
+    pub speaking: bool,
+}
+
+/// A [`SpeakingPacket`] relayed to the rest of the room, stamped with the
+/// speaker id of the client that sent it.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub struct RelayedSpeakingPacket {
+    pub speaker: SpeakerId,
+    pub speaking: bool,
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Sent by the server, once a client advertising
+/// [`Capabilities::TRANSPORT_UDP`](super::connect::Capabilities::TRANSPORT_UDP)
+/// has joined, to let it carry audio over UDP as well.
+///
+/// Datagrams go to `port` on the address the client connected to, each
+/// starting with `token`, which binds them to this connection. See
+/// [`crate::datagram`].
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct UdpOfferPacket {
+    pub port: u16,
+    pub token: Uuid,
+}
+
+/// Sent by the client over UDP after an offer, and echoed back by the server.
+///
+/// Audio only moves to UDP once an echo made it back, so clients behind a
+/// firewall dropping datagrams keep talking over TCP. Clients keep probing
+/// afterwards, so that datagrams going missing later on are noticed too.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub struct UdpProbePacket {
+    pub attempt: u8,
+}
+
+/// Sent by the client over TCP when audio moves to UDP, after a probe made it
+/// back, and when it moves back to TCP, after the echoes stopped.
+///
+/// The server only sends audio over UDP in between, so both ends always
+/// agree on where audio goes.
+#[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub struct UdpReadyPacket {
+    pub ready: bool,
+}
@@ -0,0 +1,267 @@
+//! QUIC as the transport of a connection, selected by a `quic://` address.
+//!
+//! The client opens a single bidirectional stream, which carries the same
+//! bytes a TCP connection would. Audio goes out as unreliable datagrams of
+//! the connection instead, one framed packet each, so a lost one holds back
+//! nothing. Connections survive the client's address changing, as when it
+//! moves to another network.
+//!
+//! TLS is part of QUIC: servers present a certificate, which clients check
+//! against the roots they were given rather than the platform's.
+//!
+//! [`QuicListener`] and [`QuicConnector`] make it a [`crate::transport`].
+
+use crate::{
+    capture::{Capture, Direction},
+    packet::{
+        codec::{CodecError, PacketCodec},
+        error::DecodeError,
+        Packet,
+    },
+    transport::{self, Connector, Incoming, Listener},
+};
+use bytes::{Bytes, BytesMut};
+use quinn::{
+    rustls::{self, client::VerifierBuilderError, RootCertStore},
+    ClientConfig, ConnectError, ConnectionError, SendDatagramError, ServerConfig,
+};
+use std::{
+    fmt::Display,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio_util::codec::Decoder;
+
+pub use quinn::{
+    rustls::pki_types::{CertificateDer, PrivateKeyDer},
+    Connection, Endpoint,
+};
+
+#[derive(Debug)]
+pub enum QuicError {
+    Io(io::Error),
+    /// The certificate or its key could not be used.
+    Tls(rustls::Error),
+    /// No roots to check server certificates against.
+    Verifier(VerifierBuilderError),
+    Connect(ConnectError),
+    Connection(ConnectionError),
+    /// The address did not resolve to anything.
+    Unresolved(String),
+}
+
+impl Display for QuicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuicError::Io(e) => write!(f, "io error: {}", e),
+            QuicError::Tls(e) => write!(f, "tls error: {}", e),
+            QuicError::Verifier(e) => write!(f, "cannot verify servers: {}", e),
+            QuicError::Connect(e) => write!(f, "failed to connect: {}", e),
+            QuicError::Connection(e) => write!(f, "connection failed: {}", e),
+            QuicError::Unresolved(addr) => write!(f, "{} does not resolve", addr),
+        }
+    }
+}
+
+impl std::error::Error for QuicError {}
+
+impl From<QuicError> for io::Error {
+    fn from(error: QuicError) -> Self {
+        match error {
+            QuicError::Io(e) => e,
+            e => io::Error::other(e),
+        }
+    }
+}
+
+impl From<io::Error> for QuicError {
+    fn from(error: io::Error) -> Self {
+        QuicError::Io(error)
+    }
+}
+
+impl From<rustls::Error> for QuicError {
+    fn from(error: rustls::Error) -> Self {
+        QuicError::Tls(error)
+    }
+}
+
+impl From<VerifierBuilderError> for QuicError {
+    fn from(error: VerifierBuilderError) -> Self {
+        QuicError::Verifier(error)
+    }
+}
+
+impl From<ConnectError> for QuicError {
+    fn from(error: ConnectError) -> Self {
+        QuicError::Connect(error)
+    }
+}
+
+impl From<ConnectionError> for QuicError {
+    fn from(error: ConnectionError) -> Self {
+        QuicError::Connection(error)
+    }
+}
+
+async fn resolve(addr: &str) -> Result<SocketAddr, QuicError> {
+    tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| QuicError::Unresolved(addr.to_string()))
+}
+
+pub struct QuicListener {
+    endpoint: Endpoint,
+}
+
+impl QuicListener {
+    /// Listens on `addr`, presenting the certificate `chain` to clients.
+    pub async fn bind(
+        addr: &str,
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, QuicError> {
+        let config = ServerConfig::with_single_cert(chain, key)?;
+        Ok(Self {
+            endpoint: Endpoint::server(config, resolve(addr).await?)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+}
+
+impl Listener for QuicListener {
+    async fn accept(&mut self) -> io::Result<Incoming> {
+        let incoming = self
+            .endpoint
+            .accept()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "endpoint is closed"))?;
+
+        Ok(Box::pin(async move {
+            let connection = incoming.await.map_err(QuicError::from)?;
+            // Opened along with the client's first packet.
+            let (send, recv) = connection.accept_bi().await.map_err(QuicError::from)?;
+            Ok(transport::Connection {
+                stream: Box::new(tokio::io::join(recv, send)),
+                peer: Some(connection.remote_address()),
+                quic: Some(connection),
+            })
+        }))
+    }
+}
+
+pub struct QuicConnector {
+    addr: String,
+    roots: Vec<CertificateDer<'static>>,
+}
+
+impl QuicConnector {
+    /// Connects to `addr`, see [`connect`].
+    pub fn new(addr: impl Into<String>, roots: Vec<CertificateDer<'static>>) -> Self {
+        Self {
+            addr: addr.into(),
+            roots,
+        }
+    }
+}
+
+impl Connector for QuicConnector {
+    async fn connect(&self) -> io::Result<transport::Connection> {
+        let (_endpoint, connection) = connect(&self.addr, &self.roots).await?;
+        let (send, recv) = connection.open_bi().await.map_err(QuicError::from)?;
+        Ok(transport::Connection {
+            stream: Box::new(tokio::io::join(recv, send)),
+            peer: Some(connection.remote_address()),
+            quic: Some(connection),
+        })
+    }
+}
+
+/// Connects to the server at `addr`, whose certificate has to chain up to
+/// one of `roots` and name the host part of `addr`.
+///
+/// The endpoint is only needed to change the local address later on, the
+/// connection keeps working once it is dropped.
+pub async fn connect(
+    addr: &str,
+    roots: &[CertificateDer<'static>],
+) -> Result<(Endpoint, Connection), QuicError> {
+    let server = resolve(addr).await?;
+    let mut store = RootCertStore::empty();
+    for root in roots {
+        store.add(root.clone())?;
+    }
+
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let mut endpoint = Endpoint::client(local)?;
+    endpoint.set_default_client_config(ClientConfig::with_root_certificates(Arc::new(store))?);
+    let connection = endpoint.connect(server, host(addr))?.await?;
+    Ok((endpoint, connection))
+}
+
+/// Host part of a `host:port` address, as certificates name it.
+pub fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Audio datagrams of a QUIC connection, see the [module docs](self).
+pub struct QuicDatagrams {
+    connection: Connection,
+    capture: Capture,
+}
+
+impl QuicDatagrams {
+    pub fn new(connection: Connection, capture: Capture) -> Self {
+        Self {
+            connection,
+            capture,
+        }
+    }
+
+    /// Sends `packet` as a datagram. Returns whether it was sent, which it
+    /// is not when too large for one or the peer takes none.
+    pub fn send(&self, packet: Packet) -> Result<bool, CodecError> {
+        self.capture.record(Direction::Sent, &packet);
+        match self.connection.send_datagram(Bytes::from(packet.encode())) {
+            Ok(()) => Ok(true),
+            Err(SendDatagramError::ConnectionLost(e)) => Err(io::Error::from(e).into()),
+            Err(_) => Ok(false),
+        }
+    }
+
+    pub async fn receive(&self) -> Result<Packet, CodecError> {
+        let datagram = self
+            .connection
+            .read_datagram()
+            .await
+            .map_err(io::Error::from)?;
+        let mut src = BytesMut::from(&datagram[..]);
+        let packet = match PacketCodec.decode(&mut src)? {
+            Some(packet) if src.is_empty() => packet,
+            _ => return Err(DecodeError::BadDatagram(datagram.len()).into()),
+        };
+        self.capture.record(Direction::Received, &packet);
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_name_servers_by_host() {
+        assert_eq!(host("localhost:8080"), "localhost");
+        assert_eq!(host("127.0.0.1:8080"), "127.0.0.1");
+        assert_eq!(host("[::1]:8080"), "::1");
+    }
+}
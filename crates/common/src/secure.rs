@@ -0,0 +1,585 @@
+//! Encryption of everything exchanged on a connection, underneath the packet
+//! framing.
+//!
+//! Before anything else, the client runs a `Noise_NX_25519_ChaChaPoly_BLAKE2s`
+//! handshake with the server, in which the server proves it holds its static
+//! key; clients may pin that key to refuse any other. Handshake messages are
+//! sent as a `u16` big endian length followed by the message.
+//!
+//! Each side then wraps its codec in a [`SecureCodec`], which seals whatever
+//! the inner codec writes into records:
+//!
+//! | bytes | content                                         |
+//! |-------|-------------------------------------------------|
+//! | 0..2  | length of the rest of the record, big endian    |
+//! | 2..10 | nonce, big endian                               |
+//! | 10..  | ciphertext, ending with its 16 byte tag         |
+//!
+//! Nonces count up from zero in each direction, or from [`DATAGRAM_NONCES`]
+//! for records sent as datagrams, so the two never reuse one under the same
+//! key. They travel with the record rather than being implied: on the stream
+//! each record must carry the nonce right after the previous one, while
+//! datagrams, which may be lost or reordered, are only refused once replayed
+//! or older than the [`ReplayWindow`]. Neither accepts a nonce of the other.
+
+use crate::packet::error::DecodeError;
+use bytes::{Buf, BufMut, BytesMut};
+use snow::{
+    params::{DHChoice, NoiseParams},
+    resolvers::{CryptoResolver, DefaultResolver},
+    Builder, StatelessTransportState,
+};
+use std::{fmt::Display, sync::Arc};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+pub const NOISE_PARAMS: &str = "Noise_NX_25519_ChaChaPoly_BLAKE2s";
+
+/// Mixed into the handshake, so it cannot be mistaken for another protocol's.
+const PROLOGUE: &[u8] = b"league-voice";
+
+const LENGTH_SIZE: usize = 2;
+const NONCE_SIZE: usize = 8;
+const TAG_SIZE: usize = 16;
+
+/// Largest plaintext sealed into a single record.
+pub const MAX_RECORD_PLAINTEXT: usize = u16::MAX as usize - NONCE_SIZE - TAG_SIZE;
+
+/// Bytes a record adds to the plaintext it seals.
+pub const RECORD_OVERHEAD: usize = LENGTH_SIZE + NONCE_SIZE + TAG_SIZE;
+
+/// First nonce of records sealed with [`Session::datagram_codec`].
+pub const DATAGRAM_NONCES: u64 = 1 << 63;
+
+/// Curve25519 public key, as pinned by clients.
+pub type PublicKey = [u8; 32];
+
+#[derive(Debug)]
+pub enum SecureError {
+    Io(std::io::Error),
+    Noise(snow::Error),
+    /// The server authenticated with another key than the pinned one.
+    KeyMismatch,
+}
+
+impl Display for SecureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecureError::Io(e) => write!(f, "io error: {}", e),
+            SecureError::Noise(e) => write!(f, "noise error: {}", e),
+            SecureError::KeyMismatch => write!(f, "server key does not match the pinned one"),
+        }
+    }
+}
+
+impl std::error::Error for SecureError {}
+
+impl From<std::io::Error> for SecureError {
+    fn from(error: std::io::Error) -> Self {
+        SecureError::Io(error)
+    }
+}
+
+impl From<snow::Error> for SecureError {
+    fn from(error: snow::Error) -> Self {
+        SecureError::Noise(error)
+    }
+}
+
+fn params() -> NoiseParams {
+    NOISE_PARAMS
+        .parse()
+        .expect("NOISE_PARAMS is a valid pattern")
+}
+
+/// Static key pair a server authenticates with.
+#[derive(Clone)]
+pub struct StaticKey {
+    private: [u8; 32],
+    public: PublicKey,
+}
+
+impl StaticKey {
+    pub fn generate() -> Result<Self, SecureError> {
+        let keypair = Builder::new(params()).generate_keypair()?;
+        Ok(Self {
+            private: to_key(&keypair.private)?,
+            public: to_key(&keypair.public)?,
+        })
+    }
+
+    pub fn from_private(private: [u8; 32]) -> Self {
+        let mut dh = DefaultResolver
+            .resolve_dh(&DHChoice::Curve25519)
+            .expect("the default resolver supports Curve25519");
+        dh.set(&private);
+        let mut public = [0; 32];
+        public.copy_from_slice(dh.pubkey());
+        Self { private, public }
+    }
+
+    pub fn private(&self) -> &[u8; 32] {
+        &self.private
+    }
+
+    pub fn public(&self) -> &PublicKey {
+        &self.public
+    }
+}
+
+impl std::fmt::Debug for StaticKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticKey")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+fn to_key(bytes: &[u8]) -> Result<[u8; 32], SecureError> {
+    bytes.try_into().map_err(|_| SecureError::KeyMismatch)
+}
+
+/// Keys agreed on by a completed handshake.
+#[derive(Clone)]
+pub struct Session {
+    transport: Arc<StatelessTransportState>,
+    remote_key: Option<PublicKey>,
+}
+
+impl Session {
+    /// Static key the server authenticated with, on the client side.
+    pub fn remote_key(&self) -> Option<&PublicKey> {
+        self.remote_key.as_ref()
+    }
+
+    /// Wraps `inner` to seal what it encodes and open what it decodes. Both
+    /// halves of a connection get their own codec from the same session.
+    pub fn codec<C>(&self, inner: C) -> SecureCodec<C> {
+        SecureCodec {
+            transport: Some(self.transport.clone()),
+            ..SecureCodec::plaintext(inner)
+        }
+    }
+
+    /// Like [`Session::codec`], for records sent as datagrams next to the
+    /// stream, see [`crate::datagram`].
+    pub fn datagram_codec<C>(&self, inner: C) -> SecureCodec<C> {
+        SecureCodec {
+            next_nonce: DATAGRAM_NONCES,
+            received: Received::Datagrams(ReplayWindow::default()),
+            ..self.codec(inner)
+        }
+    }
+}
+
+/// Runs the client side of the handshake. With a `pinned` key, servers
+/// authenticating with any other are refused.
+pub async fn initiate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    pinned: Option<&PublicKey>,
+) -> Result<Session, SecureError> {
+    let mut handshake = Builder::new(params())
+        .prologue(PROLOGUE)
+        .build_initiator()?;
+    let mut buffer = vec![0; u16::MAX as usize];
+
+    let length = handshake.write_message(&[], &mut buffer)?;
+    write_message(stream, &buffer[..length]).await?;
+    let message = read_message(stream).await?;
+    handshake.read_message(&message, &mut buffer)?;
+
+    let remote_key = to_key(handshake.get_remote_static().unwrap_or_default())?;
+    if pinned.is_some_and(|pinned| *pinned != remote_key) {
+        return Err(SecureError::KeyMismatch);
+    }
+
+    Ok(Session {
+        transport: Arc::new(handshake.into_stateless_transport_mode()?),
+        remote_key: Some(remote_key),
+    })
+}
+
+/// Runs the server side of the handshake, authenticating with `key`.
+pub async fn respond<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    key: &StaticKey,
+) -> Result<Session, SecureError> {
+    let mut handshake = Builder::new(params())
+        .prologue(PROLOGUE)
+        .local_private_key(&key.private)
+        .build_responder()?;
+    let mut buffer = vec![0; u16::MAX as usize];
+
+    let message = read_message(stream).await?;
+    handshake.read_message(&message, &mut buffer)?;
+    let length = handshake.write_message(&[], &mut buffer)?;
+    write_message(stream, &buffer[..length]).await?;
+
+    Ok(Session {
+        transport: Arc::new(handshake.into_stateless_transport_mode()?),
+        remote_key: None,
+    })
+}
+
+async fn write_message<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    message: &[u8],
+) -> Result<(), SecureError> {
+    stream.write_u16(message.len() as u16).await?;
+    stream.write_all(message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>, SecureError> {
+    let length = stream.read_u16().await?;
+    let mut message = vec![0; length as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+/// Remembers which of the latest 64 nonces were already received.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReplayWindow {
+    /// One past the highest nonce received.
+    next: u64,
+    /// Bit `n` is set once `next - 1 - n` has been received.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    const SIZE: u64 = u64::BITS as u64;
+
+    /// Whether `nonce` was neither received yet nor is too old to tell.
+    pub fn is_fresh(&self, nonce: u64) -> bool {
+        if nonce == u64::MAX {
+            // Reserved by Noise, never sent.
+            return false;
+        }
+        if nonce >= self.next {
+            return true;
+        }
+
+        let age = self.next - 1 - nonce;
+        age < Self::SIZE && self.seen & (1 << age) == 0
+    }
+
+    pub fn mark(&mut self, nonce: u64) {
+        if nonce >= self.next {
+            let shift = nonce - self.next + 1;
+            self.seen = if shift >= Self::SIZE {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.next = nonce + 1;
+            return;
+        }
+
+        let age = self.next - 1 - nonce;
+        if age < Self::SIZE {
+            self.seen |= 1 << age;
+        }
+    }
+}
+
+/// Nonces of the records a [`SecureCodec`] opened so far.
+#[derive(Debug)]
+enum Received {
+    /// The next nonce expected on the stream.
+    Stream(u64),
+    Datagrams(ReplayWindow),
+}
+
+impl Received {
+    fn check(&self, nonce: u64) -> Result<(), DecodeError> {
+        match self {
+            Received::Stream(next) if nonce < *next => Err(DecodeError::Replayed(nonce)),
+            Received::Stream(next) if nonce != *next || nonce >= DATAGRAM_NONCES => {
+                Err(DecodeError::UnexpectedNonce(nonce))
+            }
+            Received::Stream(_) => Ok(()),
+            Received::Datagrams(_) if nonce < DATAGRAM_NONCES => {
+                Err(DecodeError::UnexpectedNonce(nonce))
+            }
+            Received::Datagrams(window) if !window.is_fresh(nonce) => {
+                Err(DecodeError::Replayed(nonce))
+            }
+            Received::Datagrams(_) => Ok(()),
+        }
+    }
+
+    fn mark(&mut self, nonce: u64) {
+        match self {
+            Received::Stream(next) => *next = nonce + 1,
+            Received::Datagrams(window) => window.mark(nonce),
+        }
+    }
+}
+
+/// Seals and opens the bytes of an inner codec, see the [module docs](self).
+///
+/// Without a session it passes everything through as is, so connections can
+/// be set up the same way whether they are encrypted or not.
+#[derive(Debug)]
+pub struct SecureCodec<C> {
+    inner: C,
+    transport: Option<Arc<StatelessTransportState>>,
+    next_nonce: u64,
+    received: Received,
+    /// Opened bytes the inner codec has yet to decode.
+    plaintext: BytesMut,
+}
+
+impl<C> SecureCodec<C> {
+    pub fn plaintext(inner: C) -> Self {
+        Self {
+            inner,
+            transport: None,
+            next_nonce: 0,
+            received: Received::Stream(0),
+            plaintext: BytesMut::new(),
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.transport.is_some()
+    }
+}
+
+impl<C: Decoder> Decoder for SecureCodec<C>
+where
+    C::Error: From<DecodeError>,
+{
+    type Item = C::Item;
+    type Error = C::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<C::Item>, C::Error> {
+        let Some(transport) = &self.transport else {
+            return self.inner.decode(src);
+        };
+
+        loop {
+            if let Some(item) = self.inner.decode(&mut self.plaintext)? {
+                return Ok(Some(item));
+            }
+
+            if src.len() < LENGTH_SIZE {
+                return Ok(None);
+            }
+            let length = u16::from_be_bytes([src[0], src[1]]) as usize;
+            if length < NONCE_SIZE + TAG_SIZE {
+                return Err(DecodeError::Unauthenticated.into());
+            }
+            if src.len() < LENGTH_SIZE + length {
+                src.reserve(LENGTH_SIZE + length - src.len());
+                return Ok(None);
+            }
+
+            src.advance(LENGTH_SIZE);
+            let mut record = src.split_to(length);
+            let nonce = record.get_u64();
+            self.received.check(nonce)?;
+
+            let start = self.plaintext.len();
+            self.plaintext.resize(start + record.len() - TAG_SIZE, 0);
+            transport
+                .read_message(nonce, &record, &mut self.plaintext[start..])
+                .map_err(|_| DecodeError::Unauthenticated)?;
+            self.received.mark(nonce);
+        }
+    }
+}
+
+impl<I, C: Encoder<I>> Encoder<I> for SecureCodec<C> {
+    type Error = C::Error;
+
+    fn encode(&mut self, item: I, dst: &mut BytesMut) -> Result<(), C::Error> {
+        let Some(transport) = &self.transport else {
+            return self.inner.encode(item, dst);
+        };
+
+        let mut plaintext = BytesMut::new();
+        self.inner.encode(item, &mut plaintext)?;
+        for chunk in plaintext.chunks(MAX_RECORD_PLAINTEXT) {
+            let nonce = self.next_nonce;
+            self.next_nonce += 1;
+
+            let length = NONCE_SIZE + chunk.len() + TAG_SIZE;
+            dst.reserve(LENGTH_SIZE + length);
+            dst.put_u16(length as u16);
+            dst.put_u64(nonce);
+            let start = dst.len();
+            dst.resize(start + chunk.len() + TAG_SIZE, 0);
+            transport
+                .write_message(nonce, chunk, &mut dst[start..])
+                .map_err(std::io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{
+        codec::{CodecError, PacketCodec},
+        ConnectPacket, Packet,
+    };
+
+    async fn sessions(pinned: Option<PublicKey>) -> (Result<Session, SecureError>, Session) {
+        let key = StaticKey::generate().unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let server = tokio::spawn(async move { respond(&mut server, &key).await });
+        let client = initiate(&mut client, pinned.as_ref()).await;
+        (client, server.await.unwrap().unwrap())
+    }
+
+    fn packet() -> Packet {
+        Packet::new(ConnectPacket::default()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_exchange_packets_through_sealed_records() {
+        let (client, server) = sessions(None).await;
+        let client = client.unwrap();
+        let mut sender = client.codec(PacketCodec);
+        let mut receiver = server.codec(PacketCodec);
+
+        let mut wire = BytesMut::new();
+        sender.encode(packet(), &mut wire).unwrap();
+        sender.encode(packet(), &mut wire).unwrap();
+        let plaintext = packet().encode();
+        assert!(
+            !wire
+                .windows(plaintext.len())
+                .any(|bytes| bytes == plaintext),
+            "expected the packet to be encrypted"
+        );
+
+        // Trickle the bytes in, as a socket would.
+        let mut buffer = BytesMut::new();
+        let mut received = Vec::new();
+        for byte in wire {
+            buffer.put_u8(byte);
+            while let Some(packet) = receiver.decode(&mut buffer).unwrap() {
+                received.push(packet);
+            }
+        }
+        assert_eq!(received, vec![packet(), packet()]);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_replayed_and_tampered_records() {
+        let (client, server) = sessions(None).await;
+        let mut sender = client.unwrap().codec(PacketCodec);
+        let mut receiver = server.codec(PacketCodec);
+
+        let mut record = BytesMut::new();
+        sender.encode(packet(), &mut record).unwrap();
+        let mut replayed = record.clone();
+        receiver.decode(&mut record).unwrap().unwrap();
+        assert!(matches!(
+            receiver.decode(&mut replayed),
+            Err(CodecError::Decode(DecodeError::Replayed(0)))
+        ));
+
+        let mut tampered = BytesMut::new();
+        sender.encode(packet(), &mut tampered).unwrap();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(matches!(
+            receiver.decode(&mut tampered),
+            Err(CodecError::Decode(DecodeError::Unauthenticated))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_refuse_records_skipped_on_the_stream() {
+        let (client, server) = sessions(None).await;
+        let mut sender = client.unwrap().codec(PacketCodec);
+        let mut receiver = server.codec(PacketCodec);
+
+        let mut skipped = BytesMut::new();
+        sender.encode(packet(), &mut skipped).unwrap();
+        let mut record = BytesMut::new();
+        sender.encode(packet(), &mut record).unwrap();
+        assert!(matches!(
+            receiver.decode(&mut record),
+            Err(CodecError::Decode(DecodeError::UnexpectedNonce(1)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_refuse_records_of_the_other_channel() {
+        let (client, server) = sessions(None).await;
+        let client = client.unwrap();
+
+        let mut record = BytesMut::new();
+        client
+            .codec(PacketCodec)
+            .encode(packet(), &mut record)
+            .unwrap();
+        assert!(matches!(
+            server.datagram_codec(PacketCodec).decode(&mut record),
+            Err(CodecError::Decode(DecodeError::UnexpectedNonce(0)))
+        ));
+
+        let mut datagram = BytesMut::new();
+        client
+            .datagram_codec(PacketCodec)
+            .encode(packet(), &mut datagram)
+            .unwrap();
+        assert!(matches!(
+            server.codec(PacketCodec).decode(&mut datagram),
+            Err(CodecError::Decode(DecodeError::UnexpectedNonce(
+                DATAGRAM_NONCES
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_refuse_server_without_pinned_key() {
+        let (client, _) = sessions(Some(*StaticKey::generate().unwrap().public())).await;
+        assert!(matches!(client, Err(SecureError::KeyMismatch)));
+    }
+
+    #[tokio::test]
+    async fn should_accept_server_with_pinned_key() {
+        let key = StaticKey::generate().unwrap();
+        let pinned = *key.public();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move { respond(&mut server, &key).await });
+        let session = initiate(&mut client, Some(&pinned)).await.unwrap();
+        assert_eq!(session.remote_key(), Some(&pinned));
+    }
+
+    #[test]
+    fn should_derive_public_key_from_private() {
+        let key = StaticKey::generate().unwrap();
+        assert_eq!(
+            StaticKey::from_private(*key.private()).public(),
+            key.public()
+        );
+    }
+
+    #[test]
+    fn should_track_nonces_within_the_window() {
+        let mut window = ReplayWindow::default();
+        for nonce in [0, 2, 1, 70] {
+            assert!(window.is_fresh(nonce));
+            window.mark(nonce);
+            assert!(!window.is_fresh(nonce));
+        }
+
+        assert!(window.is_fresh(69));
+        assert!(
+            !window.is_fresh(2),
+            "expected nonces past the window to be refused"
+        );
+        assert!(!window.is_fresh(u64::MAX));
+    }
+}
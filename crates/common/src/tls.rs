@@ -0,0 +1,393 @@
+//! TLS around a connection's stream, selected by a `tls://` address.
+//!
+//! A lighter alternative to the handshake of [`crate::secure`], for servers
+//! that already hold a certificate. Clients check it in one of two ways, see
+//! [`Trust`]: against roots, by default those of the public CAs, or by pinning
+//! its [`Fingerprint`], as for the self-signed one of a community-hosted
+//! server.
+//!
+//! [`TlsListener`] and [`TlsConnector`] wrap the listener and connector of
+//! another [`crate::transport`], usually TCP. Audio stays on the stream, as
+//! datagrams next to it would go out in the clear.
+
+use crate::transport::{Connection, Connector, Incoming, Listener};
+use std::{fmt::Display, io, str::FromStr, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::{
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            VerifierBuilderError, WebPkiServerVerifier,
+        },
+        crypto::{self, CryptoProvider, WebPkiSupportedAlgorithms},
+        pki_types::{InvalidDnsNameError, ServerName, UnixTime},
+        CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig,
+        SignatureScheme,
+    },
+    TlsAcceptor,
+};
+
+pub use tokio_rustls::rustls::pki_types::{
+    pem::{self, PemObject},
+    CertificateDer, PrivateKeyDer,
+};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    /// The certificate or its key could not be used.
+    Tls(rustls::Error),
+    /// No roots to check server certificates against.
+    Verifier(VerifierBuilderError),
+    Pem(pem::Error),
+    /// The host is neither a DNS name nor an IP address.
+    ServerName(InvalidDnsNameError),
+    /// Not a SHA-256 fingerprint, as `AB:CD:...` or without the colons.
+    Fingerprint(String),
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::Io(e) => write!(f, "io error: {}", e),
+            TlsError::Tls(e) => write!(f, "tls error: {}", e),
+            TlsError::Verifier(e) => write!(f, "cannot verify servers: {}", e),
+            TlsError::Pem(e) => write!(f, "bad pem file: {}", e),
+            TlsError::ServerName(e) => write!(f, "bad server name: {}", e),
+            TlsError::Fingerprint(s) => write!(f, "{} is not a sha-256 fingerprint", s),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<io::Error> for TlsError {
+    fn from(error: io::Error) -> Self {
+        TlsError::Io(error)
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(error: rustls::Error) -> Self {
+        TlsError::Tls(error)
+    }
+}
+
+impl From<VerifierBuilderError> for TlsError {
+    fn from(error: VerifierBuilderError) -> Self {
+        TlsError::Verifier(error)
+    }
+}
+
+impl From<pem::Error> for TlsError {
+    fn from(error: pem::Error) -> Self {
+        TlsError::Pem(error)
+    }
+}
+
+impl From<InvalidDnsNameError> for TlsError {
+    fn from(error: InvalidDnsNameError) -> Self {
+        TlsError::ServerName(error)
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+/// SHA-256 of a certificate's DER encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of(cert: &CertificateDer<'_>) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, cert);
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest.as_ref());
+        Self(fingerprint)
+    }
+}
+
+/// Colon separated upper case hex, as `openssl x509 -fingerprint -sha256`
+/// prints it.
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = TlsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: Vec<u8> = s.bytes().filter(|&b| b != b':').collect();
+        let mut fingerprint = [0; 32];
+        if hex.len() != fingerprint.len() * 2 {
+            return Err(TlsError::Fingerprint(s.to_string()));
+        }
+        for (byte, pair) in fingerprint.iter_mut().zip(hex.chunks(2)) {
+            *byte = std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| TlsError::Fingerprint(s.to_string()))?;
+        }
+        Ok(Self(fingerprint))
+    }
+}
+
+/// Which server certificates a client accepts.
+#[derive(Debug, Clone, Default)]
+pub enum Trust {
+    /// Those a public CA issued for the server's host, as listed by the
+    /// Mozilla roots `webpki-roots` bundles.
+    #[default]
+    Public,
+    /// Those chaining up to one of these roots and naming the server's host.
+    Roots(Vec<CertificateDer<'static>>),
+    /// Only the one with this fingerprint, whatever it names or however long
+    /// it is valid for.
+    Pinned(Fingerprint),
+}
+
+impl Trust {
+    fn client_config(&self) -> Result<ClientConfig, TlsError> {
+        let provider = provider();
+        let verifier: Arc<dyn ServerCertVerifier> = match self {
+            Trust::Public => {
+                let store =
+                    RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+                WebPkiServerVerifier::builder_with_provider(Arc::new(store), provider.clone())
+                    .build()?
+            }
+            Trust::Roots(roots) => {
+                let mut store = RootCertStore::empty();
+                for root in roots {
+                    store.add(root.clone())?;
+                }
+                WebPkiServerVerifier::builder_with_provider(Arc::new(store), provider.clone())
+                    .build()?
+            }
+            Trust::Pinned(fingerprint) => Arc::new(PinnedVerifier {
+                fingerprint: *fingerprint,
+                algorithms: provider.signature_verification_algorithms,
+            }),
+        };
+
+        Ok(ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth())
+    }
+}
+
+/// Accepts the one certificate of [`Trust::Pinned`]. The handshake is still
+/// checked to be signed by its key.
+#[derive(Debug)]
+struct PinnedVerifier {
+    fingerprint: Fingerprint,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Fingerprint::of(end_entity) != self.fingerprint {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Terminates TLS on the connections `L` accepts.
+pub struct TlsListener<L> {
+    listener: L,
+    acceptor: TlsAcceptor,
+}
+
+impl<L: Listener> TlsListener<L> {
+    /// Presents the certificate `chain` to clients, signing with `key`.
+    pub fn new(
+        listener: L,
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, TlsError> {
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)?;
+        Ok(Self {
+            listener,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+}
+
+impl<L: Listener> Listener for TlsListener<L> {
+    async fn accept(&mut self) -> io::Result<Incoming> {
+        let incoming = self.listener.accept().await?;
+        let acceptor = self.acceptor.clone();
+        Ok(Box::pin(async move {
+            let Connection { stream, peer, .. } = incoming.await?;
+            let stream = acceptor.accept(stream).await?;
+            Ok(Connection::new(stream, peer))
+        }))
+    }
+}
+
+/// Runs a TLS handshake over the connections `C` opens.
+pub struct TlsConnector<C> {
+    connector: C,
+    server_name: ServerName<'static>,
+    tls: tokio_rustls::TlsConnector,
+}
+
+impl<C: Connector> TlsConnector<C> {
+    /// Expects the server to be `host`, with a certificate `trust` accepts.
+    pub fn new(connector: C, host: &str, trust: &Trust) -> Result<Self, TlsError> {
+        Ok(Self {
+            connector,
+            server_name: ServerName::try_from(host.to_string())?,
+            tls: tokio_rustls::TlsConnector::from(Arc::new(trust.client_config()?)),
+        })
+    }
+}
+
+impl<C: Connector + Sync> Connector for TlsConnector<C> {
+    async fn connect(&self) -> io::Result<Connection> {
+        let Connection { stream, peer, .. } = self.connector.connect().await?;
+        let stream = self.tls.connect(self.server_name.clone(), stream).await?;
+        Ok(Connection::new(stream, peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn certificate() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+        (certified.cert.der().clone(), key)
+    }
+
+    /// Sends a byte to a server presenting `cert`, with the client trusting
+    /// `trust`.
+    async fn exchange(
+        (cert, key): (CertificateDer<'static>, PrivateKeyDer<'static>),
+        trust: Trust,
+    ) -> io::Result<u8> {
+        let (listener, connector) = memory::channel();
+        let mut listener = TlsListener::new(listener, vec![cert], key).unwrap();
+        let connector = TlsConnector::new(connector, "localhost", &trust).unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut stream = listener.accept().await?.await?.stream;
+            stream.read_u8().await
+        });
+        let mut stream = connector.connect().await?.stream;
+        stream.write_u8(7).await?;
+        stream.flush().await?;
+        server.await.unwrap()
+    }
+
+    #[test]
+    fn should_read_fingerprints_as_printed() {
+        let (cert, _) = certificate();
+        let fingerprint = Fingerprint::of(&cert);
+        let printed = fingerprint.to_string();
+        assert_eq!(printed.len(), 32 * 3 - 1);
+        assert_eq!(printed.parse::<Fingerprint>().unwrap(), fingerprint);
+        assert_eq!(
+            printed
+                .replace(':', "")
+                .to_lowercase()
+                .parse::<Fingerprint>()
+                .unwrap(),
+            fingerprint
+        );
+        assert!(matches!(
+            printed[3..].parse::<Fingerprint>(),
+            Err(TlsError::Fingerprint(_))
+        ));
+        assert!(matches!(
+            "zz".repeat(32).parse::<Fingerprint>(),
+            Err(TlsError::Fingerprint(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_trust_roots_or_the_pinned_certificate() {
+        let (cert, key) = certificate();
+        let root = Trust::Roots(vec![cert.clone()]);
+        assert_eq!(
+            exchange((cert.clone(), key.clone_key()), root)
+                .await
+                .unwrap(),
+            7
+        );
+        let pinned = Trust::Pinned(Fingerprint::of(&cert));
+        assert_eq!(exchange((cert, key), pinned).await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_other_certificates() {
+        let (cert, _) = certificate();
+        let pinned = Trust::Pinned(Fingerprint::of(&cert));
+        let error = exchange(certificate(), pinned).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let error = exchange(certificate(), Trust::Roots(vec![cert]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(
+            TlsConnector::new(memory::channel().1, "localhost", &Trust::Roots(Vec::new())),
+            Err(TlsError::Verifier(_))
+        ));
+    }
+
+    #[test]
+    fn should_trust_public_roots_by_default() {
+        assert!(matches!(Trust::default(), Trust::Public));
+        TlsConnector::new(memory::channel().1, "example.com", &Trust::default()).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+//! Connections within the process, over a [`tokio::io::duplex`] pipe.
+//!
+//! Nothing is bound, so tests can run side by side without picking ports.
+
+use super::{Connection, Connector, Incoming, Listener};
+use std::io;
+use tokio::{io::DuplexStream, sync::mpsc};
+
+/// Bytes either end of a connection may write ahead of the other reading.
+pub const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Creates a listener along with a connector for it, which may be cloned
+/// to connect from several places.
+pub fn channel() -> (MemoryListener, MemoryConnector) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connector = MemoryConnector { tx };
+    let listener = MemoryListener {
+        rx,
+        _connector: connector.clone(),
+    };
+    (listener, connector)
+}
+
+/// Like a bound socket, waits for connections for as long as it exists,
+/// whether or not anyone is left to connect.
+pub struct MemoryListener {
+    rx: mpsc::UnboundedReceiver<DuplexStream>,
+    _connector: MemoryConnector,
+}
+
+impl Listener for MemoryListener {
+    async fn accept(&mut self) -> io::Result<Incoming> {
+        let stream = self
+            .rx
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "listener is closed"))?;
+        Ok(Box::pin(async move { Ok(Connection::new(stream, None)) }))
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryConnector {
+    tx: mpsc::UnboundedSender<DuplexStream>,
+}
+
+impl Connector for MemoryConnector {
+    async fn connect(&self) -> io::Result<Connection> {
+        let (client, server) = tokio::io::duplex(BUFFER_SIZE);
+        self.tx
+            .send(server)
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, "listener is gone"))?;
+        Ok(Connection::new(client, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn should_connect_both_ends() {
+        let (mut listener, connector) = channel();
+        let mut client = connector.connect().await.unwrap();
+        let mut server = listener.accept().await.unwrap().await.unwrap();
+
+        client.stream.write_all(b"ping").await.unwrap();
+        let mut buffer = [0; 4];
+        server.stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+    }
+
+    #[tokio::test]
+    async fn should_refuse_once_the_listener_is_gone() {
+        let (listener, connector) = channel();
+        drop(listener);
+        let error = connector.connect().await.err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
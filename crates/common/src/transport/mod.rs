@@ -0,0 +1,55 @@
+//! Transports that connections run over, so the server and client do not
+//! depend on any one of them.
+//!
+//! A [`Listener`] accepts connections and a [`Connector`] opens one; either
+//! way, each comes down to a [`Connection`], whose stream carries framed
+//! packets just as TCP does. Besides [`tcp`], there is an in-memory
+//! [`memory`] transport, and QUIC in [`crate::quic`]. [`crate::tls`] layers
+//! onto any of them.
+
+use std::{future::Future, io, net::SocketAddr, pin::Pin};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub mod memory;
+pub mod tcp;
+
+/// Byte stream of a connection, whichever transport it runs over.
+pub trait PacketStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> PacketStream for S {}
+
+/// One connection, ready to carry packets.
+pub struct Connection {
+    pub stream: Box<dyn PacketStream>,
+    /// Address of the other end, for transports that have one, where
+    /// datagrams may be sent to.
+    pub peer: Option<SocketAddr>,
+    /// QUIC connection the stream belongs to, whose datagrams carry audio.
+    pub quic: Option<crate::quic::Connection>,
+}
+
+impl Connection {
+    pub fn new(stream: impl PacketStream + 'static, peer: Option<SocketAddr>) -> Self {
+        Self {
+            stream: Box::new(stream),
+            peer,
+            quic: None,
+        }
+    }
+}
+
+/// Setup of an accepted connection that may still take a few round trips,
+/// such as a handshake. Awaited apart from the listener, so one slow peer
+/// does not hold back the next.
+pub type Incoming = Pin<Box<dyn Future<Output = io::Result<Connection>> + Send>>;
+
+/// Accepts the connections of a transport.
+pub trait Listener: Send + 'static {
+    /// Waits for the next connection. An error means the listener is done.
+    fn accept(&mut self) -> impl Future<Output = io::Result<Incoming>> + Send;
+}
+
+/// Opens connections over a transport, to wherever it was pointed at.
+pub trait Connector {
+    fn connect(&self) -> impl Future<Output = io::Result<Connection>> + Send;
+}
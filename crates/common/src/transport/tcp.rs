@@ -0,0 +1,49 @@
+use super::{Connection, Connector, Incoming, Listener};
+use std::{io, net::SocketAddr};
+use tokio::net::TcpStream;
+
+fn connection(stream: TcpStream) -> io::Result<Connection> {
+    // Frames are tiny and latency sensitive, so do not let Nagle hold them back.
+    stream.set_nodelay(true)?;
+    let peer = stream.peer_addr()?;
+    Ok(Connection::new(stream, Some(peer)))
+}
+
+pub struct TcpListener {
+    listener: tokio::net::TcpListener,
+}
+
+impl TcpListener {
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        Ok(Self {
+            listener: tokio::net::TcpListener::bind(addr).await?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl Listener for TcpListener {
+    async fn accept(&mut self) -> io::Result<Incoming> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(Box::pin(async move { connection(stream) }))
+    }
+}
+
+pub struct TcpConnector {
+    addr: String,
+}
+
+impl TcpConnector {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into() }
+    }
+}
+
+impl Connector for TcpConnector {
+    async fn connect(&self) -> io::Result<Connection> {
+        connection(TcpStream::connect(&self.addr).await?)
+    }
+}
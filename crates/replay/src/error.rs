@@ -0,0 +1,32 @@
+use common::{
+    capture::CaptureError,
+    packet::{codec::CodecError, format::FormatError},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("{0}")]
+    Usage(String),
+
+    #[error("{0}")]
+    Capture(#[from] CaptureError),
+
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Codec(#[from] CodecError),
+
+    #[error("failed to decode packet type: {0}")]
+    PacketFormat(#[from] FormatError),
+
+    #[error("capture has no accepted connect response to take the wire format from")]
+    MissingConnectResponse,
+
+    #[error("opus error: {0}")]
+    Opus(#[from] opus::Error),
+
+    #[error("wav error: {0}")]
+    Wav(#[from] hound::Error),
+}
@@ -0,0 +1,56 @@
+//! Replays capture files written by the client and server, see
+//! [`common::capture`].
+//!
+//! ```text
+//! replay play <capture> [addr]   send the client's packets to a server again
+//! replay wav <capture> [out-dir] decode the audio streams into WAV files
+//! ```
+
+use common::capture::{CaptureReader, Direction, Side};
+use error::ReplayError;
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+mod error;
+mod play;
+mod wav;
+
+const USAGE: &str = "usage: replay play <capture> [addr] | replay wav <capture> [out-dir]";
+
+#[tokio::main]
+async fn main() -> Result<(), ReplayError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, path) = match args.as_slice() {
+        [command, path, ..] => (command.as_str(), path),
+        _ => return Err(ReplayError::Usage(USAGE.to_string())),
+    };
+    let capture = CaptureReader::new(BufReader::new(File::open(path)?))?;
+
+    match command {
+        "play" => {
+            let addr = args
+                .get(2)
+                .cloned()
+                .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+            let sent = play::play(capture, &addr).await?;
+            println!("Replayed {} packets to {}", sent, addr);
+        }
+        "wav" => {
+            let out_dir = args.get(2).map(PathBuf::from).unwrap_or_default();
+            for path in wav::extract(capture, &out_dir)? {
+                println!("Wrote {}", path.display());
+            }
+        }
+        _ => return Err(ReplayError::Usage(USAGE.to_string())),
+    }
+
+    Ok(())
+}
+
+/// Whether a record travelled from the client to the server, whichever side
+/// recorded it.
+fn from_client(side: Side, direction: Direction) -> bool {
+    matches!(
+        (side, direction),
+        (Side::Client, Direction::Sent) | (Side::Server, Direction::Received)
+    )
+}
@@ -0,0 +1,110 @@
+use crate::{error::ReplayError, from_client};
+use common::{
+    capture::CaptureReader,
+    packet::{fragment::FragmentCodec, ids::PacketId},
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{io::Read, time::Duration};
+use tokio::{net::TcpStream, time::Instant};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+/// How long to keep listening for the server once everything has been sent.
+const LINGER: Duration = Duration::from_secs(1);
+
+/// Sends the packets the client sent in `capture` to the server at `addr`,
+/// keeping their original timing, and prints what the server answers.
+///
+/// Returns the number of packets sent.
+pub async fn play<R: Read>(capture: CaptureReader<R>, addr: &str) -> Result<usize, ReplayError> {
+    let side = capture.side();
+    let stream = TcpStream::connect(addr).await?;
+    let (read, write) = stream.into_split();
+    let mut reader = FramedRead::new(read, FragmentCodec::default());
+    let mut writer = FramedWrite::new(write, FragmentCodec::default());
+
+    let read_handle = tokio::spawn(async move {
+        while let Some(Ok(packet)) = reader.next().await {
+            match PacketId::from_u8(packet.packet_id) {
+                Some(id) => println!("<- {:?} ({} bytes)", id, packet.data.len()),
+                None => println!("<- unknown packet {}", packet.packet_id),
+            }
+        }
+    });
+
+    let started = Instant::now();
+    let mut sent = 0;
+    for record in capture {
+        let record = record?;
+        if !from_client(side, record.direction) {
+            continue;
+        }
+
+        tokio::time::sleep_until(started + record.elapsed).await;
+        writer.send(record.packet).await?;
+        sent += 1;
+    }
+
+    // A timeout only means the server kept the connection open.
+    let _ = tokio::time::timeout(LINGER, read_handle).await;
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        capture::{CaptureWriter, Direction, Side},
+        packet::{
+            packet_type::PacketType, ConnectPacket, ConnectResponsePacket, Packet, PingPacket,
+            PongPacket,
+        },
+    };
+    use std::io::Cursor;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn should_replay_packets_sent_by_the_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut writer = CaptureWriter::new(Vec::new(), Side::Server).unwrap();
+        let connect = Packet::new(ConnectPacket::default()).unwrap();
+        let pong = Packet::new(PongPacket { timestamp: 7 }).unwrap();
+        writer.record(Direction::Received, &connect).unwrap();
+        writer
+            .record(
+                Direction::Sent,
+                &Packet::new(ConnectResponsePacket::rejected(
+                    common::packet::ConnectStatus::RoomFull,
+                ))
+                .unwrap(),
+            )
+            .unwrap();
+        writer
+            .record(
+                Direction::Sent,
+                &Packet::new(PingPacket { timestamp: 7 }).unwrap(),
+            )
+            .unwrap();
+        writer.record(Direction::Received, &pong).unwrap();
+        let capture = CaptureReader::new(Cursor::new(writer.into_inner())).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut reader = FramedRead::new(socket, FragmentCodec::default());
+            let mut received = Vec::new();
+            while let Some(Ok(packet)) = reader.next().await {
+                received.push(packet);
+            }
+            received
+        });
+
+        assert_eq!(play(capture, &addr).await.unwrap(), 2);
+        let received = server.await.unwrap();
+        assert_eq!(received, vec![connect, pong]);
+        assert_eq!(
+            PongPacket::decode(&received[1].data).unwrap(),
+            PongPacket { timestamp: 7 }
+        );
+    }
+}
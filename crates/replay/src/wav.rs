@@ -0,0 +1,231 @@
+use crate::{error::ReplayError, from_client};
+use common::{
+    capture::CaptureReader,
+    packet::{
+        format::WireFormat, ids::PacketId, packet_type::PacketType, AudioPacket,
+        ConnectResponsePacket, RelayedAudioPacket,
+    },
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use opus::{Channels, Decoder};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Read},
+    path::{Path, PathBuf},
+};
+
+/// Rate and channel count audio is encoded with, see the client's opus codec.
+const SAMPLE_RATE: u32 = 48000;
+/// Largest opus frame, 120 ms.
+const MAX_FRAME_SIZE: usize = SAMPLE_RATE as usize / 1000 * 120;
+/// Longest silence inserted for a gap in timestamps, so a bogus timestamp
+/// cannot produce hours of nothing.
+const MAX_GAP: u32 = SAMPLE_RATE * 5;
+
+/// One decoded audio stream and the file it is written to.
+struct Track {
+    decoder: Decoder,
+    writer: WavWriter<BufWriter<File>>,
+    /// Timestamp the next packet is expected at if nothing is lost.
+    next_timestamp: Option<u32>,
+}
+
+impl Track {
+    fn create(path: &Path) -> Result<Self, ReplayError> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+
+        Ok(Self {
+            decoder: Decoder::new(SAMPLE_RATE, Channels::Mono)?,
+            writer: WavWriter::create(path, spec)?,
+            next_timestamp: None,
+        })
+    }
+
+    fn push(&mut self, audio: &AudioPacket) -> Result<(), ReplayError> {
+        if let Some(next) = self.next_timestamp {
+            let gap = audio.timestamp.wrapping_sub(next);
+            // Anything "negative" is a reordered packet, played as it comes.
+            if gap < u32::MAX / 2 {
+                for _ in 0..gap.min(MAX_GAP) {
+                    self.writer.write_sample(0.0f32)?;
+                }
+            }
+        }
+
+        let mut samples = [0.0; MAX_FRAME_SIZE];
+        let decoded = self
+            .decoder
+            .decode_float(&audio.track, &mut samples, false)?;
+        for &sample in &samples[..decoded] {
+            self.writer.write_sample(sample)?;
+        }
+        self.next_timestamp = Some(audio.timestamp.wrapping_add(decoded as u32));
+
+        Ok(())
+    }
+}
+
+/// Decodes every audio stream in `capture` into a WAV file in `out_dir`:
+/// `local.wav` for the audio the client sent and `speaker-<id>.wav` for each
+/// speaker the server relayed. Returns the files written.
+///
+/// Gaps in timestamps are filled with silence so streams stay aligned with
+/// what was heard.
+pub fn extract<R: Read>(
+    capture: CaptureReader<R>,
+    out_dir: &Path,
+) -> Result<Vec<PathBuf>, ReplayError> {
+    let side = capture.side();
+    let mut format = None;
+    let mut tracks: HashMap<PathBuf, Track> = HashMap::new();
+
+    for record in capture {
+        let record = record?;
+        let packet = &record.packet;
+        let client_sent = from_client(side, record.direction);
+
+        let (path, audio) = match (PacketId::from_u8(packet.packet_id), client_sent) {
+            (Some(PacketId::ConnectResponsePacket), false) => {
+                let response =
+                    ConnectResponsePacket::decode_as(&packet.data, WireFormat::HANDSHAKE)?;
+                if response.is_accepted() {
+                    format = response.capabilities.format();
+                }
+                continue;
+            }
+            (Some(PacketId::AudioPacket), true) => {
+                let format = format.ok_or(ReplayError::MissingConnectResponse)?;
+                (
+                    out_dir.join("local.wav"),
+                    AudioPacket::decode_as(&packet.data, format)?,
+                )
+            }
+            (Some(PacketId::RelayedAudioPacket), false) => {
+                let format = format.ok_or(ReplayError::MissingConnectResponse)?;
+                let relayed = RelayedAudioPacket::decode_as(&packet.data, format)?;
+                (
+                    out_dir.join(format!("speaker-{}.wav", relayed.speaker)),
+                    relayed.audio,
+                )
+            }
+            _ => continue,
+        };
+
+        if !tracks.contains_key(&path) {
+            let track = Track::create(&path)?;
+            tracks.insert(path.clone(), track);
+        }
+        tracks.get_mut(&path).unwrap().push(&audio)?;
+    }
+
+    let mut paths = Vec::with_capacity(tracks.len());
+    for (path, track) in tracks {
+        track.writer.finalize()?;
+        paths.push(path);
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        capture::{CaptureWriter, Direction, Side},
+        packet::{Capabilities, ConnectPacket, Packet},
+    };
+    use hound::WavReader;
+    use opus::{Application, Encoder};
+    use std::io::Cursor;
+
+    const FRAME: usize = 960;
+
+    fn audio(encoder: &mut Encoder, sequence: u16) -> AudioPacket {
+        AudioPacket {
+            sequence,
+            timestamp: sequence as u32 * FRAME as u32,
+            track: encoder.encode_vec_float(&[0.25; FRAME], 4000).unwrap(),
+        }
+    }
+
+    #[test]
+    fn should_decode_audio_streams_to_wav() {
+        let format = WireFormat::MessagePack;
+        let out_dir = std::env::temp_dir().join(format!("replay-wav-{}", std::process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Audio).unwrap();
+
+        let mut writer = CaptureWriter::new(Vec::new(), Side::Client).unwrap();
+        let mut record = |direction, packet| writer.record(direction, &packet).unwrap();
+        record(
+            Direction::Sent,
+            Packet::new(ConnectPacket::default()).unwrap(),
+        );
+        record(
+            Direction::Received,
+            Packet::new(ConnectResponsePacket::negotiate(
+                &ConnectPacket::default(),
+                &Capabilities::default(),
+            ))
+            .unwrap(),
+        );
+        record(
+            Direction::Sent,
+            Packet::new_as(audio(&mut encoder, 0), format).unwrap(),
+        );
+        // Sequence 1 was lost, its frame comes out as silence.
+        record(
+            Direction::Sent,
+            Packet::new_as(audio(&mut encoder, 2), format).unwrap(),
+        );
+        record(
+            Direction::Received,
+            Packet::new_as(
+                RelayedAudioPacket {
+                    speaker: 3,
+                    audio: audio(&mut encoder, 0),
+                },
+                format,
+            )
+            .unwrap(),
+        );
+        let capture = CaptureReader::new(Cursor::new(writer.into_inner())).unwrap();
+
+        let paths = extract(capture, &out_dir).unwrap();
+        assert_eq!(
+            paths,
+            vec![out_dir.join("local.wav"), out_dir.join("speaker-3.wav")]
+        );
+
+        let local = WavReader::open(&paths[0]).unwrap();
+        assert_eq!(local.spec().sample_rate, SAMPLE_RATE);
+        assert_eq!(local.len() as usize, FRAME * 3);
+        let speaker = WavReader::open(&paths[1]).unwrap();
+        assert_eq!(speaker.len() as usize, FRAME);
+
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn should_require_connect_response_for_audio() {
+        let mut writer = CaptureWriter::new(Vec::new(), Side::Server).unwrap();
+        writer
+            .record(
+                Direction::Received,
+                &Packet::new(AudioPacket::default()).unwrap(),
+            )
+            .unwrap();
+        let capture = CaptureReader::new(Cursor::new(writer.into_inner())).unwrap();
+
+        assert!(matches!(
+            extract(capture, Path::new(".")),
+            Err(ReplayError::MissingConnectResponse)
+        ));
+    }
+}
@@ -1,3 +1,11 @@
+use common::{
+    packet::{
+        codec::CodecError, error::DecodeError, format::FormatError, CloseReason, ConnectStatus,
+    },
+    quic::QuicError,
+    secure::SecureError,
+    tls::TlsError,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -5,8 +13,8 @@ pub enum ServerError {
     #[error("failed to join: {0}")]
     JoinError(#[from] tokio::task::JoinError),
 
-    #[error("failed to process packet: Buffer overflow: possible protocol error")]
-    FailedToProcessPacket,
+    #[error("{0}")]
+    DecodeError(#[from] DecodeError),
 
     #[error("connection closed by peer")]
     ConnectionClosedByPeer,
@@ -26,6 +34,112 @@ pub enum ServerError {
     #[error("failed to decode packet type: {0}")]
     FailedToDecodePacketType(#[from] Box<bincode::ErrorKind>),
 
+    #[error("failed to serialize packet type: {0}")]
+    PacketFormat(#[from] FormatError),
+
     #[error("failed to send to client")]
     ClientSendError,
+
+    #[error("client is not keeping up with its packets")]
+    ClientBacklogged,
+
+    #[error("quic error: {0}")]
+    Quic(#[from] QuicError),
+
+    #[error("unsupported address scheme: {0}")]
+    UnsupportedScheme(String),
+
+    #[error("serving quic requires a certificate")]
+    QuicCertificateMissing,
+
+    #[error("tls error: {0}")]
+    Tls(#[from] TlsError),
+
+    #[error("serving tls requires a certificate")]
+    TlsCertificateMissing,
+
+    #[error("secure handshake failed: {0}")]
+    SecureHandshake(#[from] SecureError),
+
+    #[error("expected a connect packet before any other packet")]
+    HandshakeRequired,
+
+    #[error("connect packet sent after the handshake completed")]
+    HandshakeRepeated,
+
+    #[error("rejected client during handshake: {0}")]
+    HandshakeRejected(ConnectStatus),
+
+    #[error("peer missed too many heartbeats")]
+    HeartbeatTimeout,
+}
+
+impl From<CodecError> for ServerError {
+    fn from(error: CodecError) -> Self {
+        match error {
+            CodecError::Io(e) => ServerError::IoError(e),
+            CodecError::Decode(e) => ServerError::DecodeError(e),
+        }
+    }
+}
+
+impl ServerError {
+    /// Reason to tell the client before dropping it over this error, if it is
+    /// still worth telling it anything.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        match self {
+            ServerError::DecodeError(_)
+            | ServerError::HandlerNotFound
+            | ServerError::InvalidPacket
+            | ServerError::InvalidHandlerPacketId
+            | ServerError::FailedToDecodePacketType(_)
+            | ServerError::PacketFormat(_)
+            | ServerError::HandshakeRequired
+            | ServerError::HandshakeRepeated => Some(CloseReason::ProtocolError),
+            ServerError::HandshakeRejected(ConnectStatus::UnsupportedVersion) => {
+                Some(CloseReason::VersionMismatch)
+            }
+            ServerError::HeartbeatTimeout => Some(CloseReason::Timeout),
+            // Other rejections are already explained by the connect response,
+            // and the rest mean the connection itself is gone.
+            ServerError::HandshakeRejected(_)
+            | ServerError::JoinError(_)
+            | ServerError::ConnectionClosedByPeer
+            | ServerError::SecureHandshake(_)
+            | ServerError::Quic(_)
+            | ServerError::UnsupportedScheme(_)
+            | ServerError::QuicCertificateMissing
+            | ServerError::Tls(_)
+            | ServerError::TlsCertificateMissing
+            | ServerError::IoError(_)
+            | ServerError::ClientSendError
+            | ServerError::ClientBacklogged => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_map_errors_to_close_reasons() {
+        assert_eq!(
+            ServerError::HandlerNotFound.close_reason(),
+            Some(CloseReason::ProtocolError)
+        );
+        assert_eq!(
+            ServerError::DecodeError(DecodeError::FrameTooLarge(4096)).close_reason(),
+            Some(CloseReason::ProtocolError)
+        );
+        assert_eq!(
+            ServerError::HandshakeRejected(ConnectStatus::UnsupportedVersion).close_reason(),
+            Some(CloseReason::VersionMismatch)
+        );
+        assert_eq!(
+            ServerError::HandshakeRejected(ConnectStatus::RoomFull).close_reason(),
+            None
+        );
+        assert_eq!(ServerError::ConnectionClosedByPeer.close_reason(), None);
+    }
 }
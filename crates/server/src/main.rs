@@ -1,8 +1,14 @@
-use common::packet::ids::PacketId;
+use common::{
+    address::Scheme,
+    heartbeat::HeartbeatConfig,
+    quic::{self, CertificateDer, PrivateKeyDer},
+    secure::StaticKey,
+    tls::{pem, Fingerprint, PemObject, TlsError},
+};
 use error::ServerError;
 use packets::handlers;
 use server::{tokio::TokioServer, Server};
-use std::borrow::Cow;
+use std::{borrow::Cow, io::Write, path::Path, str::FromStr, time::Duration};
 
 mod error;
 mod packets;
@@ -15,17 +21,186 @@ async fn main() -> Result<(), ServerError> {
         .unwrap_or_else(|| "127.0.0.1:8080".to_string());
 
     let mut server = TokioServer::new();
-    server.add_handler(
-        PacketId::ConnectPacket,
-        Box::new(handlers::connect::ConnectHandler {}),
-    );
-    server.add_handler(
-        PacketId::AudioPacket,
-        Box::new(handlers::audio::AudioHandler(server.clients().clone())),
-    );
-    server.add_handler(
-        PacketId::DisconnectPacket,
-        Box::new(handlers::disconnect::DisconnectHandler {}),
-    );
-    server.run(Cow::Borrowed(&addr)).await
+    server.set_heartbeat(heartbeat_config());
+    if let Ok(dir) = std::env::var("CAPTURE_DIR") {
+        println!("Capturing connections to {}", dir);
+        server.set_capture_dir(dir);
+    }
+    if let Ok(path) = std::env::var("STATIC_KEY_FILE") {
+        let key = load_static_key(Path::new(&path))?;
+        let public: String = key.public().iter().map(|b| format!("{:02x}", b)).collect();
+        println!("Encrypting connections, public key: {}", public);
+        server.set_static_key(key);
+    }
+    if let Ok(websocket_addr) = std::env::var("WEBSOCKET_ADDR") {
+        server.set_websocket_addr(websocket_addr);
+    }
+    if let Some((Scheme::Quic, quic_addr)) = Scheme::split(&addr) {
+        let (chain, key) = load_quic_certificate(quic_addr)?;
+        server.set_quic_certificate(chain, key);
+    }
+    if let Some((Scheme::Tls, tls_addr)) = Scheme::split(&addr) {
+        let (chain, key) = load_tls_certificate(tls_addr)?;
+        println!(
+            "Serving TLS, certificate fingerprint: {}",
+            Fingerprint::of(&chain[0])
+        );
+        server.set_tls_certificate(chain, key);
+    }
+    handlers::register(&mut server);
+
+    let clients = server.clients();
+    tokio::select! {
+        result = server.run(Cow::Borrowed(&addr)) => result,
+        _ = tokio::signal::ctrl_c() => {
+            println!("Shutting down");
+            TokioServer::shutdown(&clients).await;
+            Ok(())
+        }
+    }
+}
+
+/// Reads the server's private key from `path`, generating one there on first start.
+fn load_static_key(path: &Path) -> Result<StaticKey, ServerError> {
+    match std::fs::read(path) {
+        Ok(private) => {
+            let private = private.try_into().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "static key file must hold exactly 32 bytes",
+                )
+            })?;
+            Ok(StaticKey::from_private(private))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key = StaticKey::generate()?;
+            write_private(path, key.private())?;
+            Ok(key)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes `contents` to a new file at `path` that only its owner may read.
+fn write_private(path: impl AsRef<Path>, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+/// Reads a certificate and its key, or `None` if neither exists yet.
+///
+/// Only one of them existing is an error rather than a reason to generate
+/// both, as the new pair would not match the file that is left.
+fn read_certificate_files(
+    cert_path: &str,
+    key_path: &str,
+) -> std::io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let read = |path: &str| match std::fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    };
+    let missing = |missing: &str, present: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "{} is missing while {} exists, remove it to generate both",
+                missing, present
+            ),
+        )
+    };
+
+    match (read(cert_path)?, read(key_path)?) {
+        (Some(cert), Some(key)) => Ok(Some((cert, key))),
+        (None, None) => Ok(None),
+        (Some(_), None) => Err(missing(key_path, cert_path)),
+        (None, Some(_)) => Err(missing(cert_path, key_path)),
+    }
+}
+
+/// Reads the QUIC certificate and its key from `QUIC_CERT_FILE` and
+/// `QUIC_KEY_FILE`, both DER encoded. On first start, a self-signed one for
+/// `localhost` and the host of `addr` is generated there, which clients
+/// then have to be given as a root.
+fn load_quic_certificate(
+    addr: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), ServerError> {
+    let cert_path = std::env::var("QUIC_CERT_FILE").unwrap_or_else(|_| "quic-cert.der".into());
+    let key_path = std::env::var("QUIC_KEY_FILE").unwrap_or_else(|_| "quic-key.der".into());
+
+    let (cert, key) = match read_certificate_files(&cert_path, &key_path)? {
+        Some(files) => files,
+        None => {
+            let names = vec!["localhost".to_string(), quic::host(addr).to_string()];
+            let certified =
+                rcgen::generate_simple_self_signed(names).map_err(std::io::Error::other)?;
+            let cert = certified.cert.der().to_vec();
+            let key = certified.key_pair.serialize_der();
+            std::fs::write(&cert_path, &cert)?;
+            write_private(&key_path, &key)?;
+            println!(
+                "Generated a QUIC certificate, clients need {} as a root",
+                cert_path
+            );
+            (cert, key)
+        }
+    };
+
+    let key = PrivateKeyDer::try_from(key)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok((vec![CertificateDer::from(cert)], key))
+}
+
+/// Reads the TLS certificate chain and its key from `TLS_CERT_FILE` and
+/// `TLS_KEY_FILE`, both PEM encoded, as a CA issues them. On first start, a
+/// self-signed one for `localhost` and the host of `addr` is generated there,
+/// which clients then have to pin.
+fn load_tls_certificate(
+    addr: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), ServerError> {
+    let cert_path = std::env::var("TLS_CERT_FILE").unwrap_or_else(|_| "tls-cert.pem".into());
+    let key_path = std::env::var("TLS_KEY_FILE").unwrap_or_else(|_| "tls-key.pem".into());
+
+    let (cert, key) = match read_certificate_files(&cert_path, &key_path)? {
+        Some(files) => files,
+        None => {
+            let names = vec!["localhost".to_string(), quic::host(addr).to_string()];
+            let certified =
+                rcgen::generate_simple_self_signed(names).map_err(std::io::Error::other)?;
+            let cert = certified.cert.pem().into_bytes();
+            let key = certified.key_pair.serialize_pem().into_bytes();
+            std::fs::write(&cert_path, &cert)?;
+            write_private(&key_path, &key)?;
+            println!("Generated a self-signed TLS certificate in {}", cert_path);
+            (cert, key)
+        }
+    };
+
+    let chain = CertificateDer::pem_slice_iter(&cert)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TlsError::from)?;
+    if chain.is_empty() {
+        return Err(TlsError::Pem(pem::Error::NoItemsFound).into());
+    }
+    let key = PrivateKeyDer::from_pem_slice(&key).map_err(TlsError::from)?;
+    Ok((chain, key))
+}
+
+/// Heartbeat settings, overridable through `HEARTBEAT_INTERVAL_MS` and `HEARTBEAT_MAX_MISSED`.
+fn heartbeat_config() -> HeartbeatConfig {
+    let default = HeartbeatConfig::default();
+
+    HeartbeatConfig {
+        interval: env_var("HEARTBEAT_INTERVAL_MS")
+            .map(Duration::from_millis)
+            .unwrap_or(default.interval),
+        max_missed: env_var("HEARTBEAT_MAX_MISSED").unwrap_or(default.max_missed),
+    }
+}
+
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok()?.parse().ok()
 }
@@ -3,96 +3,191 @@ use std::sync::Arc;
 use crate::{
     error::ServerError,
     packets::{PacketData, PacketHandler},
-    server::client::Clients,
+    server::{
+        client::{Client, Clients},
+        room,
+    },
 };
-use common::packet::{ids::PacketId, packet_type::PacketType, AudioPacket, Packet};
+use common::packet::{ids::PacketId, AudioPacket, RelayedAudioPacket};
 
 pub struct AudioHandler(pub Arc<Clients>);
 
 #[async_trait::async_trait]
 impl PacketHandler for AudioHandler {
     async fn process(&self, data: PacketData) -> Result<(), ServerError> {
-        if &data.packet_id != &PacketId::AudioPacket {
+        if data.packet_id != PacketId::AudioPacket {
             return Err(ServerError::InvalidHandlerPacketId);
         }
 
-        let packet = AudioPacket::decode(&data.data).map_err(|_| ServerError::InvalidPacket)?;
-        let packet = Packet::new(packet).map_err(|_| ServerError::InvalidPacket)?;
-
-        let encoded_packet = packet.encode();
-
-        for client in self.0.lock().await.values() {
-            if client.id() != data.client_id {
-                client.send(&encoded_packet).await?;
-            }
+        let audio: AudioPacket = data.decode()?;
+
+        let clients = self.0.lock().await;
+        let sender = clients
+            .get(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        let (Some(room), Some(speaker)) = (sender.room(), sender.speaker()) else {
+            return Err(ServerError::HandshakeRequired);
+        };
+        if sender.is_muted() {
+            return Ok(());
         }
 
-        Ok(())
+        let packet = RelayedAudioPacket {
+            speaker: speaker.id,
+            audio,
+        };
+
+        let listening = |client: &Client| client.id() != data.client_id && !client.is_deafened();
+        room::broadcast_to(&clients, room, listening, &packet).await
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::server::client::Client;
-
     use super::*;
+    use crate::server::client::joined;
     use ::tokio::sync::{mpsc, Mutex};
-    use common::packet::{ids::PacketId, packet_type::PacketType, AudioPacket};
+    use common::packet::{
+        ids::PacketId, packet_type::PacketType, AudioPacket, RosterUser, UserUpdatePacket,
+    };
     use std::collections::HashMap;
-    use tokio::select;
     use uuid::Uuid;
 
     #[tokio::test]
     async fn test_audio_handler() {
         let clients = Arc::new(Mutex::new(HashMap::new()));
-        let (tx, mut read_tx) = mpsc::channel(1);
-        let (tx_2, mut read_tx_2) = mpsc::channel(1);
-
-        let client = Client::new(Uuid::new_v4(), tx);
-        let second_client = Client::new(Uuid::new_v4(), tx_2);
+        let (client, mut read_tx) = joined("lobby", 0);
+        let (second_client, mut read_tx_2) = joined("lobby", 1);
+        let (other_room, mut read_tx_3) = joined("ranked", 0);
+        let sender_id = client.id();
 
         {
-            clients.lock().await.insert(client.id(), client);
-            clients
-                .lock()
-                .await
-                .insert(second_client.id(), second_client);
+            let mut clients = clients.lock().await;
+            clients.insert(client.id(), client);
+            clients.insert(second_client.id(), second_client);
+            clients.insert(other_room.id(), other_room);
         }
 
         let audio_packet = AudioPacket {
+            sequence: 3,
+            timestamp: 1440,
             track: vec![1, 2, 3, 4, 5],
-        }
-        .encode()
-        .unwrap();
-
-        let packet = Packet::new(AudioPacket {
-            track: vec![1, 2, 3, 4, 5],
-        })
-        .unwrap()
-        .encode();
+        };
 
         assert!(
             AudioHandler(clients)
                 .process(PacketData::new(
-                    Default::default(),
+                    sender_id,
                     PacketId::AudioPacket,
-                    audio_packet.clone(),
+                    audio_packet.encode().unwrap(),
                 ))
                 .await
                 .is_ok(),
             "Expected handler to process packet"
         );
 
-        select! {
-            result = tokio::spawn(async move { read_tx.recv().await }) => {
-                assert!(result.is_ok());
-                assert_eq!(result.unwrap().unwrap(), packet, "Expected packet to be sent to first client");
-            }
-            result = tokio::spawn(async move { read_tx_2.recv().await }) => {
-                assert!(result.is_ok());
-                assert_eq!(result.unwrap().unwrap(), packet, "Expected packet to be sent to second client");
-            }
+        let packet = read_tx_2.recv().await.unwrap();
+        assert_eq!(packet.packet_id, PacketId::RelayedAudioPacket.to_u8());
+
+        let relayed = RelayedAudioPacket::decode(&packet.data).unwrap();
+        assert_eq!(relayed.speaker, 0, "Expected server to stamp speaker id");
+        assert_eq!(
+            relayed.audio, audio_packet,
+            "Expected audio to be preserved"
+        );
+
+        assert!(
+            read_tx.try_recv().is_err(),
+            "Expected packet not to be echoed to its sender"
+        );
+        assert!(
+            read_tx_3.try_recv().is_err(),
+            "Expected packet not to leave the room"
+        );
+    }
+
+    fn set_flags(client: &mut Client, flags: u32) {
+        client.update(UserUpdatePacket {
+            summoner: None,
+            flags,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_audio_handler_drops_audio_of_muted_client() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (mut sender, _sender_rx) = joined("lobby", 0);
+        let (listener, mut listener_rx) = joined("lobby", 1);
+        set_flags(&mut sender, RosterUser::MUTED);
+        let sender_id = sender.id();
+        for client in [sender, listener] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        AudioHandler(clients)
+            .process(PacketData::new(
+                sender_id,
+                PacketId::AudioPacket,
+                AudioPacket::default().encode().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        assert!(
+            listener_rx.try_recv().is_err(),
+            "Expected audio of a muted client not to be relayed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audio_handler_skips_deafened_clients() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, _sender_rx) = joined("lobby", 0);
+        let (mut deafened, mut deafened_rx) = joined("lobby", 1);
+        let (listener, mut listener_rx) = joined("lobby", 2);
+        set_flags(&mut deafened, RosterUser::DEAFENED);
+        let sender_id = sender.id();
+        for client in [sender, deafened, listener] {
+            clients.lock().await.insert(client.id(), client);
         }
+
+        AudioHandler(clients)
+            .process(PacketData::new(
+                sender_id,
+                PacketId::AudioPacket,
+                AudioPacket::default().encode().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        assert!(listener_rx.recv().await.is_some());
+        assert!(
+            deafened_rx.try_recv().is_err(),
+            "Expected no audio to reach a deafened client"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audio_handler_before_join() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, _rx) = mpsc::channel(1);
+        let client = Client::new(Uuid::new_v4(), tx);
+        let sender_id = client.id();
+        clients.lock().await.insert(sender_id, client);
+
+        assert!(
+            matches!(
+                AudioHandler(clients)
+                    .process(PacketData::new(
+                        sender_id,
+                        PacketId::AudioPacket,
+                        AudioPacket::default().encode().unwrap(),
+                    ))
+                    .await,
+                Err(ServerError::HandshakeRequired)
+            ),
+            "Expected handler to refuse audio from clients outside a room"
+        );
     }
 
     #[tokio::test]
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use crate::{
+    error::ServerError,
+    packets::{PacketData, PacketHandler},
+    server::{client::Clients, room},
+};
+use common::packet::{ids::PacketId, CalloutPacket, RejectReason, RelayedCalloutPacket};
+
+/// Relays a client's callouts to its whole room, dropping those it repeats
+/// before their cooldown is over.
+pub struct CalloutHandler(pub Arc<Clients>);
+
+#[async_trait::async_trait]
+impl PacketHandler for CalloutHandler {
+    async fn process(&self, data: PacketData) -> Result<(), ServerError> {
+        if data.packet_id != PacketId::CalloutPacket {
+            return Err(ServerError::InvalidHandlerPacketId);
+        }
+
+        let callout: CalloutPacket = data.decode()?;
+
+        let mut clients = self.0.lock().await;
+        let sender = clients
+            .get_mut(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        if callout
+            .target
+            .as_ref()
+            .is_some_and(|target| target.chars().count() > CalloutPacket::MAX_TARGET_LENGTH)
+        {
+            let message = format!(
+                "callout target is longer than {} characters",
+                CalloutPacket::MAX_TARGET_LENGTH
+            );
+            if let Err(e) = sender
+                .reject(
+                    PacketId::CalloutPacket,
+                    RejectReason::CalloutTargetTooLong,
+                    message,
+                )
+                .await
+            {
+                println!("Dropped callout of {} unanswered: {}", data.client_id, e);
+            }
+            return Ok(());
+        }
+        let (Some(room), Some(speaker)) = (
+            sender.room().map(str::to_string),
+            sender.speaker().map(|speaker| speaker.id),
+        ) else {
+            return Err(ServerError::HandshakeRequired);
+        };
+        // Holding the key down is not worth disconnecting over.
+        if !sender.try_callout(callout.callout) {
+            return Ok(());
+        }
+
+        let packet = RelayedCalloutPacket {
+            speaker,
+            callout: callout.callout,
+            target: callout.target,
+        };
+
+        room::broadcast_to(&clients, &room, |_| true, &packet).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client::joined;
+    use common::packet::{packet_type::PacketType, Callout, RejectedPacket};
+    use std::collections::HashMap;
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+
+    fn callout(sender: Uuid, callout: Callout, target: Option<&str>) -> PacketData {
+        let callout = CalloutPacket {
+            callout,
+            target: target.map(str::to_string),
+        };
+        PacketData::new(sender, PacketId::CalloutPacket, callout.encode().unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_relay_callouts_to_room_within_cooldown() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, mut sender_rx) = joined("lobby", 2);
+        let (listener, mut listener_rx) = joined("lobby", 1);
+        let (other_room, mut other_room_rx) = joined("ranked", 0);
+        let sender_id = sender.id();
+        for client in [sender, listener, other_room] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        let handler = CalloutHandler(clients);
+        handler
+            .process(callout(sender_id, Callout::Missing, Some("mid")))
+            .await
+            .unwrap();
+        handler
+            .process(callout(sender_id, Callout::Missing, Some("top")))
+            .await
+            .unwrap();
+
+        for rx in [&mut sender_rx, &mut listener_rx] {
+            let packet = rx.recv().await.unwrap();
+            assert_eq!(
+                RelayedCalloutPacket::decode(&packet.data).unwrap(),
+                RelayedCalloutPacket {
+                    speaker: 2,
+                    callout: Callout::Missing,
+                    target: Some("mid".to_string()),
+                }
+            );
+            assert!(rx.try_recv().is_err(), "expected the repeat to be dropped");
+        }
+        assert!(other_room_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_reject_callout_targets_over_the_limit() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, mut sender_rx) = joined("lobby", 0);
+        let (listener, mut listener_rx) = joined("lobby", 1);
+        let sender_id = sender.id();
+        for client in [sender, listener] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        let target = "a".repeat(CalloutPacket::MAX_TARGET_LENGTH + 1);
+        CalloutHandler(clients)
+            .process(callout(sender_id, Callout::Help, Some(&target)))
+            .await
+            .unwrap();
+
+        let packet = sender_rx.recv().await.unwrap();
+        assert_eq!(packet.packet_id, PacketId::RejectedPacket.to_u8());
+        let rejected = RejectedPacket::decode(&packet.data).unwrap();
+        assert_eq!(rejected.packet_id, PacketId::CalloutPacket.to_u8());
+        assert_eq!(rejected.reason, RejectReason::CalloutTargetTooLong);
+        assert!(listener_rx.try_recv().is_err());
+    }
+}
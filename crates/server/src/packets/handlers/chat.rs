@@ -0,0 +1,162 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::ServerError,
+    packets::{PacketData, PacketHandler},
+    server::{chat::ChatHistory, client::Clients, room},
+};
+use common::packet::{ids::PacketId, ChatPacket, RejectReason, RelayedChatPacket};
+use tokio::sync::Mutex;
+
+/// Stamps a client's chat message with its identity and the time, relays it
+/// to its room and keeps it for clients joining later.
+pub struct ChatHandler(pub Arc<Clients>, pub Arc<Mutex<ChatHistory>>);
+
+#[async_trait::async_trait]
+impl PacketHandler for ChatHandler {
+    async fn process(&self, data: PacketData) -> Result<(), ServerError> {
+        if data.packet_id != PacketId::ChatPacket {
+            return Err(ServerError::InvalidHandlerPacketId);
+        }
+
+        let chat: ChatPacket = data.decode()?;
+        let text = chat.text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let clients = self.0.lock().await;
+        let sender = clients
+            .get(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        if text.chars().count() > ChatPacket::MAX_LENGTH {
+            let message = format!(
+                "chat message is longer than {} characters",
+                ChatPacket::MAX_LENGTH
+            );
+            if let Err(e) = sender
+                .reject(
+                    PacketId::ChatPacket,
+                    RejectReason::ChatMessageTooLong,
+                    message,
+                )
+                .await
+            {
+                println!(
+                    "Dropped chat message of {} unanswered: {}",
+                    data.client_id, e
+                );
+            }
+            return Ok(());
+        }
+        let (Some(room), Some(speaker)) = (sender.room(), sender.speaker()) else {
+            return Err(ServerError::HandshakeRequired);
+        };
+
+        let message = RelayedChatPacket {
+            sender: data.client_id,
+            display_name: speaker.display_name.clone(),
+            sent_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64),
+            text: text.to_string(),
+        };
+
+        {
+            let mut history = self.1.lock().await;
+            history.retain_rooms(|room| clients.values().any(|client| client.room() == Some(room)));
+            history.push(room, message.clone());
+        }
+
+        room::broadcast_to(&clients, room, |_| true, &message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client::joined;
+    use common::packet::{packet_type::PacketType, RejectedPacket};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn chat(sender: Uuid, text: impl Into<String>) -> PacketData {
+        let chat = ChatPacket { text: text.into() };
+        PacketData::new(sender, PacketId::ChatPacket, chat.encode().unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_relay_and_record_chat_messages() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let history = Arc::new(Mutex::new(ChatHistory::default()));
+        let (sender, mut sender_rx) = joined("lobby", 0);
+        let (listener, mut listener_rx) = joined("lobby", 1);
+        let (other_room, mut other_room_rx) = joined("ranked", 0);
+        let sender_id = sender.id();
+        for client in [sender, listener, other_room] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        ChatHandler(clients, history.clone())
+            .process(chat(sender_id, "  gg wp "))
+            .await
+            .unwrap();
+
+        for rx in [&mut sender_rx, &mut listener_rx] {
+            let packet = rx.recv().await.unwrap();
+            let message = RelayedChatPacket::decode(&packet.data).unwrap();
+            assert_eq!(message.sender, sender_id);
+            assert_eq!(message.display_name, "speaker 0");
+            assert_eq!(message.text, "gg wp");
+            assert!(message.sent_at > 0, "expected the server to stamp the time");
+        }
+        assert!(other_room_rx.try_recv().is_err());
+        assert_eq!(history.lock().await.messages("lobby").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_ignore_empty_chat_messages() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let history = Arc::new(Mutex::new(ChatHistory::default()));
+        let (sender, mut sender_rx) = joined("lobby", 0);
+        let sender_id = sender.id();
+        clients.lock().await.insert(sender_id, sender);
+
+        ChatHandler(clients, history.clone())
+            .process(chat(sender_id, " \n "))
+            .await
+            .unwrap();
+
+        assert!(sender_rx.try_recv().is_err());
+        assert!(history.lock().await.messages("lobby").is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_reject_chat_messages_over_the_limit() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let history = Arc::new(Mutex::new(ChatHistory::default()));
+        let (sender, mut sender_rx) = joined("lobby", 0);
+        let (listener, mut listener_rx) = joined("lobby", 1);
+        let sender_id = sender.id();
+        for client in [sender, listener] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        let text = "a".repeat(ChatPacket::MAX_LENGTH + 1);
+        ChatHandler(clients, history.clone())
+            .process(chat(sender_id, text))
+            .await
+            .unwrap();
+
+        let packet = sender_rx.recv().await.unwrap();
+        assert_eq!(packet.packet_id, PacketId::RejectedPacket.to_u8());
+        let rejected = RejectedPacket::decode(&packet.data).unwrap();
+        assert_eq!(rejected.packet_id, PacketId::ChatPacket.to_u8());
+        assert_eq!(rejected.reason, RejectReason::ChatMessageTooLong);
+        assert!(listener_rx.try_recv().is_err());
+        assert!(history.lock().await.messages("lobby").is_empty());
+    }
+}
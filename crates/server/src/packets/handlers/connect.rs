@@ -1,36 +1,121 @@
+use std::sync::Arc;
+
 use crate::{
     error::ServerError,
     packets::{PacketData, PacketHandler},
+    server::{chat::ChatHistory, client::Clients, room},
+};
+use common::packet::{
+    ids::PacketId, packet_type::PacketType, Capabilities, ChatHistoryPacket, ConnectPacket,
+    ConnectResponsePacket, ConnectStatus, Packet, RosterPacket, Speaker, SpeakerMapPacket,
 };
-use common::packet::{ids::PacketId, packet_type::PacketType, ConnectPacket};
+use tokio::sync::Mutex;
 
-#[derive(Debug, Default)]
-pub struct ConnectHandler {}
+pub struct ConnectHandler(pub Arc<Clients>, pub Arc<Mutex<ChatHistory>>);
 
 #[async_trait::async_trait]
 impl PacketHandler for ConnectHandler {
     async fn process(&self, data: PacketData) -> Result<(), ServerError> {
-        if &data.packet_id != &PacketId::ConnectPacket {
+        if data.packet_id != PacketId::ConnectPacket {
             return Err(ServerError::InvalidHandlerPacketId);
         }
 
         let packet = ConnectPacket::decode(&data.data).map_err(|_| ServerError::InvalidPacket)?;
         println!("Processing connect packet: {:?}", packet);
-        Ok(())
+
+        let mut clients = self.0.lock().await;
+        let speaker_id = room::allocate_speaker_id(&clients, &packet.room);
+
+        let supported = Capabilities {
+            transports: Capabilities::TRANSPORT_TCP | Capabilities::TRANSPORT_UDP,
+            ..Capabilities::default()
+        };
+        let mut response = ConnectResponsePacket::negotiate(&packet, &supported);
+        if response.is_accepted() && speaker_id.is_none() {
+            response = ConnectResponsePacket::rejected(ConnectStatus::RoomFull);
+        }
+        let status = response.status;
+        let capabilities = response.capabilities;
+        let accepted = response.is_accepted();
+        let response = Packet::new(response).map_err(|_| ServerError::InvalidPacket)?;
+
+        let client = clients
+            .get_mut(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        client.send(response).await?;
+
+        let speaker_id = match speaker_id {
+            Some(speaker_id) if accepted => speaker_id,
+            _ => return Err(ServerError::HandshakeRejected(status)),
+        };
+
+        let speaker = Speaker {
+            id: speaker_id,
+            client_id: data.client_id,
+            display_name: packet.display_name,
+        };
+        // The response went out in the handshake format, what follows uses the negotiated one.
+        client.set_capabilities(capabilities);
+        client.join(packet.room.clone(), speaker.clone(), packet.summoner);
+        let user = client.roster_user().ok_or(ServerError::ClientSendError)?;
+
+        let snapshot = SpeakerMapPacket {
+            added: room::speakers(&clients, &packet.room),
+            removed: Vec::new(),
+        };
+        let announcement = SpeakerMapPacket {
+            added: vec![speaker],
+            removed: Vec::new(),
+        };
+
+        let roster = RosterPacket::Snapshot(room::roster(&clients, &packet.room));
+        let history = self.1.lock().await.messages(&packet.room);
+
+        if let Some(client) = clients.get(&data.client_id) {
+            client.send_packet(snapshot).await?;
+            client.send_packet(roster).await?;
+            if !history.is_empty() {
+                client
+                    .send_packet(ChatHistoryPacket { messages: history })
+                    .await?;
+            }
+        }
+        room::broadcast(&clients, &packet.room, data.client_id, &announcement).await?;
+        room::broadcast(
+            &clients,
+            &packet.room,
+            data.client_id,
+            &RosterPacket::UserJoined(user),
+        )
+        .await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use common::packet::ids::PacketId;
+    use crate::server::client::Client;
+    use common::packet::{format::WireFormat, ids::PacketId, Summoner, PROTOCOL_VERSION};
+    use std::collections::HashMap;
+    use tokio::sync::{mpsc, Mutex};
+    use uuid::Uuid;
+
+    async fn clients_with(id: Uuid) -> (Arc<Clients>, mpsc::Receiver<Packet>) {
+        let (tx, rx) = mpsc::channel(8);
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        clients.lock().await.insert(id, Client::new(id, tx));
+        (clients, rx)
+    }
 
     #[tokio::test]
     async fn test_connect_handler() {
+        let id = Uuid::new_v4();
+        let (clients, mut rx) = clients_with(id).await;
+
         assert!(
-            ConnectHandler {}
+            ConnectHandler(clients.clone(), Default::default())
                 .process(PacketData::new(
-                    Default::default(),
+                    id,
                     PacketId::ConnectPacket,
                     ConnectPacket::default().encode().unwrap()
                 ))
@@ -38,14 +123,133 @@ mod tests {
                 .is_ok(),
             "Expected handler to process packet"
         );
+
+        let response = rx.recv().await.unwrap();
+        let response = ConnectResponsePacket::decode(&response.data).unwrap();
+        assert!(response.is_accepted(), "Expected client to be accepted");
+        assert_eq!(
+            clients.lock().await.get(&id).unwrap().capabilities(),
+            Some(Capabilities::default())
+        );
+
+        let snapshot = rx.recv().await.unwrap();
+        let snapshot =
+            SpeakerMapPacket::decode_as(&snapshot.data, WireFormat::MessagePack).unwrap();
+        assert_eq!(snapshot.added.len(), 1);
+        assert_eq!(snapshot.added[0].id, 0);
+        assert_eq!(snapshot.added[0].client_id, id);
+
+        let roster = rx.recv().await.unwrap();
+        assert_eq!(roster.packet_id, PacketId::RosterPacket.to_u8());
+        match RosterPacket::decode_as(&roster.data, WireFormat::MessagePack).unwrap() {
+            RosterPacket::Snapshot(users) => {
+                assert_eq!(users.len(), 1);
+                assert_eq!(users[0].client_id, id);
+            }
+            other => panic!("Expected a roster snapshot, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_handler_announces_speaker_to_room() {
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let (clients, mut first_rx) = clients_with(first).await;
+        let (tx, mut second_rx) = mpsc::channel(8);
+        clients.lock().await.insert(second, Client::new(second, tx));
+
+        let handler = ConnectHandler(clients.clone(), Default::default());
+        for id in [first, second] {
+            let packet = ConnectPacket {
+                room: "lobby".to_string(),
+                display_name: id.to_string(),
+                summoner: Some(Summoner {
+                    riot_id: format!("{}#EUW", id),
+                    champion: None,
+                }),
+                ..Default::default()
+            };
+            handler
+                .process(PacketData::new(
+                    id,
+                    PacketId::ConnectPacket,
+                    packet.encode().unwrap(),
+                ))
+                .await
+                .unwrap();
+        }
+
+        // Connect response and the initial snapshots.
+        for _ in 0..3 {
+            first_rx.recv().await.unwrap();
+        }
+        let announcement = first_rx.recv().await.unwrap();
+        let announcement =
+            SpeakerMapPacket::decode_as(&announcement.data, WireFormat::MessagePack).unwrap();
+        assert_eq!(announcement.added.len(), 1);
+        assert_eq!(announcement.added[0].id, 1);
+        assert_eq!(announcement.added[0].display_name, second.to_string());
+        let joined = first_rx.recv().await.unwrap();
+        match RosterPacket::decode_as(&joined.data, WireFormat::MessagePack).unwrap() {
+            RosterPacket::UserJoined(user) => {
+                assert_eq!(user.client_id, second);
+                assert_eq!(user.speaker, 1);
+                assert_eq!(user.summoner.unwrap().riot_id, format!("{}#EUW", second));
+            }
+            other => panic!("Expected a joined user, got {:?}", other),
+        }
+
+        second_rx.recv().await.unwrap();
+        let snapshot = second_rx.recv().await.unwrap();
+        let snapshot =
+            SpeakerMapPacket::decode_as(&snapshot.data, WireFormat::MessagePack).unwrap();
+        assert_eq!(snapshot.added.len(), 2);
+        let roster = second_rx.recv().await.unwrap();
+        let roster = RosterPacket::decode_as(&roster.data, WireFormat::MessagePack).unwrap();
+        assert!(matches!(roster, RosterPacket::Snapshot(users) if users.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn test_connect_handler_version_mismatch() {
+        let id = Uuid::new_v4();
+        let (clients, mut rx) = clients_with(id).await;
+
+        let packet = ConnectPacket {
+            protocol_version: PROTOCOL_VERSION - 1,
+            ..Default::default()
+        };
+        let result = ConnectHandler(clients.clone(), Default::default())
+            .process(PacketData::new(
+                id,
+                PacketId::ConnectPacket,
+                packet.encode().unwrap(),
+            ))
+            .await;
+        assert!(
+            matches!(
+                result,
+                Err(ServerError::HandshakeRejected(
+                    ConnectStatus::UnsupportedVersion
+                ))
+            ),
+            "Expected handler to reject client"
+        );
+
+        let response = rx.recv().await.unwrap();
+        let response = ConnectResponsePacket::decode(&response.data).unwrap();
+        assert_eq!(response.status, ConnectStatus::UnsupportedVersion);
+        assert_eq!(clients.lock().await.get(&id).unwrap().capabilities(), None);
     }
 
     #[tokio::test]
     async fn test_connect_handler_invalid_packet_id() {
+        let id = Uuid::new_v4();
+        let (clients, _rx) = clients_with(id).await;
+
         assert!(
-            ConnectHandler {}
+            ConnectHandler(clients, Default::default())
                 .process(PacketData::new(
-                    Default::default(),
+                    id,
                     PacketId::AudioPacket,
                     ConnectPacket::default().encode().unwrap()
                 ))
@@ -2,7 +2,7 @@ use crate::{
     error::ServerError,
     packets::{PacketData, PacketHandler},
 };
-use common::packet::{ids::PacketId, packet_type::PacketType, DisconnectPacket};
+use common::packet::{ids::PacketId, DisconnectPacket};
 
 #[derive(Debug)]
 pub struct DisconnectHandler {}
@@ -10,12 +10,11 @@ pub struct DisconnectHandler {}
 #[async_trait::async_trait]
 impl PacketHandler for DisconnectHandler {
     async fn process(&self, data: PacketData) -> Result<(), ServerError> {
-        if &data.packet_id != &PacketId::DisconnectPacket {
+        if data.packet_id != PacketId::DisconnectPacket {
             return Err(ServerError::InvalidHandlerPacketId);
         }
 
-        let packet =
-            DisconnectPacket::decode(&data.data).map_err(|_| ServerError::InvalidPacket)?;
+        let packet: DisconnectPacket = data.decode()?;
         println!("Processing disconnect packet: {:?}", packet);
         Ok(())
     }
@@ -24,7 +23,7 @@ impl PacketHandler for DisconnectHandler {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use common::packet::ids::PacketId;
+    use common::packet::{ids::PacketId, packet_type::PacketType};
 
     #[tokio::test]
     async fn test_disconnect_handler() {
@@ -33,7 +32,7 @@ mod tests {
                 .process(PacketData::new(
                     Default::default(),
                     PacketId::DisconnectPacket,
-                    DisconnectPacket::default().encode().unwrap()
+                    DisconnectPacket.encode().unwrap()
                 ))
                 .await
                 .is_ok(),
@@ -48,7 +47,7 @@ mod tests {
                 .process(PacketData::new(
                     Default::default(),
                     PacketId::AudioPacket,
-                    DisconnectPacket::default().encode().unwrap()
+                    DisconnectPacket.encode().unwrap()
                 ))
                 .await
                 .is_err(),
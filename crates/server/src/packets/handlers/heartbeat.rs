@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use crate::{
+    error::ServerError,
+    packets::{PacketData, PacketHandler},
+    server::client::Clients,
+};
+use common::packet::{ids::PacketId, PingPacket, PongPacket};
+
+/// Answers a client's ping by echoing its timestamp back.
+pub struct PingHandler(pub Arc<Clients>);
+
+#[async_trait::async_trait]
+impl PacketHandler for PingHandler {
+    async fn process(&self, data: PacketData) -> Result<(), ServerError> {
+        if data.packet_id != PacketId::PingPacket {
+            return Err(ServerError::InvalidHandlerPacketId);
+        }
+
+        let ping: PingPacket = data.decode()?;
+
+        let clients = self.0.lock().await;
+        let client = clients
+            .get(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        // A client too far behind to take the pong misses it, rather than
+        // hold `clients` until it catches up.
+        match client.try_send_packet(PongPacket::from(ping)).await {
+            Err(ServerError::ClientBacklogged) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Records a client's answer to one of the server's pings.
+pub struct PongHandler(pub Arc<Clients>);
+
+#[async_trait::async_trait]
+impl PacketHandler for PongHandler {
+    async fn process(&self, data: PacketData) -> Result<(), ServerError> {
+        if data.packet_id != PacketId::PongPacket {
+            return Err(ServerError::InvalidHandlerPacketId);
+        }
+
+        let pong: PongPacket = data.decode()?;
+
+        let mut clients = self.0.lock().await;
+        let client = clients
+            .get_mut(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        client.heartbeat_mut().pong(&pong);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client::Client;
+    use common::packet::{packet_type::PacketType, Packet};
+    use std::collections::HashMap;
+    use tokio::sync::{mpsc, Mutex};
+    use uuid::Uuid;
+
+    async fn clients_with(id: Uuid) -> (Arc<Clients>, mpsc::Receiver<Packet>) {
+        let (tx, rx) = mpsc::channel(1);
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        clients.lock().await.insert(id, Client::new(id, tx));
+        (clients, rx)
+    }
+
+    #[tokio::test]
+    async fn test_ping_handler() {
+        let id = Uuid::new_v4();
+        let (clients, mut rx) = clients_with(id).await;
+
+        PingHandler(clients)
+            .process(PacketData::new(
+                id,
+                PacketId::PingPacket,
+                PingPacket { timestamp: 42 }.encode().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        let pong = rx.recv().await.unwrap();
+        assert_eq!(pong.packet_id, PacketId::PongPacket.to_u8());
+        assert_eq!(PongPacket::decode(&pong.data).unwrap().timestamp, 42);
+    }
+
+    #[tokio::test]
+    async fn test_ping_handler_backlogged() {
+        let id = Uuid::new_v4();
+        let (clients, mut rx) = clients_with(id).await;
+        let ping = || {
+            PacketData::new(
+                id,
+                PacketId::PingPacket,
+                PingPacket { timestamp: 42 }.encode().unwrap(),
+            )
+        };
+
+        PingHandler(clients.clone()).process(ping()).await.unwrap();
+        PingHandler(clients).process(ping()).await.unwrap();
+
+        rx.recv().await.unwrap();
+        assert!(
+            rx.try_recv().is_err(),
+            "expected the second pong to be dropped"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pong_handler() {
+        let id = Uuid::new_v4();
+        let (clients, _rx) = clients_with(id).await;
+
+        let ping = clients
+            .lock()
+            .await
+            .get_mut(&id)
+            .unwrap()
+            .heartbeat_mut()
+            .ping()
+            .unwrap();
+
+        PongHandler(clients.clone())
+            .process(PacketData::new(
+                id,
+                PacketId::PongPacket,
+                PongPacket::from(ping).encode().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        assert!(clients
+            .lock()
+            .await
+            .get(&id)
+            .unwrap()
+            .heartbeat()
+            .rtt()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_ping_handler_invalid_packet_id() {
+        assert!(
+            PingHandler(Arc::new(Mutex::new(HashMap::new())))
+                .process(PacketData::new(
+                    Default::default(),
+                    PacketId::PongPacket,
+                    PongPacket::default().encode().unwrap()
+                ))
+                .await
+                .is_err(),
+            "Expected handler to return error for invalid packet id"
+        );
+    }
+}
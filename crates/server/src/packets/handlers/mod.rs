@@ -1,3 +1,36 @@
+use crate::server::{chat::ChatHistory, tokio::TokioServer, Server};
+use common::packet::{
+    AudioPacket, CalloutPacket, ChatPacket, ConnectPacket, DisconnectPacket, PingPacket,
+    PongPacket, SpeakingPacket, UdpReadyPacket, UserUpdatePacket,
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
 pub mod audio;
-pub mod disconnect;
+pub mod callout;
+pub mod chat;
 pub mod connect;
+pub mod disconnect;
+pub mod heartbeat;
+pub mod roster;
+pub mod speaking;
+pub mod udp;
+
+/// Registers a handler for every packet clients are allowed to send.
+pub fn register(server: &mut TokioServer) {
+    let clients = server.clients();
+    let chat_history = Arc::new(Mutex::new(ChatHistory::default()));
+    server.add_handler::<ConnectPacket>(Box::new(connect::ConnectHandler(
+        clients.clone(),
+        chat_history.clone(),
+    )));
+    server.add_handler::<AudioPacket>(Box::new(audio::AudioHandler(clients.clone())));
+    server.add_handler::<DisconnectPacket>(Box::new(disconnect::DisconnectHandler {}));
+    server.add_handler::<PingPacket>(Box::new(heartbeat::PingHandler(clients.clone())));
+    server.add_handler::<PongPacket>(Box::new(heartbeat::PongHandler(clients.clone())));
+    server.add_handler::<UserUpdatePacket>(Box::new(roster::UserUpdateHandler(clients.clone())));
+    server.add_handler::<SpeakingPacket>(Box::new(speaking::SpeakingHandler(clients.clone())));
+    server.add_handler::<ChatPacket>(Box::new(chat::ChatHandler(clients.clone(), chat_history)));
+    server.add_handler::<CalloutPacket>(Box::new(callout::CalloutHandler(clients.clone())));
+    server.add_handler::<UdpReadyPacket>(Box::new(udp::UdpReadyHandler(clients)));
+}
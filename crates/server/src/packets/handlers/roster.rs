@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use crate::{
+    error::ServerError,
+    packets::{PacketData, PacketHandler},
+    server::{client::Clients, room},
+};
+use common::packet::{ids::PacketId, RosterPacket, UserUpdatePacket};
+use uuid::Uuid;
+
+/// Applies a client's changes to its roster entry and announces them to its room.
+pub struct UserUpdateHandler(pub Arc<Clients>);
+
+#[async_trait::async_trait]
+impl PacketHandler for UserUpdateHandler {
+    async fn process(&self, data: PacketData) -> Result<(), ServerError> {
+        if data.packet_id != PacketId::UserUpdatePacket {
+            return Err(ServerError::InvalidHandlerPacketId);
+        }
+
+        let update: UserUpdatePacket = data.decode()?;
+
+        let mut clients = self.0.lock().await;
+        let client = clients
+            .get_mut(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        client.update(update);
+        let (Some(room), Some(user)) = (client.room().map(str::to_string), client.roster_user())
+        else {
+            return Err(ServerError::HandshakeRequired);
+        };
+
+        // The sender gets its own update back too, so its roster matches everyone else's.
+        room::broadcast(
+            &clients,
+            &room,
+            Uuid::nil(),
+            &RosterPacket::UserUpdated(user),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client::{joined, Client};
+    use common::packet::{packet_type::PacketType, Summoner};
+    use std::collections::HashMap;
+    use tokio::sync::{mpsc, Mutex};
+
+    #[tokio::test]
+    async fn should_announce_update_to_room() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, mut sender_rx) = joined("lobby", 0);
+        let (listener, mut listener_rx) = joined("lobby", 1);
+        let (other_room, mut other_room_rx) = joined("ranked", 0);
+        let sender_id = sender.id();
+        for client in [sender, listener, other_room] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        let update = UserUpdatePacket {
+            summoner: Some(Summoner {
+                riot_id: "Teemo#EUW".to_string(),
+                champion: Some("Teemo".to_string()),
+            }),
+            flags: 1,
+        };
+        UserUpdateHandler(clients)
+            .process(PacketData::new(
+                sender_id,
+                PacketId::UserUpdatePacket,
+                update.encode().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        for rx in [&mut sender_rx, &mut listener_rx] {
+            let packet = rx.recv().await.unwrap();
+            let RosterPacket::UserUpdated(user) = RosterPacket::decode(&packet.data).unwrap()
+            else {
+                panic!("Expected a user update");
+            };
+            assert_eq!(user.client_id, sender_id);
+            assert_eq!(user.summoner, update.summoner);
+            assert_eq!(user.flags, 1);
+        }
+        assert!(
+            other_room_rx.try_recv().is_err(),
+            "Expected update not to leave the room"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_refuse_update_before_join() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, _rx) = mpsc::channel(1);
+        let client = Client::new(Uuid::new_v4(), tx);
+        let sender_id = client.id();
+        clients.lock().await.insert(sender_id, client);
+
+        assert!(matches!(
+            UserUpdateHandler(clients)
+                .process(PacketData::new(
+                    sender_id,
+                    PacketId::UserUpdatePacket,
+                    UserUpdatePacket::default().encode().unwrap(),
+                ))
+                .await,
+            Err(ServerError::HandshakeRequired)
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use crate::{
+    error::ServerError,
+    packets::{PacketData, PacketHandler},
+    server::{client::Clients, room},
+};
+use common::packet::{ids::PacketId, RelayedSpeakingPacket, SpeakingPacket};
+
+/// Relays a client's speaking state to the rest of its room.
+pub struct SpeakingHandler(pub Arc<Clients>);
+
+#[async_trait::async_trait]
+impl PacketHandler for SpeakingHandler {
+    async fn process(&self, data: PacketData) -> Result<(), ServerError> {
+        if data.packet_id != PacketId::SpeakingPacket {
+            return Err(ServerError::InvalidHandlerPacketId);
+        }
+
+        let speaking: SpeakingPacket = data.decode()?;
+
+        let clients = self.0.lock().await;
+        let sender = clients
+            .get(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        let (Some(room), Some(speaker)) = (sender.room(), sender.speaker()) else {
+            return Err(ServerError::HandshakeRequired);
+        };
+        // Muted clients are silent to everyone, whatever their microphone picks up.
+        if sender.is_muted() && speaking.speaking {
+            return Ok(());
+        }
+
+        let packet = RelayedSpeakingPacket {
+            speaker: speaker.id,
+            speaking: speaking.speaking,
+        };
+
+        room::broadcast(&clients, room, data.client_id, &packet).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client::{joined, Client};
+    use common::packet::{packet_type::PacketType, RosterUser, UserUpdatePacket};
+    use std::collections::HashMap;
+    use tokio::sync::{mpsc, Mutex};
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn should_relay_speaking_state_to_room() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, mut sender_rx) = joined("lobby", 2);
+        let (listener, mut listener_rx) = joined("lobby", 0);
+        let (other_room, mut other_room_rx) = joined("ranked", 0);
+        let sender_id = sender.id();
+        for client in [sender, listener, other_room] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        SpeakingHandler(clients)
+            .process(PacketData::new(
+                sender_id,
+                PacketId::SpeakingPacket,
+                SpeakingPacket { speaking: true }.encode().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        let packet = listener_rx.recv().await.unwrap();
+        assert_eq!(packet.packet_id, PacketId::RelayedSpeakingPacket.to_u8());
+        assert_eq!(
+            RelayedSpeakingPacket::decode(&packet.data).unwrap(),
+            RelayedSpeakingPacket {
+                speaker: 2,
+                speaking: true
+            }
+        );
+        assert!(sender_rx.try_recv().is_err());
+        assert!(other_room_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_only_relay_speaking_stop_of_muted_client() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (mut sender, _sender_rx) = joined("lobby", 0);
+        let (listener, mut listener_rx) = joined("lobby", 1);
+        sender.update(UserUpdatePacket {
+            summoner: None,
+            flags: RosterUser::MUTED,
+        });
+        let sender_id = sender.id();
+        for client in [sender, listener] {
+            clients.lock().await.insert(client.id(), client);
+        }
+
+        let handler = SpeakingHandler(clients);
+        for speaking in [true, false] {
+            handler
+                .process(PacketData::new(
+                    sender_id,
+                    PacketId::SpeakingPacket,
+                    SpeakingPacket { speaking }.encode().unwrap(),
+                ))
+                .await
+                .unwrap();
+        }
+
+        let packet = listener_rx.recv().await.unwrap();
+        assert!(
+            !RelayedSpeakingPacket::decode(&packet.data)
+                .unwrap()
+                .speaking
+        );
+        assert!(listener_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_speaking_state_before_join() {
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, _rx) = mpsc::channel(1);
+        let client = Client::new(Uuid::new_v4(), tx);
+        let sender_id = client.id();
+        clients.lock().await.insert(sender_id, client);
+
+        assert!(matches!(
+            SpeakingHandler(clients)
+                .process(PacketData::new(
+                    sender_id,
+                    PacketId::SpeakingPacket,
+                    SpeakingPacket::default().encode().unwrap(),
+                ))
+                .await,
+            Err(ServerError::HandshakeRequired)
+        ));
+    }
+}
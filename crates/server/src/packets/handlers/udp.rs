@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use crate::{
+    error::ServerError,
+    packets::{PacketData, PacketHandler},
+    server::client::Clients,
+};
+use common::packet::{ids::PacketId, UdpReadyPacket};
+
+/// Moves a client's audio over its datagram path, or back to its stream, as
+/// the client says it is ready for, see [`UdpReadyPacket`].
+pub struct UdpReadyHandler(pub Arc<Clients>);
+
+#[async_trait::async_trait]
+impl PacketHandler for UdpReadyHandler {
+    async fn process(&self, data: PacketData) -> Result<(), ServerError> {
+        if data.packet_id != PacketId::UdpReadyPacket {
+            return Err(ServerError::InvalidHandlerPacketId);
+        }
+
+        let update: UdpReadyPacket = data.decode()?;
+
+        let mut clients = self.0.lock().await;
+        let client = clients
+            .get_mut(&data.client_id)
+            .ok_or(ServerError::ClientSendError)?;
+        if !client.set_datagrams_ready(update.ready) {
+            println!(
+                "Ignoring UDP readiness of {}, it was never offered",
+                data.client_id
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::client::joined;
+    use common::{
+        capture::Capture,
+        datagram::{DatagramCodec, DatagramLink, DatagramPath, MAX_DATAGRAM_SIZE},
+        packet::{
+            codec::PacketCodec, packet_type::PacketType, AudioPacket, Packet, RelayedAudioPacket,
+        },
+        secure::SecureCodec,
+    };
+    use std::collections::HashMap;
+    use tokio::{net::UdpSocket, sync::Mutex};
+    use uuid::Uuid;
+
+    fn ready(client_id: Uuid, ready: bool) -> PacketData {
+        let update = UdpReadyPacket { ready };
+        PacketData::new(
+            client_id,
+            PacketId::UdpReadyPacket,
+            update.encode().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn should_only_send_audio_over_udp_while_ready() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let link = DatagramLink::new(
+            Arc::new(socket),
+            DatagramCodec::new(Uuid::new_v4(), SecureCodec::plaintext(PacketCodec)),
+            Some(peer.local_addr().unwrap()),
+            Capture::default(),
+        );
+        let (mut client, mut rx) = joined("lobby", 0);
+        client.set_datagrams(DatagramPath::Udp(Arc::new(link)));
+        let client_id = client.id();
+        let clients = Arc::new(Mutex::new(HashMap::from([(client_id, client)])));
+        let audio = Packet::new(RelayedAudioPacket {
+            speaker: 1,
+            audio: AudioPacket::default(),
+        })
+        .unwrap();
+        let send = || async {
+            let clients = clients.lock().await;
+            clients[&client_id].send(audio.clone()).await.unwrap();
+        };
+
+        send().await;
+        assert_eq!(rx.try_recv().unwrap(), audio, "expected TCP before ready");
+
+        let handler = UdpReadyHandler(clients.clone());
+        handler.process(ready(client_id, true)).await.unwrap();
+        send().await;
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        peer.recv(&mut buffer).await.unwrap();
+        assert!(rx.try_recv().is_err());
+
+        handler.process(ready(client_id, false)).await.unwrap();
+        send().await;
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            audio,
+            "expected TCP after falling back"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_ignore_readiness_without_an_offer() {
+        let (client, mut rx) = joined("lobby", 0);
+        let client_id = client.id();
+        let clients = Arc::new(Mutex::new(HashMap::from([(client_id, client)])));
+
+        UdpReadyHandler(clients.clone())
+            .process(ready(client_id, true))
+            .await
+            .unwrap();
+
+        let audio = Packet::new(RelayedAudioPacket::default()).unwrap();
+        clients.lock().await[&client_id]
+            .send(audio.clone())
+            .await
+            .unwrap();
+        assert_eq!(rx.try_recv().unwrap(), audio);
+    }
+}
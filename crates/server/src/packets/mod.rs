@@ -1,23 +1,37 @@
 pub mod handlers;
 
 use crate::error::ServerError;
-use common::packet::ids::PacketId;
+use bytes::Bytes;
+use common::packet::{format::WireFormat, ids::PacketId, packet_type::PacketType};
 use uuid::Uuid;
 
 pub struct PacketData {
     client_id: Uuid,
     packet_id: PacketId,
-    data: Vec<u8>, // This is the raw packet data,
+    data: Bytes, // This is the raw packet data, shared with the receive buffer
+    format: WireFormat,
 }
 
 impl PacketData {
-    pub fn new(client_id: Uuid, packet_id: PacketId, packet: Vec<u8>) -> Self {
+    pub fn new(client_id: Uuid, packet_id: PacketId, packet: impl Into<Bytes>) -> Self {
         Self {
             client_id,
             packet_id,
-            data: packet,
+            data: packet.into(),
+            format: WireFormat::HANDSHAKE,
         }
     }
+
+    /// Sets the format the payload was encoded with, as negotiated with the client.
+    pub fn with_format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Decodes the payload with the format negotiated with the client.
+    pub fn decode<P: PacketType>(&self) -> Result<P, ServerError> {
+        P::decode_as(&self.data, self.format).map_err(|_| ServerError::InvalidPacket)
+    }
 }
 
 #[async_trait::async_trait]
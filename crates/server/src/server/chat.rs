@@ -0,0 +1,72 @@
+use common::packet::{ChatHistoryPacket, RelayedChatPacket};
+use std::collections::{HashMap, VecDeque};
+
+/// Latest chat messages of every room, for clients joining late.
+#[derive(Default)]
+pub struct ChatHistory {
+    rooms: HashMap<String, VecDeque<RelayedChatPacket>>,
+}
+
+impl ChatHistory {
+    /// Records a message, forgetting the oldest one of the room once it holds
+    /// [`ChatHistoryPacket::MAX_MESSAGES`].
+    pub fn push(&mut self, room: &str, message: RelayedChatPacket) {
+        let messages = self.rooms.entry(room.to_string()).or_default();
+        if messages.len() == ChatHistoryPacket::MAX_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(message);
+    }
+
+    /// Messages of `room`, oldest first.
+    pub fn messages(&self, room: &str) -> Vec<RelayedChatPacket> {
+        self.rooms
+            .get(room)
+            .map(|messages| messages.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drops the history of every room `keep` rejects, so rooms nobody is in
+    /// anymore do not pile up.
+    pub fn retain_rooms(&mut self, keep: impl Fn(&str) -> bool) {
+        self.rooms.retain(|room, _| keep(room));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: usize) -> RelayedChatPacket {
+        RelayedChatPacket {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_keep_latest_messages_per_room() {
+        let mut history = ChatHistory::default();
+        for text in 0..ChatHistoryPacket::MAX_MESSAGES + 2 {
+            history.push("lobby", message(text));
+        }
+        history.push("ranked", message(0));
+
+        let lobby = history.messages("lobby");
+        assert_eq!(lobby.len(), ChatHistoryPacket::MAX_MESSAGES);
+        assert_eq!(lobby[0], message(2));
+        assert_eq!(history.messages("ranked"), vec![message(0)]);
+        assert!(history.messages("aram").is_empty());
+    }
+
+    #[test]
+    fn should_drop_rejected_rooms() {
+        let mut history = ChatHistory::default();
+        history.push("lobby", message(0));
+        history.push("ranked", message(0));
+
+        history.retain_rooms(|room| room == "lobby");
+        assert_eq!(history.messages("lobby").len(), 1);
+        assert!(history.messages("ranked").is_empty());
+    }
+}
@@ -1,44 +1,282 @@
 use crate::error::ServerError;
-use ::tokio::sync::{mpsc, Mutex};
-use std::collections::HashMap;
+use ::tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Mutex,
+};
+use common::{
+    cooldown::Cooldowns,
+    datagram::DatagramPath,
+    heartbeat::Heartbeat,
+    packet::{
+        format::WireFormat, ids::PacketId, packet_type::PacketType, Callout, Capabilities,
+        ClosePacket, CloseReason, Packet, RejectReason, RejectedPacket, RosterUser, Speaker,
+        Summoner, UserUpdatePacket,
+    },
+};
+use std::{collections::HashMap, time::Instant};
 use uuid::Uuid;
 
 pub type Clients = Mutex<HashMap<Uuid, Client>>;
 
 pub struct Client {
     pub(super) id: Uuid,
-    pub(super) write_tx: mpsc::Sender<Vec<u8>>,
+    pub(super) write_tx: mpsc::Sender<Packet>,
+    pub(super) capabilities: Option<Capabilities>,
+    pub(super) format: WireFormat,
+    pub(super) room: Option<String>,
+    pub(super) speaker: Option<Speaker>,
+    pub(super) summoner: Option<Summoner>,
+    pub(super) flags: u32,
+    pub(super) heartbeat: Heartbeat,
+    pub(super) callouts: Cooldowns<Callout>,
+    pub(super) datagrams: Option<DatagramPath>,
+    pub(super) datagrams_ready: bool,
 }
 
 impl Client {
-    pub fn new(id: Uuid, write_tx: mpsc::Sender<Vec<u8>>) -> Self {
-        Self { id, write_tx }
+    pub fn new(id: Uuid, write_tx: mpsc::Sender<Packet>) -> Self {
+        Self {
+            id,
+            write_tx,
+            capabilities: None,
+            format: WireFormat::HANDSHAKE,
+            room: None,
+            speaker: None,
+            summoner: None,
+            flags: 0,
+            heartbeat: Heartbeat::default(),
+            callouts: Cooldowns::new(Callout::COOLDOWN),
+            datagrams: None,
+            datagrams_ready: false,
+        }
     }
 
     pub fn id(&self) -> Uuid {
         self.id
     }
 
-    pub async fn send(&self, packet: &[u8]) -> Result<(), ServerError> {
-        match self.write_tx.send(packet.to_vec()).await {
+    /// Capabilities negotiated during the connect exchange, if it completed.
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// Also switches to the payload format the capabilities settle on.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = Some(capabilities);
+        self.format = capabilities.format().unwrap_or(WireFormat::HANDSHAKE);
+    }
+
+    /// Payload format of packets exchanged with the client.
+    pub fn format(&self) -> WireFormat {
+        self.format
+    }
+
+    /// Room the client joined during the connect exchange, if it completed.
+    pub fn room(&self) -> Option<&str> {
+        self.room.as_deref()
+    }
+
+    pub fn speaker(&self) -> Option<&Speaker> {
+        self.speaker.as_ref()
+    }
+
+    pub fn join(&mut self, room: String, speaker: Speaker, summoner: Option<Summoner>) {
+        self.room = Some(room);
+        self.speaker = Some(speaker);
+        self.summoner = summoner;
+    }
+
+    pub fn update(&mut self, update: UserUpdatePacket) {
+        self.summoner = update.summoner;
+        self.flags = update.flags;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.flags & RosterUser::MUTED != 0
+    }
+
+    pub fn is_deafened(&self) -> bool {
+        self.flags & RosterUser::DEAFENED != 0
+    }
+
+    /// How the client appears to the rest of its room, once it joined one.
+    pub fn roster_user(&self) -> Option<RosterUser> {
+        let speaker = self.speaker.as_ref()?;
+        Some(RosterUser {
+            client_id: self.id,
+            speaker: speaker.id,
+            display_name: speaker.display_name.clone(),
+            summoner: self.summoner.clone(),
+            flags: self.flags,
+        })
+    }
+
+    /// Whether the client may send `callout` now, rather than spamming it.
+    pub fn try_callout(&mut self, callout: Callout) -> bool {
+        self.callouts.try_start(callout, Instant::now())
+    }
+
+    pub fn heartbeat(&self) -> &Heartbeat {
+        &self.heartbeat
+    }
+
+    pub fn heartbeat_mut(&mut self) -> &mut Heartbeat {
+        &mut self.heartbeat
+    }
+
+    pub fn set_heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Datagram path of the client, either offered over [`super::udp`] or
+    /// that of its QUIC connection.
+    pub fn datagrams(&self) -> Option<&DatagramPath> {
+        self.datagrams.as_ref()
+    }
+
+    /// Audio goes out over `path` right away if it is that of the client's
+    /// QUIC connection, otherwise only once the client confirmed it works,
+    /// see [`Self::set_datagrams_ready`].
+    pub fn set_datagrams(&mut self, path: DatagramPath) {
+        self.datagrams_ready = matches!(path, DatagramPath::Quic(_));
+        self.datagrams = Some(path);
+    }
+
+    /// Whether audio goes out over the datagram path, as the client last told
+    /// with a [`UdpReadyPacket`](common::packet::UdpReadyPacket). Returns
+    /// false if it has no path to go over.
+    pub fn set_datagrams_ready(&mut self, ready: bool) -> bool {
+        if self.datagrams.is_none() {
+            return false;
+        }
+        self.datagrams_ready = ready;
+        true
+    }
+
+    /// Queues `packet` on the connection's stream, or sends it right away as
+    /// a datagram if it is audio and the client has a datagram path.
+    pub async fn send(&self, packet: Packet) -> Result<(), ServerError> {
+        let Some(packet) = self.send_datagram(packet).await else {
+            return Ok(());
+        };
+
+        match self.write_tx.send(packet).await {
             Ok(_) => Ok(()),
             Err(_) => Err(ServerError::ClientSendError),
         }
     }
+
+    /// Like [`Self::send`], but fails rather than wait for the client to
+    /// catch up on a full queue.
+    pub async fn try_send(&self, packet: Packet) -> Result<(), ServerError> {
+        let Some(packet) = self.send_datagram(packet).await else {
+            return Ok(());
+        };
+
+        self.write_tx.try_send(packet).map_err(|e| match e {
+            TrySendError::Full(_) => ServerError::ClientBacklogged,
+            TrySendError::Closed(_) => ServerError::ClientSendError,
+        })
+    }
+
+    /// Sends audio over the datagram path, if there is one the client is
+    /// ready for. Returns the packet when it has to go on the stream instead.
+    async fn send_datagram(&self, packet: Packet) -> Option<Packet> {
+        if packet.packet_id != PacketId::RelayedAudioPacket.to_u8() || !self.datagrams_ready {
+            return Some(packet);
+        }
+        let Some(path) = &self.datagrams else {
+            return Some(packet);
+        };
+
+        match path.send(packet.clone()).await {
+            Ok(true) => return None,
+            // Nothing came from the client over UDP yet, stay on the stream.
+            Ok(false) => {}
+            Err(e) => println!("Sending audio datagram to {} failed: {}", self.id, e),
+        }
+        Some(packet)
+    }
+
+    /// Encodes `packet` with the client's payload format and queues it.
+    pub async fn send_packet<P: PacketType>(&self, packet: P) -> Result<(), ServerError> {
+        self.send(Packet::new_as(packet, self.format)?).await
+    }
+
+    /// Like [`Self::send_packet`], but through [`Self::try_send`].
+    pub async fn try_send_packet<P: PacketType>(&self, packet: P) -> Result<(), ServerError> {
+        self.try_send(Packet::new_as(packet, self.format)?).await
+    }
+
+    /// Queues a [`ClosePacket`]; the connection is torn down once the client is removed.
+    ///
+    /// Closing is best effort: a client too far behind to take the packet
+    /// is not waited on, as it may never read again.
+    pub async fn close(
+        &self,
+        reason: CloseReason,
+        message: impl Into<String>,
+    ) -> Result<(), ServerError> {
+        self.try_send(Packet::new_as(
+            ClosePacket::new(reason, message),
+            self.format,
+        )?)
+        .await
+    }
+
+    /// Tells the client a packet of type `packet_id` it sent was dropped,
+    /// without closing the connection over it.
+    ///
+    /// Best effort like [`Self::close`], as this is usually answered while
+    /// `clients` is locked.
+    pub async fn reject(
+        &self,
+        packet_id: PacketId,
+        reason: RejectReason,
+        message: impl Into<String>,
+    ) -> Result<(), ServerError> {
+        self.try_send(Packet::new_as(
+            RejectedPacket::new(packet_id, reason, message),
+            self.format,
+        )?)
+        .await
+    }
+}
+
+/// A client that has joined `room` as `speaker_id`, along with the receiving
+/// end of its write queue, for handler tests.
+#[cfg(test)]
+pub(crate) fn joined(
+    room: &str,
+    speaker_id: common::packet::SpeakerId,
+) -> (Client, mpsc::Receiver<Packet>) {
+    let (tx, rx) = mpsc::channel(4);
+    let mut client = Client::new(Uuid::new_v4(), tx);
+    client.join(
+        room.to_string(),
+        Speaker {
+            id: speaker_id,
+            client_id: client.id(),
+            display_name: format!("speaker {}", speaker_id),
+        },
+        None,
+    );
+    (client, rx)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use ::tokio::sync::mpsc;
+    use common::packet::{DisconnectPacket, PingPacket};
 
     #[tokio::test]
     async fn test_client_send() {
         let (tx, mut rx) = mpsc::channel(1);
         let client = Client::new(Uuid::new_v4(), tx);
 
-        let packet = vec![0, 1, 2, 3];
-        client.send(&packet).await.unwrap();
+        let packet = Packet::new(DisconnectPacket).unwrap();
+        client.send(packet.clone()).await.unwrap();
 
         let received = rx.recv().await.unwrap();
         assert_eq!(packet, received);
@@ -60,15 +298,149 @@ mod tests {
         let client = Client::new(id, tx);
 
         assert_eq!(id, client.id());
+        assert_eq!(None, client.capabilities());
+        assert_eq!(None, client.room());
+        assert_eq!(None, client.speaker());
+    }
+
+    #[test]
+    fn test_client_join() {
+        let (tx, _rx) = mpsc::channel(1);
+        let id = Uuid::new_v4();
+        let mut client = Client::new(id, tx);
+        let speaker = Speaker {
+            id: 3,
+            client_id: id,
+            display_name: "Teemo".to_string(),
+        };
+        assert_eq!(None, client.roster_user());
+        client.join("lobby".to_string(), speaker.clone(), None);
+
+        assert_eq!(Some("lobby"), client.room());
+        assert_eq!(Some(&speaker), client.speaker());
+    }
+
+    #[test]
+    fn should_rate_limit_each_callout() {
+        let (tx, _rx) = mpsc::channel(1);
+        let mut client = Client::new(Uuid::new_v4(), tx);
+
+        assert!(client.try_callout(Callout::DragonSoon));
+        assert!(client.try_callout(Callout::BaronSoon));
+        assert!(!client.try_callout(Callout::DragonSoon));
+    }
+
+    #[test]
+    fn test_client_update() {
+        let (tx, _rx) = mpsc::channel(1);
+        let id = Uuid::new_v4();
+        let mut client = Client::new(id, tx);
+        let speaker = Speaker {
+            id: 3,
+            client_id: id,
+            display_name: "Teemo".to_string(),
+        };
+        client.join("lobby".to_string(), speaker, None);
+
+        let summoner = Summoner {
+            riot_id: "Teemo#EUW".to_string(),
+            champion: Some("Teemo".to_string()),
+        };
+        client.update(UserUpdatePacket {
+            summoner: Some(summoner.clone()),
+            flags: 2,
+        });
+
+        assert_eq!(
+            client.roster_user(),
+            Some(RosterUser {
+                client_id: id,
+                speaker: 3,
+                display_name: "Teemo".to_string(),
+                summoner: Some(summoner),
+                flags: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_client_set_capabilities() {
+        let (tx, _rx) = mpsc::channel(1);
+        let mut client = Client::new(Uuid::new_v4(), tx);
+        client.set_capabilities(Capabilities::default());
+
+        assert_eq!(Some(Capabilities::default()), client.capabilities());
+        assert_eq!(WireFormat::MessagePack, client.format());
     }
+
+    #[tokio::test]
+    async fn test_client_send_packet_in_negotiated_format() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut client = Client::new(Uuid::new_v4(), tx);
+        client.set_capabilities(Capabilities::default());
+
+        client
+            .send_packet(PingPacket { timestamp: 42 })
+            .await
+            .unwrap();
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(
+            PingPacket::decode_as(&received.data, WireFormat::MessagePack).unwrap(),
+            PingPacket { timestamp: 42 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_close() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let client = Client::new(Uuid::new_v4(), tx);
+
+        client.close(CloseReason::Kicked, "bye").await.unwrap();
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(
+            ClosePacket::decode(&received.data).unwrap(),
+            ClosePacket::new(CloseReason::Kicked, "bye")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_try_send_full() {
+        let (tx, _rx) = mpsc::channel(1);
+        let client = Client::new(Uuid::new_v4(), tx);
+
+        let packet = Packet::new(DisconnectPacket).unwrap();
+        client.try_send(packet.clone()).await.unwrap();
+        assert!(matches!(
+            client.try_send(packet).await,
+            Err(ServerError::ClientBacklogged)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_client_close_full() {
+        let (tx, _rx) = mpsc::channel(1);
+        let client = Client::new(Uuid::new_v4(), tx);
+        client
+            .send(Packet::new(DisconnectPacket).unwrap())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            client.close(CloseReason::Kicked, "bye").await,
+            Err(ServerError::ClientBacklogged)
+        ));
+    }
+
     #[tokio::test]
     async fn test_client_send_error() {
         let (tx, _) = mpsc::channel(1);
         let client = Client::new(Uuid::new_v4(), tx);
 
-        let packet = vec![0, 1, 2, 3];
+        let packet = Packet::new(DisconnectPacket).unwrap();
         assert!(
-            client.send(&packet).await.is_err(),
+            client.send(packet).await.is_err(),
             "expected send to fail with ClientSendError"
         );
     }
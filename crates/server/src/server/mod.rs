@@ -1,9 +1,13 @@
+pub mod chat;
 pub mod client;
+pub mod room;
 pub mod tokio;
+pub mod udp;
+pub mod websocket;
 
 use crate::error::ServerError;
 use client::Clients;
-use common::packet::Packet;
+use common::packet::{format::WireFormat, Packet};
 use std::{borrow::Cow, sync::Arc};
 use uuid::Uuid;
 
@@ -15,6 +19,7 @@ pub(crate) trait Server: Send + Sync {
         client_id: Uuid,
         handlers: Arc<Self::Handlers>,
         packet: Packet,
+        format: WireFormat,
     ) -> Result<(), ServerError>;
     fn clients(&self) -> Arc<Clients>;
 }
@@ -0,0 +1,243 @@
+use super::client::Client;
+use crate::error::ServerError;
+use common::packet::{
+    format::WireFormat, packet_type::PacketType, Packet, RosterUser, Speaker, SpeakerId,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Returns the lowest speaker id not taken in `room`.
+///
+/// Ids are derived from the clients currently in the room, so an id is free for
+/// reuse as soon as the client holding it is removed from the map.
+pub fn allocate_speaker_id(clients: &HashMap<Uuid, Client>, room: &str) -> Option<SpeakerId> {
+    let mut taken: Vec<SpeakerId> = members(clients, room)
+        .filter_map(|client| client.speaker().map(|speaker| speaker.id))
+        .collect();
+    taken.sort_unstable();
+
+    let mut id: SpeakerId = 0;
+    for taken in taken {
+        if taken != id {
+            break;
+        }
+        id = id.checked_add(1)?;
+    }
+
+    Some(id)
+}
+
+/// Speaker mapping of everyone currently in `room`.
+pub fn speakers(clients: &HashMap<Uuid, Client>, room: &str) -> Vec<Speaker> {
+    members(clients, room)
+        .filter_map(|client| client.speaker().cloned())
+        .collect()
+}
+
+/// Roster entries of everyone currently in `room`, ordered by speaker id.
+pub fn roster(clients: &HashMap<Uuid, Client>, room: &str) -> Vec<RosterUser> {
+    let mut roster: Vec<RosterUser> = members(clients, room)
+        .filter_map(Client::roster_user)
+        .collect();
+    roster.sort_unstable_by_key(|user| user.speaker);
+    roster
+}
+
+/// Sends `packet` to every client in `room` except `except`.
+pub async fn broadcast<P: PacketType>(
+    clients: &HashMap<Uuid, Client>,
+    room: &str,
+    except: Uuid,
+    packet: &P,
+) -> Result<(), ServerError> {
+    broadcast_to(clients, room, |client| client.id() != except, packet).await
+}
+
+/// Sends `packet` to the clients in `room` that `filter` accepts.
+///
+/// The packet is encoded once per payload format in use, not once per client.
+/// Clients that are gone or behind miss it, without waiting on them while
+/// `clients` is locked; their own connection is what drops them.
+pub async fn broadcast_to<P: PacketType>(
+    clients: &HashMap<Uuid, Client>,
+    room: &str,
+    filter: impl Fn(&Client) -> bool,
+    packet: &P,
+) -> Result<(), ServerError> {
+    let mut encoded: HashMap<WireFormat, Packet> = HashMap::new();
+    for client in members(clients, room) {
+        if !filter(client) {
+            continue;
+        }
+
+        let packet = match encoded.get(&client.format()) {
+            Some(packet) => packet.clone(),
+            None => {
+                let data = packet.encode_as(client.format())?;
+                let packet = Packet::from_parts(P::packet_id(), data.into());
+                encoded.insert(client.format(), packet.clone());
+                packet
+            }
+        };
+        if let Err(e) = client.try_send(packet).await {
+            println!("Skipping {} in broadcast: {}", client.id(), e);
+        }
+    }
+
+    Ok(())
+}
+
+fn members<'a>(
+    clients: &'a HashMap<Uuid, Client>,
+    room: &'a str,
+) -> impl Iterator<Item = &'a Client> {
+    clients
+        .values()
+        .filter(move |client| client.room() == Some(room))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::tokio::sync::mpsc;
+    use common::packet::{Capabilities, PingPacket, UserUpdatePacket};
+
+    fn join(clients: &mut HashMap<Uuid, Client>, room: &str) -> (Uuid, mpsc::Receiver<Packet>) {
+        let (tx, rx) = mpsc::channel(1);
+        let id = Uuid::new_v4();
+        let mut client = Client::new(id, tx);
+        let speaker_id = allocate_speaker_id(clients, room).unwrap();
+        client.join(
+            room.to_string(),
+            Speaker {
+                id: speaker_id,
+                client_id: id,
+                display_name: String::new(),
+            },
+            None,
+        );
+        clients.insert(id, client);
+        (id, rx)
+    }
+
+    #[test]
+    fn should_allocate_ids_per_room() {
+        let mut clients = HashMap::new();
+        join(&mut clients, "lobby");
+        join(&mut clients, "lobby");
+
+        assert_eq!(allocate_speaker_id(&clients, "lobby"), Some(2));
+        assert_eq!(allocate_speaker_id(&clients, "ranked"), Some(0));
+    }
+
+    #[test]
+    fn should_recycle_ids_of_removed_clients() {
+        let mut clients = HashMap::new();
+        join(&mut clients, "lobby");
+        let (second, _rx) = join(&mut clients, "lobby");
+        join(&mut clients, "lobby");
+
+        clients.remove(&second);
+        assert_eq!(allocate_speaker_id(&clients, "lobby"), Some(1));
+    }
+
+    #[test]
+    fn should_list_speakers_of_room() {
+        let mut clients = HashMap::new();
+        let (id, _rx) = join(&mut clients, "lobby");
+        join(&mut clients, "ranked");
+
+        let speakers = speakers(&clients, "lobby");
+        assert_eq!(speakers.len(), 1);
+        assert_eq!(speakers[0].client_id, id);
+    }
+
+    #[test]
+    fn should_list_roster_of_room_by_speaker_id() {
+        let mut clients = HashMap::new();
+        let (first, _rx) = join(&mut clients, "lobby");
+        let (second, _rx) = join(&mut clients, "lobby");
+        join(&mut clients, "ranked");
+        clients.get_mut(&second).unwrap().update(UserUpdatePacket {
+            summoner: None,
+            flags: 1,
+        });
+
+        let roster = roster(&clients, "lobby");
+        assert_eq!(roster.len(), 2);
+        assert_eq!((roster[0].client_id, roster[0].flags), (first, 0));
+        assert_eq!((roster[1].client_id, roster[1].flags), (second, 1));
+    }
+
+    #[tokio::test]
+    async fn should_broadcast_within_room_only() {
+        let mut clients = HashMap::new();
+        let (sender, mut sender_rx) = join(&mut clients, "lobby");
+        let (_, mut lobby_rx) = join(&mut clients, "lobby");
+        let (_, mut ranked_rx) = join(&mut clients, "ranked");
+
+        let packet = Packet::new(common::packet::DisconnectPacket).unwrap();
+        broadcast(&clients, "lobby", sender, &common::packet::DisconnectPacket)
+            .await
+            .unwrap();
+
+        assert_eq!(lobby_rx.recv().await, Some(packet));
+        assert!(sender_rx.try_recv().is_err());
+        assert!(ranked_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_broadcast_past_clients_gone_or_behind() {
+        let mut clients = HashMap::new();
+        let (sender, _sender_rx) = join(&mut clients, "lobby");
+        let (_, gone_rx) = join(&mut clients, "lobby");
+        let (_, mut behind_rx) = join(&mut clients, "lobby");
+        let (_, mut lobby_rx) = join(&mut clients, "lobby");
+        drop(gone_rx);
+
+        let first = PingPacket { timestamp: 1 };
+        let second = PingPacket { timestamp: 2 };
+        broadcast(&clients, "lobby", sender, &first).await.unwrap();
+        lobby_rx.recv().await.unwrap();
+        broadcast(&clients, "lobby", sender, &second).await.unwrap();
+
+        let received = lobby_rx.recv().await.unwrap();
+        assert_eq!(PingPacket::decode(&received.data).unwrap(), second);
+        let received = behind_rx.recv().await.unwrap();
+        assert_eq!(PingPacket::decode(&received.data).unwrap(), first);
+        assert!(
+            behind_rx.try_recv().is_err(),
+            "expected a full queue to miss out"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_encode_broadcast_per_client_format() {
+        let mut clients = HashMap::new();
+        let (sender, _sender_rx) = join(&mut clients, "lobby");
+        let (legacy, mut legacy_rx) = join(&mut clients, "lobby");
+        let (current, mut current_rx) = join(&mut clients, "lobby");
+        clients
+            .get_mut(&legacy)
+            .unwrap()
+            .set_capabilities(Capabilities {
+                formats: WireFormat::Bincode.bit(),
+                ..Default::default()
+            });
+        clients
+            .get_mut(&current)
+            .unwrap()
+            .set_capabilities(Capabilities::default());
+
+        let ping = PingPacket { timestamp: 42 };
+        broadcast(&clients, "lobby", sender, &ping).await.unwrap();
+
+        let legacy = legacy_rx.recv().await.unwrap();
+        assert_eq!(PingPacket::decode(&legacy.data).unwrap(), ping);
+        let current = current_rx.recv().await.unwrap();
+        assert_eq!(
+            PingPacket::decode_as(&current.data, WireFormat::MessagePack).unwrap(),
+            ping
+        );
+    }
+}
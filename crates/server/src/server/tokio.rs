@@ -2,23 +2,54 @@ use super::{Clients, Server};
 use crate::{
     error::ServerError,
     packets::{PacketData, PacketHandler},
-    server::client::Client,
+    server::{client::Client, room, udp::UdpTransport, websocket::WebSocketListener},
 };
-use common::packet::{ids::PacketId, Packet, MAX_PACKET_SIZE};
-use std::{borrow::Cow, collections::HashMap, sync::Arc};
+use common::{
+    address::Scheme,
+    capture::{Capture, Direction, Side},
+    datagram::{DatagramCodec, DatagramPath, MAX_DATAGRAM_SIZE},
+    heartbeat::{Heartbeat, HeartbeatConfig},
+    packet::{
+        codec::CodecError, dispatch::Dispatcher, error::DecodeError, format::WireFormat,
+        fragment::FragmentCodec, ids::PacketId, packet_type::PacketType, Capabilities, CloseReason,
+        Packet, RosterPacket, SpeakerMapPacket, UdpOfferPacket,
+    },
+    quic::{CertificateDer, PrivateKeyDer, QuicDatagrams, QuicListener},
+    secure::{self, SecureCodec, Session, StaticKey},
+    tls::TlsListener,
+    transport::{tcp::TcpListener, Connection, Listener},
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{borrow::Cow, collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
     select,
     sync::Mutex,
+    time::{interval_at, timeout, Instant},
 };
+use tokio_util::codec::{FramedRead, FramedWrite};
 use uuid::Uuid;
 
-type PacketHandlerMap = HashMap<u8, Box<dyn PacketHandler>>;
+type PacketHandlerMap = Dispatcher<Box<dyn PacketHandler>>;
+
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(200);
+
+/// How connections are set up, the same for each of them.
+#[derive(Clone)]
+struct ConnectionConfig {
+    heartbeat: HeartbeatConfig,
+    static_key: Option<Arc<StaticKey>>,
+    udp: Option<Arc<UdpTransport>>,
+}
 
 pub(crate) struct TokioServer {
     handlers: Arc<PacketHandlerMap>,
     clients: Arc<Clients>,
+    heartbeat: HeartbeatConfig,
+    capture_dir: Option<PathBuf>,
+    static_key: Option<Arc<StaticKey>>,
+    quic_identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    tls_identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    websocket_addr: Option<String>,
 }
 
 impl TokioServer {
@@ -26,73 +57,593 @@ impl TokioServer {
         Self {
             handlers: Arc::new(PacketHandlerMap::new()),
             clients: Arc::new(Mutex::new(HashMap::new())),
+            heartbeat: HeartbeatConfig::default(),
+            capture_dir: None,
+            static_key: None,
+            quic_identity: None,
+            tls_identity: None,
+            websocket_addr: None,
+        }
+    }
+
+    /// Opens the capture of a new connection, if capturing is on.
+    fn capture(capture_dir: Option<&PathBuf>, client_id: Uuid) -> Capture {
+        let Some(dir) = capture_dir else {
+            return Capture::default();
+        };
+
+        let path = dir.join(format!("{}.lvcp", client_id));
+        match Capture::create(&path, Side::Server) {
+            Ok(capture) => capture,
+            Err(e) => {
+                println!("Failed to create capture {}: {}", path.display(), e);
+                Capture::default()
+            }
+        }
+    }
+
+    /// Serves every connection `listener` accepts, until it fails.
+    async fn accept<L: Listener>(
+        mut listener: L,
+        handlers: Arc<PacketHandlerMap>,
+        clients: Arc<Clients>,
+        config: ConnectionConfig,
+        capture_dir: Option<PathBuf>,
+    ) -> Result<(), ServerError> {
+        loop {
+            let incoming = listener.accept().await?;
+            let handlers = handlers.clone();
+            let clients = clients.clone();
+            let capture_dir = capture_dir.clone();
+            let config = config.clone();
+
+            tokio::spawn(async move {
+                // Peers that never finish setting up are as dead as silent ones.
+                let heartbeat = config.heartbeat;
+                match timeout(heartbeat.interval * heartbeat.max_missed, incoming).await {
+                    Ok(Ok(connection)) => {
+                        Self::serve(handlers, clients, config, capture_dir, connection).await
+                    }
+                    Ok(Err(e)) => println!("Failed to set up connection: {}", e),
+                    Err(_) => println!("Connection was never set up"),
+                }
+            });
+        }
+    }
+
+    /// Serves a client over `connection` until it is gone.
+    async fn serve(
+        handlers: Arc<PacketHandlerMap>,
+        clients: Arc<Clients>,
+        config: ConnectionConfig,
+        capture_dir: Option<PathBuf>,
+        connection: Connection,
+    ) {
+        let client_id = Uuid::new_v4();
+        println!("Client connected: {}", client_id);
+        let capture = Self::capture(capture_dir.as_ref(), client_id);
+
+        if let Err(e) = TokioServer::handle_stream(
+            client_id,
+            handlers,
+            clients.clone(),
+            config.clone(),
+            capture,
+            connection,
+        )
+        .await
+        {
+            println!("Error: {}", e);
+        }
+
+        let mut clients = clients.lock().await;
+        if let Some(client) = clients.remove(&client_id) {
+            if let (Some(udp), Some(DatagramPath::Udp(link))) = (&config.udp, client.datagrams()) {
+                udp.close(link.token()).await;
+            }
+            if let Err(e) = Self::announce_leave(&clients, &client).await {
+                println!("Failed to announce leave of {}: {}", client_id, e);
+            }
         }
+        println!("Client disconnected: {}", client_id);
     }
 
     async fn handle_stream(
         client_id: Uuid,
         handlers: Arc<PacketHandlerMap>,
         clients: Arc<Clients>,
-        stream: TcpStream,
+        config: ConnectionConfig,
+        capture: Capture,
+        connection: Connection,
     ) -> Result<(), ServerError> {
-        let (write_tx, mut write_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(32);
-        let (mut read, mut write) = stream.into_split();
+        let ConnectionConfig {
+            heartbeat,
+            static_key,
+            udp,
+        } = config;
+        let Connection {
+            mut stream, quic, ..
+        } = connection;
+        let session = match static_key {
+            Some(key) => Some(
+                // Peers that never complete the handshake are as dead as silent ones.
+                timeout(
+                    heartbeat.interval * heartbeat.max_missed,
+                    secure::respond(&mut stream, &key),
+                )
+                .await
+                .map_err(|_| ServerError::HeartbeatTimeout)??,
+            ),
+            None => None,
+        };
+        let codec = || match &session {
+            Some(session) => session.codec(FragmentCodec::default()),
+            None => SecureCodec::plaintext(FragmentCodec::default()),
+        };
+        let (read_codec, write_codec) = (codec(), codec());
 
-        let read_handle = tokio::spawn(async move {
-            let mut buffer = Vec::with_capacity(MAX_PACKET_SIZE * 2);
-            loop {
-                let handlers = handlers.clone();
-                let mut temp_buffer = [0; MAX_PACKET_SIZE];
-                let bytes_read = read.read(&mut temp_buffer).await?;
-                if bytes_read == 0 {
-                    return Err(ServerError::ConnectionClosedByPeer);
-                }
+        let (write_tx, mut write_rx) = tokio::sync::mpsc::channel::<Packet>(32);
+        let (read, write) = tokio::io::split(stream);
+        let mut reader = FramedRead::new(read, read_codec);
+        let mut writer = FramedWrite::new(write, write_codec);
 
-                buffer.extend_from_slice(&temp_buffer[..bytes_read]);
+        let datagrams = quic.map(|quic| Arc::new(QuicDatagrams::new(quic, capture.clone())));
+        {
+            let mut client = Client::new(client_id, write_tx);
+            client.set_heartbeat(Heartbeat::new(heartbeat));
+            if let Some(datagrams) = &datagrams {
+                client.set_datagrams(DatagramPath::Quic(datagrams.clone()));
+            }
+            clients.lock().await.insert(client_id, client);
+        }
+        let datagram_handle = datagrams.map(|datagrams| {
+            tokio::spawn(Self::receive_quic_datagrams(
+                client_id,
+                datagrams,
+                handlers.clone(),
+                clients.clone(),
+            ))
+        });
 
-                while let Ok(packet) = Packet::decode(&mut buffer) {
-                    if let Err(e) = Self::process_packet(client_id, handlers.clone(), packet).await
-                    {
-                        println!("Processing packet error: {}", e);
-                        return Err(e);
+        let read_clients = clients.clone();
+        let read_capture = capture.clone();
+        let read_handle = tokio::spawn(async move {
+            // Payload format of the client, known once the handshake completed.
+            let mut format = None;
+            while let Some(packet) = reader.next().await {
+                let packet = match packet {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        println!("Closing connection to {}: {}", client_id, e);
+                        return Err(e.into());
                     }
+                };
+                read_capture.record(Direction::Received, &packet);
+                let is_connect = packet.packet_id == PacketId::ConnectPacket.to_u8();
+                if format.is_none() && !is_connect {
+                    return Err(ServerError::HandshakeRequired);
+                }
+                // Joining again would leave the room without a word to it.
+                if format.is_some() && is_connect {
+                    return Err(ServerError::HandshakeRepeated);
                 }
 
-                if buffer.len() > MAX_PACKET_SIZE * 2 {
-                    println!("Buffer length: {}", buffer.len());
-                    return Err(ServerError::FailedToProcessPacket);
+                let packet_format = format.unwrap_or(WireFormat::HANDSHAKE);
+                if let Err(e) =
+                    Self::process_packet(client_id, handlers.clone(), packet, packet_format).await
+                {
+                    println!("Processing packet error: {}", e);
+                    return Err(e);
+                }
+                if is_connect {
+                    let mut clients = read_clients.lock().await;
+                    let Some(client) = clients.get_mut(&client_id) else {
+                        continue;
+                    };
+                    format = Some(client.format());
+                    if let Some(udp) = &udp {
+                        Self::offer_udp(
+                            client,
+                            udp,
+                            session.as_ref(),
+                            read_capture.clone(),
+                            handlers.clone(),
+                        )
+                        .await?;
+                    }
                 }
             }
+
+            Err(ServerError::ConnectionClosedByPeer)
         });
 
         let write_handle = tokio::spawn(async move {
             while let Some(packet) = write_rx.recv().await {
-                write.write_all(&packet).await?;
-                write.flush().await?;
+                capture.record(Direction::Sent, &packet);
+                writer.send(packet).await?;
             }
 
             Ok(())
         });
 
-        {
-            let mut clients = clients.lock().await;
-            clients.insert(client_id, Client::new(client_id, write_tx));
-        }
+        let heartbeat_clients = clients.clone();
+        let heartbeat_handle = tokio::spawn(async move {
+            let mut interval = interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+            loop {
+                interval.tick().await;
 
-        select! {
+                let mut clients = heartbeat_clients.lock().await;
+                let Some(client) = clients.get_mut(&client_id) else {
+                    return Ok(());
+                };
+                let Some(ping) = client.heartbeat_mut().ping() else {
+                    println!("Missed too many heartbeats from {}", client_id);
+                    return Err(ServerError::HeartbeatTimeout);
+                };
+                // Unanswered ticks still count before the handshake, so silent
+                // connections get dropped, but pings only go to joined clients.
+                if client.capabilities().is_some() {
+                    // A client too far behind to take the ping misses it, rather
+                    // than hold `clients` until it catches up.
+                    match client.try_send_packet(ping).await {
+                        Ok(()) | Err(ServerError::ClientBacklogged) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        });
+
+        // The write task is left running so queued packets, such as a handshake
+        // rejection, still get flushed once the client is removed.
+        let read_abort = read_handle.abort_handle();
+        let heartbeat_abort = heartbeat_handle.abort_handle();
+        let result = select! {
             Ok(read_result) = read_handle => {
                 read_result
             },
             Ok(write_result) = write_handle => {
                 write_result
+            },
+            Ok(heartbeat_result) = heartbeat_handle => {
+                heartbeat_result
+            }
+        };
+        read_abort.abort();
+        heartbeat_abort.abort();
+        if let Some(handle) = datagram_handle {
+            handle.abort();
+        }
+
+        if let Err(e) = &result {
+            if let Some(reason) = e.close_reason() {
+                if let Some(client) = clients.lock().await.get(&client_id) {
+                    // Best effort, the writer may be what failed in the first place.
+                    let _ = client.close(reason, e.to_string()).await;
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Offers the datagram path to a client that just joined, if it can take it.
+    async fn offer_udp(
+        client: &mut Client,
+        udp: &UdpTransport,
+        session: Option<&Session>,
+        capture: Capture,
+        handlers: Arc<PacketHandlerMap>,
+    ) -> Result<(), ServerError> {
+        let supported = client
+            .capabilities()
+            .is_some_and(|capabilities| capabilities.has_transport(Capabilities::TRANSPORT_UDP));
+        if !supported {
+            return Ok(());
+        }
+
+        let port = udp.port()?;
+        let (link, audio) = udp.open(client.id(), session, capture).await;
+        let offer = UdpOfferPacket {
+            port,
+            token: link.token(),
+        };
+        // A client too far behind to take the offer goes without, rather than
+        // hold `clients` until it catches up.
+        if let Err(e) = client.try_send_packet(offer).await {
+            udp.close(link.token()).await;
+            return match e {
+                ServerError::ClientBacklogged => Ok(()),
+                e => Err(e),
+            };
+        }
+
+        tokio::spawn(Self::process_datagrams(
+            client.id(),
+            audio,
+            handlers,
+            client.format(),
+        ));
+        client.set_datagrams(DatagramPath::Udp(link));
+        Ok(())
+    }
+
+    /// Handles every datagram arriving on the server's UDP socket.
+    ///
+    /// Only probes and audio travel over UDP. A datagram that does not open
+    /// is dropped without touching its connection, as anyone can send one.
+    /// Audio is queued for its client, or dropped if that one is behind.
+    async fn receive_datagrams(udp: Arc<UdpTransport>) {
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (length, from) = match udp.socket().recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    println!("Failed to receive datagram: {}", e);
+                    continue;
+                }
+            };
+            let datagram = &buffer[..length];
+            let Some(token) = DatagramCodec::token(datagram) else {
+                continue;
+            };
+            let Some(client) = udp.client(token).await else {
+                continue;
+            };
+            let packet = match client.link.receive(datagram, from) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    println!("Dropping datagram from {}: {}", from, e);
+                    continue;
+                }
+            };
+
+            if packet.packet_id == PacketId::UdpProbePacket.to_u8() {
+                if let Err(e) = client.link.send(packet).await {
+                    println!("Failed to answer UDP probe of {}: {}", client.client_id, e);
+                }
+            } else if packet.packet_id == PacketId::AudioPacket.to_u8() {
+                if let Err(e) = client.audio.try_send(packet) {
+                    println!("Dropping datagram from {}: {}", client.client_id, e);
+                }
+            }
+        }
+    }
+
+    /// Handles the audio a client sent over UDP, until its path is closed.
+    async fn process_datagrams(
+        client_id: Uuid,
+        mut audio: tokio::sync::mpsc::Receiver<Packet>,
+        handlers: Arc<PacketHandlerMap>,
+        format: WireFormat,
+    ) {
+        while let Some(packet) = audio.recv().await {
+            if let Err(e) = Self::process_packet(client_id, handlers.clone(), packet, format).await
+            {
+                println!("Processing datagram error: {}", e);
+            }
+        }
+    }
+
+    /// Handles the datagrams of a client's QUIC connection, which only carry
+    /// audio, until the connection is gone.
+    async fn receive_quic_datagrams(
+        client_id: Uuid,
+        datagrams: Arc<QuicDatagrams>,
+        handlers: Arc<PacketHandlerMap>,
+        clients: Arc<Clients>,
+    ) {
+        loop {
+            let packet = match datagrams.receive().await {
+                Ok(packet) => packet,
+                Err(CodecError::Io(_)) => return,
+                Err(e) => {
+                    println!("Dropping datagram from {}: {}", client_id, e);
+                    continue;
+                }
+            };
+            if packet.packet_id != PacketId::AudioPacket.to_u8() {
+                continue;
+            }
+            // Audio only counts once the client joined.
+            let format = clients
+                .lock()
+                .await
+                .get(&client_id)
+                .filter(|client| client.capabilities().is_some())
+                .map(Client::format);
+            let Some(format) = format else {
+                continue;
+            };
+            if let Err(e) = Self::process_packet(client_id, handlers.clone(), packet, format).await
+            {
+                println!("Processing datagram error: {}", e);
+            }
+        }
+    }
+
+    /// Accepts TCP connections on `addr`, with audio over UDP next to them.
+    async fn run_tcp(&mut self, addr: &str) -> Result<(), ServerError> {
+        let listener = TcpListener::bind(addr).await?;
+        println!("Server started on: {}", addr);
+        self.serve_tcp(listener).await
+    }
+
+    /// Serves the connections `listener` accepts, with audio over UDP on the
+    /// same port.
+    async fn serve_tcp(&mut self, listener: TcpListener) -> Result<(), ServerError> {
+        // Audio can always fall back to TCP, so going without UDP is not fatal.
+        let udp = match UdpTransport::bind(listener.local_addr()?).await {
+            Ok(udp) => {
+                let udp = Arc::new(udp);
+                tokio::spawn(Self::receive_datagrams(udp.clone()));
+                Some(udp)
             }
+            Err(e) => {
+                println!("Failed to bind UDP, audio stays on TCP: {}", e);
+                None
+            }
+        };
+        self.run_on(listener, udp).await
+    }
+
+    /// Accepts QUIC connections on `addr`, see [`common::quic`].
+    async fn run_quic(&mut self, addr: &str) -> Result<(), ServerError> {
+        let Some((chain, key)) = &self.quic_identity else {
+            return Err(ServerError::QuicCertificateMissing);
+        };
+        let listener = QuicListener::bind(addr, chain.clone(), key.clone_key()).await?;
+        println!("Server started on: quic://{}", addr);
+
+        // Audio goes out as datagrams of the connection itself.
+        self.run_on(listener, None).await
+    }
+
+    /// Accepts TCP connections on `addr` that start with a TLS handshake,
+    /// see [`common::tls`].
+    async fn run_tls(&mut self, addr: &str) -> Result<(), ServerError> {
+        let Some((chain, key)) = &self.tls_identity else {
+            return Err(ServerError::TlsCertificateMissing);
+        };
+        let listener = TlsListener::new(
+            TcpListener::bind(addr).await?,
+            chain.clone(),
+            key.clone_key(),
+        )?;
+        println!("Server started on: tls://{}", addr);
+
+        self.run_on(listener, None).await
+    }
+
+    /// Serves the connections of any transport, see [`common::transport`],
+    /// offering those over TCP a datagram path on `udp`.
+    pub async fn run_on<L: Listener>(
+        &mut self,
+        listener: L,
+        udp: Option<Arc<UdpTransport>>,
+    ) -> Result<(), ServerError> {
+        let config = ConnectionConfig {
+            heartbeat: self.heartbeat,
+            static_key: self.static_key.clone(),
+            udp,
+        };
+        Self::accept(
+            listener,
+            self.handlers.clone(),
+            self.clients.clone(),
+            config,
+            self.capture_dir.clone(),
+        )
+        .await
+    }
+
+    /// Tells every client the server is going away and drops them all.
+    ///
+    /// Connection writers flush what is queued once their client is dropped,
+    /// so this waits a short grace period for the close packets to go out.
+    pub async fn shutdown(clients: &Clients) {
+        let clients: Vec<Client> = clients.lock().await.drain().map(|(_, c)| c).collect();
+        for client in &clients {
+            let _ = client
+                .close(CloseReason::ServerShutdown, "server is shutting down")
+                .await;
         }
+        drop(clients);
+
+        tokio::time::sleep(SHUTDOWN_GRACE).await;
+    }
+
+    /// Tells the rest of the room that the client's speaker id is free again.
+    async fn announce_leave(
+        clients: &HashMap<Uuid, Client>,
+        client: &Client,
+    ) -> Result<(), ServerError> {
+        let (Some(room), Some(speaker)) = (client.room(), client.speaker()) else {
+            return Ok(());
+        };
+
+        let packet = SpeakerMapPacket {
+            added: Vec::new(),
+            removed: vec![speaker.id],
+        };
+        room::broadcast(clients, room, client.id(), &packet).await?;
+        room::broadcast(
+            clients,
+            room,
+            client.id(),
+            &RosterPacket::UserLeft(client.id()),
+        )
+        .await
+    }
+
+    pub fn set_heartbeat(&mut self, heartbeat: HeartbeatConfig) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Records every connection to `<dir>/<client id>.lvcp`, see [`common::capture`].
+    pub fn set_capture_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.capture_dir = Some(dir.into());
+    }
+
+    /// Requires every connection to start with a secure handshake, see
+    /// [`common::secure`], in which the server authenticates with `key`.
+    pub fn set_static_key(&mut self, key: StaticKey) {
+        self.static_key = Some(Arc::new(key));
+    }
+
+    /// Lets clients connect over QUIC, presenting the certificate `chain` with
+    /// its private `key`.
+    pub fn set_quic_certificate(
+        &mut self,
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) {
+        self.quic_identity = Some((chain, key));
+    }
+
+    /// Lets clients connect over TLS, presenting the certificate `chain` with
+    /// its private `key`.
+    pub fn set_tls_certificate(
+        &mut self,
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) {
+        self.tls_identity = Some((chain, key));
+    }
+
+    /// Accepts WebSocket connections on `addr` as well, for clients that
+    /// cannot open raw TCP, such as browser pages. Their users share rooms
+    /// with everyone connected otherwise.
+    pub fn set_websocket_addr(&mut self, addr: impl Into<String>) {
+        self.websocket_addr = Some(addr.into());
+    }
+
+    /// Serves the connections `listener` accepts in the background, next to
+    /// the main listener.
+    fn accept_websockets(&self, listener: WebSocketListener) {
+        // Browsers have no way to take up a datagram path.
+        let config = ConnectionConfig {
+            heartbeat: self.heartbeat,
+            static_key: self.static_key.clone(),
+            udp: None,
+        };
+        let accept = Self::accept(
+            listener,
+            self.handlers.clone(),
+            self.clients.clone(),
+            config,
+            self.capture_dir.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = accept.await {
+                println!("Stopped accepting WebSockets: {}", e);
+            }
+        });
     }
 
-    pub fn add_handler(&mut self, id: PacketId, handler: Box<dyn PacketHandler>) {
+    pub fn add_handler<P: PacketType>(&mut self, handler: Box<dyn PacketHandler>) {
         Arc::get_mut(&mut self.handlers)
             .unwrap()
-            .insert(id as u8, handler);
+            .register::<P>(handler);
     }
 }
 
@@ -100,34 +651,17 @@ impl Server for TokioServer {
     type Handlers = PacketHandlerMap;
 
     async fn run(&mut self, addr: Cow<'_, str>) -> Result<(), ServerError> {
-        let listener = TcpListener::bind(Cow::into_owned(addr.clone())).await?;
-        println!("Server started on: {}", addr);
-
-        let handlers = self.handlers.clone();
-        let clients = self.clients.clone();
-        loop {
-            let handlers = handlers.clone();
-            let clients = clients.clone();
-
-            let (stream, _) = listener.accept().await.unwrap();
-
-            tokio::spawn(async move {
-                let client_id = Uuid::new_v4();
-                println!("Client connected: {}", client_id);
-
-                if let Err(e) =
-                    TokioServer::handle_stream(client_id, handlers.clone(), clients.clone(), stream)
-                        .await
-                {
-                    println!("Error: {}", e);
-                }
+        if let Some(websocket_addr) = &self.websocket_addr {
+            let listener = WebSocketListener::bind(websocket_addr).await?;
+            println!("Accepting WebSockets on: {}", websocket_addr);
+            self.accept_websockets(listener);
+        }
 
-                {
-                    let mut clients = clients.lock().await;
-                    clients.remove(&client_id);
-                    println!("Client disconnected: {}", client_id);
-                }
-            });
+        match Scheme::split(&addr) {
+            Some((Scheme::Tcp, addr)) => self.run_tcp(addr).await,
+            Some((Scheme::Quic, addr)) => self.run_quic(addr).await,
+            Some((Scheme::Tls, addr)) => self.run_tls(addr).await,
+            None => Err(ServerError::UnsupportedScheme(addr.into_owned())),
         }
     }
 
@@ -135,13 +669,14 @@ impl Server for TokioServer {
         client_id: Uuid,
         handlers: Arc<Self::Handlers>,
         packet: Packet,
+        format: WireFormat,
     ) -> Result<(), ServerError> {
         let packet_id = match PacketId::from_u8(packet.packet_id) {
             Some(packet_id) => packet_id,
-            None => return Err(ServerError::InvalidPacket),
+            None => return Err(DecodeError::UnknownPacketId(packet.packet_id).into()),
         };
 
-        let handler = match handlers.get(&(packet_id.clone() as u8)) {
+        let handler = match handlers.handler(packet_id) {
             Some(handler) => handler.as_ref(),
             None => {
                 return Err(ServerError::HandlerNotFound);
@@ -149,7 +684,7 @@ impl Server for TokioServer {
         };
 
         handler
-            .process(PacketData::new(client_id, packet_id, packet.data))
+            .process(PacketData::new(client_id, packet_id, packet.data).with_format(format))
             .await
     }
 
@@ -162,45 +697,129 @@ impl Server for TokioServer {
 mod tests {
     use super::*;
     use crate::packets::handlers;
-    use common::packet::{AudioPacket, ConnectPacket, DisconnectPacket};
+    use bytes::{Bytes, BytesMut};
+    use common::capture::CaptureReader;
+    use common::datagram::DatagramCodec;
+    use common::packet::MAX_PACKET_SIZE;
+    use common::packet::{
+        codec::PacketCodec, RelayedAudioPacket, RelayedChatPacket, UdpOfferPacket, UdpProbePacket,
+        UdpReadyPacket,
+    };
+    use common::packet::{
+        packet_type::PacketType, AudioPacket, Capabilities, ChatHistoryPacket, ChatPacket,
+        ClosePacket, ConnectPacket, ConnectResponsePacket, ConnectStatus, DisconnectPacket,
+        PingPacket, PongPacket, PROTOCOL_VERSION,
+    };
+    use common::quic;
+    use common::tls::{Fingerprint, TlsConnector, Trust};
+    use common::transport::memory::{self, MemoryConnector, MemoryListener};
+    use common::transport::{tcp::TcpConnector, Connector};
     use std::io::Error;
     use std::time::Duration;
-    use tokio::net::TcpStream;
+    use tokio::net::{TcpStream, UdpSocket};
     use tokio::time::sleep;
-    use tokio::{io::AsyncWriteExt, select};
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+        select,
+    };
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// Reads up to the next packet of type `packet_id`, skipping the others.
+    async fn next_packet_of(
+        reader: &mut FramedRead<impl AsyncRead + Unpin, FragmentCodec>,
+        packet_id: PacketId,
+    ) -> Packet {
+        loop {
+            let packet = reader.next().await.expect("connection closed").unwrap();
+            if packet.packet_id == packet_id.to_u8() {
+                return packet;
+            }
+        }
+    }
+
+    /// Sequence of the next audio relayed over `reader`.
+    async fn relayed_sequence(
+        reader: &mut FramedRead<impl AsyncRead + Unpin, FragmentCodec>,
+    ) -> u16 {
+        let packet = next_packet_of(reader, PacketId::RelayedAudioPacket).await;
+        RelayedAudioPacket::decode_as(&packet.data, WireFormat::MessagePack)
+            .unwrap()
+            .audio
+            .sequence
+    }
 
     async fn start_server(addr: &str) -> Result<(), ServerError> {
+        let mut server = server_with_handlers();
+        server.run(Cow::Borrowed(addr)).await
+    }
+
+    async fn start_server_in_memory(listener: MemoryListener) -> Result<(), ServerError> {
+        server_with_handlers().run_on(listener, None).await
+    }
+
+    fn server_with_handlers() -> TokioServer {
         let mut server = TokioServer::new();
 
-        server.add_handler(
-            PacketId::ConnectPacket,
-            Box::new(handlers::connect::ConnectHandler {}),
-        );
-        server.add_handler(
-            PacketId::AudioPacket,
-            Box::new(handlers::audio::AudioHandler(server.clients().clone())),
-        );
-        server.add_handler(
-            PacketId::DisconnectPacket,
-            Box::new(handlers::disconnect::DisconnectHandler {}),
-        );
-        server.run(Cow::Borrowed(addr)).await
+        handlers::register(&mut server);
+        server
     }
 
-    async fn check_for_closed(mut client: TcpStream) -> Result<(), std::io::Error> {
+    async fn check_for_closed(mut client: impl AsyncRead + Unpin) -> Result<(), std::io::Error> {
         let mut buffer = [1; MAX_PACKET_SIZE];
         let n = client.read(&mut buffer[..]).await?;
 
         if n == 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "connection closed",
-            ));
+            return Err(std::io::Error::other("connection closed"));
         }
 
         Ok(())
     }
 
+    /// Reads until the server hangs up and returns the reason it gave, if any.
+    async fn read_close_reason(
+        mut client: impl AsyncRead + Unpin,
+    ) -> Result<Option<CloseReason>, Error> {
+        let mut buffer = Vec::new();
+        client.read_to_end(&mut buffer).await?;
+
+        let mut buffer = BytesMut::from(buffer.as_slice());
+        let mut reason = None;
+        while !buffer.is_empty() {
+            let packet = Packet::decode(&mut buffer).unwrap();
+            if packet.packet_id == PacketId::ClosePacket.to_u8() {
+                reason = Some(ClosePacket::decode(&packet.data).unwrap().reason);
+            }
+        }
+
+        Ok(reason)
+    }
+
+    #[test]
+    fn should_register_handlers_for_client_packets() {
+        let server = server_with_handlers();
+        assert_eq!(
+            server.handlers.unhandled(),
+            vec![
+                PacketId::ConnectResponsePacket,
+                PacketId::RelayedAudioPacket,
+                PacketId::SpeakerMapPacket,
+                PacketId::ClosePacket,
+                PacketId::FragmentPacket,
+                PacketId::RosterPacket,
+                PacketId::RelayedSpeakingPacket,
+                PacketId::RelayedChatPacket,
+                PacketId::ChatHistoryPacket,
+                PacketId::RelayedCalloutPacket,
+                PacketId::UdpOfferPacket,
+                PacketId::UdpProbePacket,
+                PacketId::MeshHelloPacket,
+                PacketId::MeshMembersPacket,
+                PacketId::RejectedPacket,
+            ],
+            "expected only server-to-client packets to go unhandled"
+        );
+    }
+
     #[tokio::test]
     async fn should_open_a_server_connection_on_given_address() {
         let addr = "127.0.0.1:1025";
@@ -209,7 +828,12 @@ mod tests {
         let client = tokio::spawn(async move {
             let mut client = TcpStream::connect(addr).await?;
             client
-                .write_all(Packet::new(ConnectPacket).unwrap().encode().as_slice())
+                .write_all(
+                    Packet::new(ConnectPacket::default())
+                        .unwrap()
+                        .encode()
+                        .as_slice(),
+                )
                 .await?;
             client.flush().await
         });
@@ -240,14 +864,17 @@ mod tests {
         }
     }
 
-    async fn connect_and_send_packets(addr: &str) -> Result<(), Error> {
-        let packet = Packet::new(AudioPacket { track: vec![1] })
-            .unwrap()
-            .encode();
-        let connect = Packet::new(ConnectPacket).unwrap().encode();
+    async fn connect_and_send_packets(connector: MemoryConnector) -> Result<(), Error> {
+        let packet = Packet::new(AudioPacket {
+            track: vec![1],
+            ..Default::default()
+        })
+        .unwrap()
+        .encode();
+        let connect = Packet::new(ConnectPacket::default()).unwrap().encode();
         let disconnect = Packet::new(DisconnectPacket).unwrap().encode();
 
-        let mut client = TcpStream::connect(addr).await?;
+        let mut client = connector.connect().await?.stream;
         client.write_all(connect.as_slice()).await?;
         client.flush().await?;
 
@@ -265,10 +892,10 @@ mod tests {
 
     #[tokio::test]
     async fn should_process_multiple_packets() {
-        let addr = "127.0.0.1:1027";
+        let (listener, connector) = memory::channel();
 
-        let server = tokio::spawn(async move { start_server(addr).await });
-        let client = tokio::spawn(async move { connect_and_send_packets(addr).await });
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(connect_and_send_packets(connector.clone()));
 
         select! {
             Ok(result) = server => {
@@ -282,10 +909,10 @@ mod tests {
 
     #[tokio::test]
     async fn should_process_multiple_clients() {
-        let addr = "127.0.0.1:1032";
-        let server = tokio::spawn(async move { start_server(addr).await });
-        let client = tokio::spawn(async move { connect_and_send_packets(addr).await });
-        let second_client = tokio::spawn(async move { connect_and_send_packets(addr).await });
+        let (listener, connector) = memory::channel();
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(connect_and_send_packets(connector.clone()));
+        let second_client = tokio::spawn(connect_and_send_packets(connector.clone()));
 
         select! {
             Ok(result) = server => {
@@ -302,13 +929,13 @@ mod tests {
 
     #[tokio::test]
     async fn should_close_connection_on_invalid_packet() {
-        let addr = "127.0.0.1:1028";
+        let (listener, connector) = memory::channel();
 
-        let server = tokio::spawn(async move { start_server(addr).await });
+        let server = tokio::spawn(start_server_in_memory(listener));
         let client = tokio::spawn(async move {
-            let mut client = TcpStream::connect(addr).await?;
+            let mut client = connector.connect().await?.stream;
             client.write_all(&[0, 0, 0, 0, 18]).await?;
-            check_for_closed(client).await
+            read_close_reason(client).await
         });
 
         select! {
@@ -316,21 +943,29 @@ mod tests {
                 assert!(result.is_ok(), "expected server to keep running");
             },
             Ok(result) = client => {
-                assert!(result.is_err(), "expected client to error with invalid packet");
+                assert_eq!(result.unwrap(), Some(CloseReason::ProtocolError));
             }
         }
     }
 
     #[tokio::test]
-    async fn should_close_connection_on_empty_packet() {
-        let addr = "127.0.0.1:1029";
+    async fn should_close_connection_on_packet_before_connect() {
+        let (listener, connector) = memory::channel();
 
-        let server = tokio::spawn(async move { start_server(addr).await });
+        let server = tokio::spawn(start_server_in_memory(listener));
         let client = tokio::spawn(async move {
-            let mut client = TcpStream::connect(addr).await?;
-            client.shutdown().await?;
+            let packet = Packet::new(AudioPacket {
+                track: vec![1],
+                ..Default::default()
+            })
+            .unwrap()
+            .encode();
 
-            check_for_closed(client).await
+            let mut client = connector.connect().await?.stream;
+            client.write_all(packet.as_slice()).await?;
+            client.flush().await?;
+
+            read_close_reason(client).await
         });
 
         select! {
@@ -338,28 +973,34 @@ mod tests {
                 assert!(result.is_ok(), "expected server to keep running");
             },
             Ok(result) = client => {
-                assert!(result.is_err(), "expected client to error with invalid packet");
+                assert_eq!(result.unwrap(), Some(CloseReason::ProtocolError));
             }
         }
     }
 
     #[tokio::test]
-    async fn should_close_connection_on_buffer_overflow() {
-        let addr = "127.0.0.1:1030";
+    async fn should_close_connection_on_repeated_connect() {
+        let (listener, connector) = memory::channel();
 
-        let server = tokio::spawn(async move { start_server(addr).await });
+        let server = tokio::spawn(start_server_in_memory(listener));
         let client = tokio::spawn(async move {
-            let mut client = TcpStream::connect(addr).await?;
-            let packet = Packet::new(AudioPacket { track: Vec::new() })
-                .unwrap()
-                .encode();
-            let mut buffer = vec![1; 1024 * 3];
-            buffer.extend_from_slice(&packet);
+            // Bincode throughout, so the close packet reads like the rest.
+            let connect = Packet::new(ConnectPacket {
+                capabilities: Capabilities {
+                    formats: WireFormat::Bincode.bit(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .unwrap()
+            .encode();
 
-            client.write_all(&buffer).await?;
+            let mut client = connector.connect().await?.stream;
+            client.write_all(&connect).await?;
+            client.write_all(&connect).await?;
             client.flush().await?;
 
-            check_for_closed(client).await
+            read_close_reason(client).await
         });
 
         select! {
@@ -367,26 +1008,55 @@ mod tests {
                 assert!(result.is_ok(), "expected server to keep running");
             },
             Ok(result) = client => {
-                assert!(result.is_err(), "expected client to error with buffer overflow");
+                assert_eq!(result.unwrap(), Some(CloseReason::ProtocolError));
             }
         }
     }
 
     #[tokio::test]
-    async fn should_close_connection_on_handler_not_found() {
-        let addr = "127.0.0.1:1031";
+    async fn should_reject_protocol_version_mismatch() {
+        let (listener, connector) = memory::channel();
 
-        let server = tokio::spawn(async move {
-            let mut server = TokioServer::new();
-            server.run(Cow::Borrowed(addr)).await
-        });
+        let server = tokio::spawn(start_server_in_memory(listener));
         let client = tokio::spawn(async move {
-            let connect = Packet::new(ConnectPacket).unwrap().encode();
+            let connect = Packet::new(ConnectPacket {
+                protocol_version: PROTOCOL_VERSION - 1,
+                ..Default::default()
+            })
+            .unwrap()
+            .encode();
 
-            let mut client = TcpStream::connect(addr).await?;
+            let mut client = connector.connect().await?.stream;
             client.write_all(connect.as_slice()).await?;
             client.flush().await?;
 
+            let mut buffer = Vec::new();
+            client.read_to_end(&mut buffer).await?;
+            let packet = Packet::decode(&mut BytesMut::from(buffer.as_slice())).unwrap();
+            let response = ConnectResponsePacket::decode(&packet.data).unwrap();
+
+            Ok::<ConnectStatus, Error>(response.status)
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap(), ConnectStatus::UnsupportedVersion);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_close_connection_on_empty_packet() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let mut client = connector.connect().await?.stream;
+            client.shutdown().await?;
+
             check_for_closed(client).await
         });
 
@@ -395,7 +1065,855 @@ mod tests {
                 assert!(result.is_ok(), "expected server to keep running");
             },
             Ok(result) = client => {
-                assert!(result.is_err(), "expected client to error with buffer overflow");
+                assert!(result.is_err(), "expected client to error with invalid packet");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_close_connection_on_buffer_overflow() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let mut client = connector.connect().await?.stream;
+            let packet = Packet::new(AudioPacket {
+                track: Vec::new(),
+                ..Default::default()
+            })
+            .unwrap()
+            .encode();
+            let mut buffer = vec![1; 1024 * 3];
+            buffer.extend_from_slice(&packet);
+
+            client.write_all(&buffer).await?;
+            client.flush().await?;
+
+            read_close_reason(client).await
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap(), Some(CloseReason::ProtocolError));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_oversized_frame_header() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap().encode();
+
+            let mut client = connector.connect().await?.stream;
+            client.write_all(connect.as_slice()).await?;
+            client.write_all(&[255, 255, 255, 255, 2]).await?;
+            client.flush().await?;
+
+            let mut buffer = Vec::new();
+            client.read_to_end(&mut buffer).await?;
+            Ok::<(), Error>(())
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert!(result.is_ok(), "expected server to close without waiting for the payload");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_drop_client_after_missed_heartbeats() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(async move {
+            let mut server = server_with_handlers();
+            server.set_heartbeat(HeartbeatConfig {
+                interval: Duration::from_millis(10),
+                max_missed: 2,
+            });
+            server.run_on(listener, None).await
+        });
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap().encode();
+
+            let mut client = connector.connect().await?.stream;
+            client.write_all(connect.as_slice()).await?;
+            client.flush().await?;
+
+            let mut buffer = Vec::new();
+            client.read_to_end(&mut buffer).await?;
+
+            let mut buffer = BytesMut::from(buffer.as_slice());
+            let mut pings = 0;
+            while !buffer.is_empty() {
+                let packet = Packet::decode(&mut buffer).unwrap();
+                pings += (packet.packet_id == PacketId::PingPacket.to_u8()) as usize;
+            }
+
+            Ok::<usize, Error>(pings)
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap(), 2, "expected connection to close after two unanswered pings");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_keep_serving_while_a_client_is_backlogged() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(async move {
+            let mut server = server_with_handlers();
+            server.set_heartbeat(HeartbeatConfig {
+                interval: Duration::from_millis(10),
+                max_missed: 1000,
+            });
+            server.run_on(listener, None).await
+        });
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap().encode();
+            let audio = Packet::new_as(
+                AudioPacket {
+                    track: vec![1; 900],
+                    ..Default::default()
+                },
+                WireFormat::MessagePack,
+            )
+            .unwrap()
+            .encode();
+            let ping = Packet::new_as(PingPacket { timestamp: 7 }, WireFormat::MessagePack)
+                .unwrap()
+                .encode();
+
+            // Joins the room but never reads what gets relayed to it.
+            let mut stalled = connector.connect().await?.stream;
+            stalled.write_all(connect.as_slice()).await?;
+            sleep(Duration::from_millis(50)).await;
+
+            let (read, mut write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            write.write_all(connect.as_slice()).await?;
+            for _ in 0..1000 {
+                write.write_all(audio.as_slice()).await?;
+            }
+            // Lets a few pings come due while the stalled client is behind.
+            sleep(Duration::from_millis(50)).await;
+            write.write_all(ping.as_slice()).await?;
+            let pong = next_packet_of(&mut reader, PacketId::PongPacket).await;
+
+            drop(stalled);
+            Ok::<_, Error>(PongPacket::decode_as(&pong.data, WireFormat::MessagePack).unwrap())
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            result = timeout(Duration::from_secs(5), client) => {
+                assert_eq!(result.expect("server stalled").unwrap().unwrap().timestamp, 7);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_answer_client_ping() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap().encode();
+            let ping = Packet::new_as(PingPacket { timestamp: 7 }, WireFormat::MessagePack)
+                .unwrap()
+                .encode();
+
+            let mut client = connector.connect().await?.stream;
+            client.write_all(connect.as_slice()).await?;
+            client.write_all(ping.as_slice()).await?;
+            client.flush().await?;
+
+            let mut buffer = BytesMut::new();
+            loop {
+                client.read_buf(&mut buffer).await?;
+                while let Ok(length) = Packet::frame_length(&buffer) {
+                    if buffer.len() < length {
+                        break;
+                    }
+                    let packet = Packet::decode(&mut buffer).unwrap();
+                    if packet.packet_id == PacketId::PongPacket.to_u8() {
+                        return Ok::<PongPacket, Error>(
+                            PongPacket::decode_as(&packet.data, WireFormat::MessagePack).unwrap(),
+                        );
+                    }
+                }
+            }
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap().timestamp, 7);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_keep_handshake_format_for_clients_without_messagepack() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket {
+                capabilities: Capabilities {
+                    formats: WireFormat::Bincode.bit(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .unwrap();
+
+            let (read, write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer.send(connect).await.unwrap();
+            writer
+                .send(Packet::new(PingPacket { timestamp: 7 }).unwrap())
+                .await
+                .unwrap();
+
+            while let Some(packet) = reader.next().await {
+                let packet = packet.unwrap();
+                if packet.packet_id == PacketId::PongPacket.to_u8() {
+                    return Ok::<PongPacket, Error>(PongPacket::decode(&packet.data).unwrap());
+                }
+            }
+            Err(Error::other("connection closed before the pong"))
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap().timestamp, 7);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_capture_connections() {
+        let (listener, connector) = memory::channel();
+        let dir = std::env::temp_dir().join(format!("server-capture-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+
+        let capture_dir = dir.clone();
+        let server = tokio::spawn(async move {
+            let mut server = server_with_handlers();
+            server.set_capture_dir(capture_dir);
+            server.run_on(listener, None).await
+        });
+        let client = tokio::spawn(async move {
+            let (read, write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer
+                .send(Packet::new(ConnectPacket::default()).unwrap())
+                .await
+                .unwrap();
+
+            // Connect response, speaker and roster snapshots.
+            for _ in 0..3 {
+                reader.next().await.unwrap().unwrap();
+            }
+            Ok::<(), Error>(())
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                result.unwrap();
+            }
+        }
+
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let expected = vec![
+            (Direction::Received, PacketId::ConnectPacket.to_u8()),
+            (Direction::Sent, PacketId::ConnectResponsePacket.to_u8()),
+            (Direction::Sent, PacketId::SpeakerMapPacket.to_u8()),
+            (Direction::Sent, PacketId::RosterPacket.to_u8()),
+        ];
+        // The connection is still open, so wait for the periodic flush.
+        let records = timeout(Duration::from_secs(5), async {
+            loop {
+                let reader = CaptureReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+                assert_eq!(reader.side(), Side::Server);
+                let records: Vec<(Direction, u8)> = reader
+                    .map_while(Result::ok)
+                    .map(|record| (record.direction, record.packet.packet_id))
+                    .collect();
+                if records.len() >= expected.len() {
+                    return records;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(records, expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_announce_roster_changes_to_room() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap();
+
+            let (read, write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer.send(connect.clone()).await.unwrap();
+            // Connect response, speaker and roster snapshots.
+            for _ in 0..3 {
+                reader.next().await.unwrap().unwrap();
+            }
+
+            let mut other = connector.connect().await?.stream;
+            other.write_all(&connect.encode()).await?;
+            let mut joined = None;
+            while let Some(packet) = reader.next().await {
+                let packet = packet.unwrap();
+                if packet.packet_id != PacketId::RosterPacket.to_u8() {
+                    continue;
+                }
+                match RosterPacket::decode_as(&packet.data, WireFormat::MessagePack).unwrap() {
+                    RosterPacket::UserJoined(user) => {
+                        joined = Some(user.client_id);
+                        drop(other.shutdown().await);
+                    }
+                    RosterPacket::UserLeft(client_id) => {
+                        return Ok::<_, Error>((joined, client_id));
+                    }
+                    packet => panic!("unexpected roster packet {:?}", packet),
+                }
+            }
+            Err(Error::other("connection closed before the leave"))
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                let (joined, left) = result.unwrap();
+                assert_eq!(joined, Some(left));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_encrypt_connections_with_static_key() {
+        let (listener, connector) = memory::channel();
+        let key = StaticKey::generate().unwrap();
+        let pinned = *key.public();
+
+        let server = tokio::spawn(async move {
+            let mut server = server_with_handlers();
+            server.set_static_key(key);
+            server.run_on(listener, None).await
+        });
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap();
+
+            let mut plaintext = connector.connect().await?.stream;
+            plaintext.write_all(&connect.encode()).await?;
+            assert!(
+                check_for_closed(plaintext).await.is_err(),
+                "expected plaintext clients to be dropped"
+            );
+
+            let mut stream = connector.connect().await?.stream;
+            let session = secure::initiate(&mut stream, Some(&pinned)).await.unwrap();
+            let (read, write) = tokio::io::split(stream);
+            let mut reader = FramedRead::new(read, session.codec(FragmentCodec::default()));
+            let mut writer = FramedWrite::new(write, session.codec(FragmentCodec::default()));
+            writer.send(connect).await.unwrap();
+
+            let response = reader.next().await.unwrap().unwrap();
+            Ok::<_, Error>(ConnectResponsePacket::decode(&response.data).unwrap())
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                assert!(result.unwrap().is_accepted());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_send_chat_history_to_late_joiners() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap();
+
+            let (read, write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer.send(connect.clone()).await.unwrap();
+            // Connect response, speaker and roster snapshots.
+            for _ in 0..3 {
+                reader.next().await.unwrap().unwrap();
+            }
+            let chat = ChatPacket {
+                text: "gl hf".to_string(),
+            };
+            writer
+                .send(Packet::new_as(chat, WireFormat::MessagePack).unwrap())
+                .await
+                .unwrap();
+            // Wait for the echo, so the message is in the history before the next join.
+            loop {
+                let packet = reader.next().await.unwrap().unwrap();
+                if packet.packet_id == PacketId::RelayedChatPacket.to_u8() {
+                    break;
+                }
+            }
+
+            let (read, write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer.send(connect).await.unwrap();
+            while let Some(packet) = reader.next().await {
+                let packet = packet.unwrap();
+                if packet.packet_id == PacketId::ChatHistoryPacket.to_u8() {
+                    return Ok::<_, Error>(
+                        ChatHistoryPacket::decode_as(&packet.data, WireFormat::MessagePack)
+                            .unwrap(),
+                    );
+                }
+            }
+            Err(Error::other("connection closed before the chat history"))
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                let history = result.unwrap();
+                assert_eq!(history.messages.len(), 1);
+                assert_eq!(history.messages[0].text, "gl hf");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_carry_audio_over_udp_while_confirmed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move { server_with_handlers().serve_tcp(listener).await });
+        let client = tokio::spawn(async move {
+            let format = WireFormat::MessagePack;
+            let audio = |sequence| {
+                let audio = AudioPacket {
+                    sequence,
+                    track: vec![1],
+                    ..Default::default()
+                };
+                Packet::new_as(audio, format).unwrap()
+            };
+            let connect = Packet::new(ConnectPacket {
+                capabilities: Capabilities {
+                    transports: Capabilities::TRANSPORT_TCP | Capabilities::TRANSPORT_UDP,
+                    ..Capabilities::default()
+                },
+                ..Default::default()
+            })
+            .unwrap();
+
+            let (read, write) = TcpStream::connect(addr).await?.into_split();
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer.send(connect).await.unwrap();
+            // Connect response, speaker and roster snapshots.
+            for _ in 0..3 {
+                reader.next().await.unwrap().unwrap();
+            }
+            let offer = reader.next().await.unwrap().unwrap();
+            assert_eq!(offer.packet_id, PacketId::UdpOfferPacket.to_u8());
+            let offer = UdpOfferPacket::decode_as(&offer.data, format).unwrap();
+
+            let socket = UdpSocket::bind("127.0.0.1:0").await?;
+            socket.connect(("127.0.0.1", offer.port)).await?;
+            let mut codec = DatagramCodec::new(offer.token, SecureCodec::plaintext(PacketCodec));
+            let mut buffer = [0; MAX_DATAGRAM_SIZE];
+            let probe = Packet::new_as(UdpProbePacket::default(), format).unwrap();
+            socket.send(&codec.encode(probe.clone()).unwrap()).await?;
+            let length = socket.recv(&mut buffer).await?;
+            assert_eq!(codec.decode(&buffer[..length]).unwrap(), probe);
+
+            let (read, write) = TcpStream::connect(addr).await?.into_split();
+            let mut tcp_reader = FramedRead::new(read, FragmentCodec::default());
+            let mut tcp_writer = FramedWrite::new(write, FragmentCodec::default());
+            tcp_writer
+                .send(Packet::new(ConnectPacket::default()).unwrap())
+                .await
+                .unwrap();
+            for _ in 0..3 {
+                tcp_reader.next().await.unwrap().unwrap();
+            }
+
+            // Probed, but not confirmed yet.
+            tcp_writer.send(audio(1)).await.unwrap();
+            assert_eq!(relayed_sequence(&mut reader).await, 1);
+
+            // Handled in order, so the server took the update once ponged.
+            let confirm = |ready| {
+                [
+                    Packet::new_as(UdpReadyPacket { ready }, format).unwrap(),
+                    Packet::new_as(PingPacket { timestamp: 7 }, format).unwrap(),
+                ]
+            };
+            for packet in confirm(true) {
+                writer.send(packet).await.unwrap();
+            }
+            next_packet_of(&mut reader, PacketId::PongPacket).await;
+
+            // From TCP to UDP.
+            tcp_writer.send(audio(2)).await.unwrap();
+            let length = socket.recv(&mut buffer).await?;
+            let relayed = codec.decode(&buffer[..length]).unwrap();
+            assert_eq!(relayed.packet_id, PacketId::RelayedAudioPacket.to_u8());
+            let relayed = RelayedAudioPacket::decode_as(&relayed.data, format).unwrap();
+            assert_eq!(relayed.audio.sequence, 2);
+
+            // From UDP to TCP.
+            socket.send(&codec.encode(audio(3)).unwrap()).await?;
+            assert_eq!(relayed_sequence(&mut tcp_reader).await, 3);
+
+            // Back to TCP once the client fell back.
+            for packet in confirm(false) {
+                writer.send(packet).await.unwrap();
+            }
+            next_packet_of(&mut reader, PacketId::PongPacket).await;
+            tcp_writer.send(audio(4)).await.unwrap();
+            Ok::<_, Error>(relayed_sequence(&mut reader).await)
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap(), 4);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_skip_the_udp_offer_for_a_backlogged_client() {
+        let udp = UdpTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let (write_tx, _write_rx) = tokio::sync::mpsc::channel(1);
+        let mut client = Client::new(Uuid::new_v4(), write_tx);
+        client.set_capabilities(Capabilities {
+            transports: Capabilities::TRANSPORT_TCP | Capabilities::TRANSPORT_UDP,
+            ..Capabilities::default()
+        });
+        client
+            .send_packet(PingPacket { timestamp: 7 })
+            .await
+            .unwrap();
+
+        let offer = TokioServer::offer_udp(
+            &mut client,
+            &udp,
+            None,
+            Capture::default(),
+            Arc::new(PacketHandlerMap::new()),
+        );
+        timeout(Duration::from_secs(1), offer)
+            .await
+            .expect("expected the offer not to wait for the client")
+            .unwrap();
+        assert!(client.datagrams().is_none());
+    }
+
+    #[tokio::test]
+    async fn should_carry_audio_over_quic_across_address_changes() {
+        let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let root = certified.cert.der().clone();
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+
+        let listener = QuicListener::bind("127.0.0.1:0", vec![root.clone()], key)
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server =
+            tokio::spawn(async move { server_with_handlers().run_on(listener, None).await });
+        let client = tokio::spawn(async move {
+            let audio = |sequence| {
+                let audio = AudioPacket {
+                    sequence,
+                    track: vec![1],
+                    ..Default::default()
+                };
+                Bytes::from(
+                    Packet::new_as(audio, WireFormat::MessagePack)
+                        .unwrap()
+                        .encode(),
+                )
+            };
+            let join = || async {
+                let (endpoint, connection) = quic::connect(&addr, std::slice::from_ref(&root))
+                    .await
+                    .unwrap();
+                let (send, recv) = connection.open_bi().await.unwrap();
+                let mut reader = FramedRead::new(recv, FragmentCodec::default());
+                let mut writer = FramedWrite::new(send, FragmentCodec::default());
+                writer
+                    .send(Packet::new(ConnectPacket::default()).unwrap())
+                    .await
+                    .unwrap();
+                // Connect response, speaker and roster snapshots.
+                for _ in 0..3 {
+                    reader.next().await.unwrap().unwrap();
+                }
+                (endpoint, connection, reader, writer)
+            };
+            let relayed = |datagram: Bytes| {
+                let packet = Packet::decode(&mut BytesMut::from(&datagram[..])).unwrap();
+                assert_eq!(packet.packet_id, PacketId::RelayedAudioPacket.to_u8());
+                RelayedAudioPacket::decode_as(&packet.data, WireFormat::MessagePack)
+                    .unwrap()
+                    .audio
+                    .sequence
+            };
+
+            let (endpoint, speaker, _speaker_reader, _speaker_writer) = join().await;
+            let (_endpoint, listener, _listener_reader, _listener_writer) = join().await;
+
+            speaker.send_datagram(audio(1)).unwrap();
+            assert_eq!(relayed(listener.read_datagram().await.unwrap()), 1);
+
+            // As when the speaker's Wi-Fi switches networks.
+            endpoint.rebind(std::net::UdpSocket::bind("127.0.0.1:0")?)?;
+            speaker.send_datagram(audio(2)).unwrap();
+            assert_eq!(relayed(listener.read_datagram().await.unwrap()), 2);
+            Ok::<_, Error>(())
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                result.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_share_rooms_between_websocket_and_tcp_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let websocket_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let websocket_addr = websocket_listener.local_addr().unwrap();
+
+        let mut server = server_with_handlers();
+        server.accept_websockets(WebSocketListener::new(websocket_listener));
+        let server = tokio::spawn(async move { server.run_on(listener, None).await });
+        let client = tokio::spawn(async move {
+            let url = format!("ws://{}", websocket_addr);
+            let (mut websocket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            let connect = Packet::new(ConnectPacket::default()).unwrap();
+            websocket
+                .send(Message::binary(connect.encode()))
+                .await
+                .unwrap();
+            // Connect response, speaker and roster snapshots, a message each.
+            for _ in 0..3 {
+                let message = websocket.next().await.unwrap().unwrap();
+                let mut data = BytesMut::from(&message.into_data()[..]);
+                Packet::decode(&mut data).unwrap();
+                assert!(data.is_empty(), "expected a packet per message");
+            }
+
+            let (read, write) = TcpStream::connect(addr).await?.into_split();
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer.send(connect).await.unwrap();
+            for _ in 0..3 {
+                reader.next().await.unwrap().unwrap();
+            }
+            let chat = ChatPacket {
+                text: "gl hf".to_string(),
+            };
+            writer
+                .send(Packet::new_as(chat, WireFormat::MessagePack).unwrap())
+                .await
+                .unwrap();
+
+            while let Some(message) = websocket.next().await {
+                let packet =
+                    Packet::decode(&mut BytesMut::from(&message.unwrap().into_data()[..])).unwrap();
+                if packet.packet_id == PacketId::RelayedChatPacket.to_u8() {
+                    return Ok::<_, Error>(
+                        RelayedChatPacket::decode_as(&packet.data, WireFormat::MessagePack)
+                            .unwrap(),
+                    );
+                }
+            }
+            Err(Error::other("websocket closed before the chat message"))
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap().text, "gl hf");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_serve_clients_over_tls() {
+        let mut server = server_with_handlers();
+        let result = server.run(Cow::Borrowed("tls://127.0.0.1:0")).await;
+        assert!(matches!(result, Err(ServerError::TlsCertificateMissing)));
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+        let fingerprint = Fingerprint::of(&cert);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let listener = TlsListener::new(listener, vec![cert], key).unwrap();
+        let server = tokio::spawn(async move { server.run_on(listener, None).await });
+        let client = tokio::spawn(async move {
+            // Pinning ignores that the certificate names another host.
+            let connector = TlsConnector::new(
+                TcpConnector::new(addr),
+                "127.0.0.1",
+                &Trust::Pinned(fingerprint),
+            )
+            .unwrap();
+            let (read, write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer
+                .send(Packet::new(ConnectPacket::default()).unwrap())
+                .await
+                .unwrap();
+            let packet = reader.next().await.unwrap().unwrap();
+            Ok::<_, Error>(ConnectResponsePacket::decode(&packet.data).unwrap())
+        });
+
+        select! {
+            Ok(result) = server => {
+                panic!("expected server to keep running, got {:?}", result);
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap().status, ConnectStatus::Accepted);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reassemble_fragmented_messages() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(start_server_in_memory(listener));
+        let client = tokio::spawn(async move {
+            let display_name = "a".repeat(MAX_PACKET_SIZE * 2);
+            let connect = Packet::new(ConnectPacket {
+                display_name: display_name.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+
+            let (read, write) = tokio::io::split(connector.connect().await?.stream);
+            let mut reader = FramedRead::new(read, FragmentCodec::default());
+            let mut writer = FramedWrite::new(write, FragmentCodec::default());
+            writer.send(connect).await.unwrap();
+
+            let response = reader.next().await.unwrap().unwrap();
+            assert!(ConnectResponsePacket::decode(&response.data)
+                .unwrap()
+                .is_accepted());
+
+            let snapshot = reader.next().await.unwrap().unwrap();
+            let snapshot =
+                SpeakerMapPacket::decode_as(&snapshot.data, WireFormat::MessagePack).unwrap();
+            Ok::<bool, Error>(snapshot.added[0].display_name == display_name)
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert!(result.unwrap(), "expected display name to survive fragmentation");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_close_connection_on_handler_not_found() {
+        let (listener, connector) = memory::channel();
+
+        let server = tokio::spawn(async move {
+            let mut server = TokioServer::new();
+            server.run_on(listener, None).await
+        });
+        let client = tokio::spawn(async move {
+            let connect = Packet::new(ConnectPacket::default()).unwrap().encode();
+
+            let mut client = connector.connect().await?.stream;
+            client.write_all(connect.as_slice()).await?;
+            client.flush().await?;
+
+            read_close_reason(client).await
+        });
+
+        select! {
+            Ok(result) = server => {
+                assert!(result.is_ok(), "expected server to keep running");
+            },
+            Ok(result) = client => {
+                assert_eq!(result.unwrap(), Some(CloseReason::ProtocolError));
             }
         }
     }
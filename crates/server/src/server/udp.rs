@@ -0,0 +1,88 @@
+use common::{
+    capture::Capture,
+    datagram::{DatagramCodec, DatagramLink},
+    packet::{codec::PacketCodec, Packet},
+    secure::{SecureCodec, Session},
+};
+use std::{collections::HashMap, io, net::SocketAddr, sync::Arc};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, Mutex},
+};
+use uuid::Uuid;
+
+/// Datagram path offered to a client, see [`common::datagram`].
+#[derive(Clone)]
+pub struct UdpClient {
+    pub client_id: Uuid,
+    pub link: Arc<DatagramLink>,
+    /// Audio received from the client, handled by a task of its own so one
+    /// client never holds up the datagrams of the others.
+    pub audio: mpsc::Sender<Packet>,
+}
+
+/// The server's UDP socket, shared by every client that took up the offer.
+pub struct UdpTransport {
+    socket: Arc<UdpSocket>,
+    /// Keyed by the token each client was offered.
+    clients: Mutex<HashMap<Uuid, UdpClient>>,
+}
+
+impl UdpTransport {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            socket: Arc::new(UdpSocket::bind(addr).await?),
+            clients: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn port(&self) -> io::Result<u16> {
+        Ok(self.socket.local_addr()?.port())
+    }
+
+    /// Opens a path for `client_id` under a fresh token, sealed like its TCP
+    /// connection when that is encrypted. Audio it receives goes to the
+    /// returned queue, until the path is closed.
+    pub async fn open(
+        &self,
+        client_id: Uuid,
+        session: Option<&Session>,
+        capture: Capture,
+    ) -> (Arc<DatagramLink>, mpsc::Receiver<Packet>) {
+        let codec = match session {
+            Some(session) => session.datagram_codec(PacketCodec),
+            None => SecureCodec::plaintext(PacketCodec),
+        };
+        let token = Uuid::new_v4();
+        // The peer is only known once the client's first datagram arrives.
+        let link = Arc::new(DatagramLink::new(
+            self.socket.clone(),
+            DatagramCodec::new(token, codec),
+            None,
+            capture,
+        ));
+
+        let (audio, audio_rx) = mpsc::channel(32);
+        self.clients.lock().await.insert(
+            token,
+            UdpClient {
+                client_id,
+                link: link.clone(),
+                audio,
+            },
+        );
+        (link, audio_rx)
+    }
+
+    pub async fn close(&self, token: Uuid) {
+        self.clients.lock().await.remove(&token);
+    }
+
+    pub async fn client(&self, token: Uuid) -> Option<UdpClient> {
+        self.clients.lock().await.get(&token).cloned()
+    }
+}
@@ -0,0 +1,190 @@
+use bytes::Bytes;
+use common::{
+    packet::{HEADER_SIZE, MAX_PACKET_SIZE},
+    secure::RECORD_OVERHEAD,
+    transport::{Connection, Incoming, Listener},
+};
+use futures_util::{ready, Sink, Stream};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+};
+use tokio_tungstenite::{
+    tungstenite::{self, protocol::WebSocketConfig, Message},
+    WebSocketStream,
+};
+
+/// Largest message either way, one frame of the fragmenting codec once
+/// sealed. Nothing bigger is ever needed, as messages are read back to back.
+pub const MAX_MESSAGE_SIZE: usize = HEADER_SIZE + MAX_PACKET_SIZE + RECORD_OVERHEAD;
+
+/// A WebSocket read and written as the byte stream a TCP connection is, so
+/// clients that cannot open raw TCP, such as browsers, share its framing.
+///
+/// Every write goes out as one binary message of up to [`MAX_MESSAGE_SIZE`]
+/// bytes, so a small packet usually makes for a message of its own.
+/// Incoming binary messages are read back to back, however the client split
+/// them up.
+pub struct WebSocketIo<S> {
+    socket: WebSocketStream<S>,
+    /// Rest of the last message, beyond what the reader asked for.
+    pending: Bytes,
+}
+
+impl<S> WebSocketIo<S> {
+    pub fn new(socket: WebSocketStream<S>) -> Self {
+        Self {
+            socket,
+            pending: Bytes::new(),
+        }
+    }
+}
+
+fn io_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketIo<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.pending.is_empty() {
+            match ready!(Pin::new(&mut self.socket).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => self.pending = data,
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "packets only travel in binary messages",
+                    )))
+                }
+                // Reads as the end of the stream.
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                // Pings are answered by the socket itself.
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Poll::Ready(Err(io_error(e))),
+            }
+        }
+
+        let length = self.pending.len().min(buf.remaining());
+        buf.put_slice(&self.pending.split_to(length));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketIo<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(Pin::new(&mut self.socket).poll_ready(cx)).map_err(io_error)?;
+        let length = buf.len().min(MAX_MESSAGE_SIZE);
+        Pin::new(&mut self.socket)
+            .start_send(Message::binary(buf[..length].to_vec()))
+            .map_err(io_error)?;
+        Poll::Ready(Ok(length))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_flush(cx).map_err(io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_close(cx).map_err(io_error)
+    }
+}
+
+/// Accepts connections that upgrade to a WebSocket, see [`WebSocketIo`].
+pub struct WebSocketListener {
+    listener: TcpListener,
+}
+
+impl WebSocketListener {
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        Ok(Self::new(TcpListener::bind(addr).await?))
+    }
+
+    pub fn new(listener: TcpListener) -> Self {
+        Self { listener }
+    }
+}
+
+impl Listener for WebSocketListener {
+    async fn accept(&mut self) -> io::Result<Incoming> {
+        let (stream, peer) = self.listener.accept().await?;
+        Ok(Box::pin(async move {
+            stream.set_nodelay(true)?;
+            // Refuses a large message before buffering it, rather than once
+            // the packet codec gets to read it.
+            let config = WebSocketConfig::default()
+                .max_message_size(Some(MAX_MESSAGE_SIZE))
+                .max_frame_size(Some(MAX_MESSAGE_SIZE));
+            let socket = tokio_tungstenite::accept_async_with_config(stream, Some(config))
+                .await
+                .map_err(io_error)?;
+            Ok(Connection::new(WebSocketIo::new(socket), Some(peer)))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn connect() -> (
+        Connection,
+        WebSocketStream<impl AsyncRead + AsyncWrite + Unpin>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let mut listener = WebSocketListener::new(listener);
+
+        let client = tokio::spawn(async move {
+            let (socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            socket
+        });
+        let connection = listener.accept().await.unwrap().await.unwrap();
+        (connection, client.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_refuse_messages_larger_than_a_frame() {
+        let (mut connection, mut socket) = connect().await;
+
+        socket
+            .send(Message::binary(vec![0; MAX_MESSAGE_SIZE + 1]))
+            .await
+            .unwrap();
+        let mut buffer = [0; 16];
+        assert!(connection.stream.read(&mut buffer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_split_writes_into_messages_of_a_frame() {
+        let (mut connection, mut socket) = connect().await;
+
+        let data = vec![7; MAX_MESSAGE_SIZE * 2 + 1];
+        connection.stream.write_all(&data).await.unwrap();
+        connection.stream.flush().await.unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < data.len() {
+            let message = socket.next().await.unwrap().unwrap().into_data();
+            assert!(message.len() <= MAX_MESSAGE_SIZE);
+            received.extend_from_slice(&message);
+        }
+        assert_eq!(received, data);
+    }
+}